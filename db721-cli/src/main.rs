@@ -0,0 +1,725 @@
+use clap::{Args, Parser as CliParser, Subcommand, ValueEnum};
+use db721_csv::{parse_filter, ExportError, Exporter, Format};
+#[cfg(feature = "parquet")]
+use db721_parser::parquet::{ConvertError, ParquetSource};
+use db721_parser::staging;
+use db721_parser::{
+    BuildError, ColumnType, CompactError, Compaction, DeleteVector, Parser, ParserBuilder,
+};
+use std::io::{ErrorKind, Write};
+use std::process::ExitCode;
+
+mod table;
+
+use table::TablePrinter;
+
+/* Inspect and dump db721 files, instead of reading them with hexdump:
+
+    db721 info data-farms.db721
+    db721 stats data-farms.db721 --column farm_name
+    db721 head data-farms.db721 -n 3 --columns farm_name,min_age_weeks
+    db721 cat data-farms.db721
+    db721 export data-farms.db721 --format jsonl --filter "min_age_weeks >= 10"
+    db721 schema data-farms.db721 --sql
+    db721 validate data-farms.db721 data-chickens.db721
+    db721 compact part-1.db721 part-2.db721 -o data.db721
+    db721 recover /data/chickens
+
+With the `parquet` feature, blocks and row groups convert one to one:
+
+    db721 to-parquet data-chickens.db721 data-chickens.parquet
+    db721 from-parquet data-chickens.parquet data-chickens.db721 --table Chicken
+*/
+
+#[derive(CliParser)]
+#[command(name = "db721", version, about = "Inspect and dump db721 files")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the table, its columns, and the row and block counts
+    Info { file: String },
+    /// Print the statistics of every block
+    Stats {
+        file: String,
+        /// Only these columns
+        #[arg(short, long, value_delimiter = ',')]
+        column: Vec<String>,
+    },
+    /// Print the first rows
+    Head {
+        #[command(flatten)]
+        rows: Rows,
+        /// Number of rows
+        #[arg(short = 'n', long = "rows", default_value_t = 10)]
+        limit: usize,
+    },
+    /// Print all rows
+    Cat {
+        #[command(flatten)]
+        rows: Rows,
+    },
+    /// Write the rows as CSV or JSON Lines
+    Export {
+        #[command(flatten)]
+        rows: Rows,
+        /// Only the rows matching all comparisons, like
+        /// "min_age_weeks >= 10 AND farm_name IN ('Incubator', 'Eggscellent')"
+        #[arg(long)]
+        filter: Option<String>,
+        #[arg(long, value_enum, default_value_t = ExportFormat::Csv)]
+        format: ExportFormat,
+        /// Write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<String>,
+    },
+    /// Print the columns and their types
+    Schema {
+        file: String,
+        /// Print a CREATE FOREIGN TABLE statement instead
+        #[arg(long)]
+        sql: bool,
+        /// Foreign server of the table
+        #[arg(long, default_value = "polo_server")]
+        server: String,
+        /// Name of the foreign table, db721_<table> by default
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Check the files and print every violation, fails if any is found
+    Validate {
+        #[arg(required = true)]
+        files: Vec<String>,
+    },
+    /// Rewrite the files into one with full blocks and without deleted rows, and remove
+    /// the other files
+    Compact {
+        #[arg(required = true)]
+        files: Vec<String>,
+        /// The first file by default
+        #[arg(short, long)]
+        output: Option<String>,
+        /// That of the first file by default
+        #[arg(long)]
+        max_values_per_block: Option<u32>,
+        /// Leave the files other than the output in place
+        #[arg(long)]
+        keep: bool,
+    },
+    /// Undo or finish the changes to the files, or to the files below the directories,
+    /// that a crashed writer left behind
+    Recover {
+        #[arg(required = true)]
+        paths: Vec<String>,
+    },
+    /// Convert to Parquet, one row group per block
+    #[cfg(feature = "parquet")]
+    ToParquet { file: String, output: String },
+    /// Convert from Parquet, one block per row group
+    #[cfg(feature = "parquet")]
+    FromParquet {
+        file: String,
+        output: String,
+        /// Name of the table, the one stored by to-parquet or the name of the output
+        /// file by default
+        #[arg(long)]
+        table: Option<String>,
+        /// The largest row group by default, so that no row group is split
+        #[arg(long)]
+        max_values_per_block: Option<u32>,
+    },
+}
+
+#[derive(Args)]
+struct Rows {
+    file: String,
+    /// Only these columns, in this order
+    #[arg(short, long, value_delimiter = ',')]
+    columns: Vec<String>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ExportFormat {
+    Csv,
+    Jsonl,
+}
+
+fn open(file: &str) -> Result<Parser, BuildError> {
+    ParserBuilder::new(file.to_string(), String::new()).build()
+}
+
+fn headers(names: &[&str]) -> Vec<String> {
+    names.iter().map(|n| n.to_string()).collect()
+}
+
+fn info(out: &mut dyn Write, file: &str) -> Result<(), BuildError> {
+    let parser = open(file)?;
+    let metadata = &parser.metadata;
+    writeln!(out, "Table:                {}", metadata.table)?;
+    writeln!(out, "Format Version:       {}", parser.format_version())?;
+    writeln!(out, "Rows:                 {}", parser.num_rows())?;
+    if !parser.deletes.is_empty() {
+        writeln!(
+            out,
+            "Deleted Rows:         {}",
+            parser.deletes.num_deleted()
+        )?;
+    }
+    writeln!(out, "Blocks:               {}", parser.num_blocks())?;
+    writeln!(
+        out,
+        "Max Values Per Block: {}",
+        metadata.max_values_per_block
+    )?;
+    writeln!(
+        out,
+        "Checksum:             {}",
+        metadata.checksum.map_or("none", |c| c.name())
+    )?;
+    writeln!(out)?;
+    let rows: Vec<Vec<String>> = parser
+        .columns
+        .iter()
+        .map(|meta| {
+            let column = &metadata.columns[&meta.column_name];
+            let mut encodings: Vec<&str> = meta.block_encodings.iter().map(|e| e.name()).collect();
+            encodings.dedup();
+            vec![
+                meta.column_name.clone(),
+                meta.column_type.name().to_string(),
+                meta.start_offset.to_string(),
+                meta.len().to_string(),
+                column.compression.unwrap_or_default().name().to_string(),
+                encodings.join(","),
+                column.bloom_filter.is_some().to_string(),
+            ]
+        })
+        .collect();
+    TablePrinter::print(
+        out,
+        &headers(&[
+            "column",
+            "type",
+            "offset",
+            "bytes",
+            "compression",
+            "encodings",
+            "bloom filter",
+        ]),
+        &rows,
+    )?;
+    Ok(())
+}
+
+fn stats(out: &mut dyn Write, file: &str, columns: &[String]) -> Result<(), BuildError> {
+    let parser = open(file)?;
+    for name in columns {
+        parser
+            .column_meta(name)
+            .ok_or_else(|| BuildError::ColumnNotFound(name.clone()))?;
+    }
+    let optional = |v: Option<String>| v.unwrap_or_default();
+    let mut rows = Vec::new();
+    for meta in &parser.columns {
+        if !columns.is_empty() && !columns.contains(&meta.column_name) {
+            continue;
+        }
+        for (block, stats) in parser.metadata.columns[&meta.column_name]
+            .block_stats
+            .iter()
+            .enumerate()
+        {
+            rows.push(vec![
+                meta.column_name.clone(),
+                block.to_string(),
+                stats.num.to_string(),
+                optional(stats.min.as_ref().map(|v| v.to_string())),
+                optional(stats.max.as_ref().map(|v| v.to_string())),
+                optional(stats.min_len.map(|v| v.to_string())),
+                optional(stats.max_len.map(|v| v.to_string())),
+                (meta.block_offsets[block + 1] - meta.block_offsets[block]).to_string(),
+                meta.block_encodings[block].name().to_string(),
+                meta.block_codecs[block].name().to_string(),
+            ]);
+        }
+    }
+    TablePrinter::print(
+        out,
+        &headers(&[
+            "column",
+            "block",
+            "num",
+            "min",
+            "max",
+            "min_len",
+            "max_len",
+            "bytes",
+            "encoding",
+            "compression",
+        ]),
+        &rows,
+    )?;
+    Ok(())
+}
+
+// print up to `limit` rows of the projected columns, block by block
+fn dump(out: &mut dyn Write, rows: &Rows, limit: Option<usize>) -> Result<(), BuildError> {
+    let parser = open(&rows.file)?;
+    let columns: Vec<String> = match rows.columns.is_empty() {
+        true => parser
+            .columns
+            .iter()
+            .map(|c| c.column_name.clone())
+            .collect(),
+        false => rows.columns.clone(),
+    };
+    for name in &columns {
+        parser
+            .column_meta(name)
+            .ok_or_else(|| BuildError::ColumnNotFound(name.clone()))?;
+    }
+    let mut left = limit.unwrap_or(usize::MAX);
+    let mut printer = None;
+    for block in 0..parser.num_blocks() {
+        if left == 0 {
+            break;
+        }
+        let blocks = columns
+            .iter()
+            .map(|name| parser.read_block(name, block))
+            .collect::<Result<Vec<_>, _>>()?;
+        // without the deleted rows
+        let selection = parser.filter_block(block, &[])?;
+        let rows: Vec<usize> = (0..selection.len())
+            .filter(|row| selection[*row])
+            .take(left)
+            .collect();
+        left -= rows.len();
+        let values: Vec<Vec<String>> = rows
+            .iter()
+            .map(|row| {
+                blocks
+                    .iter()
+                    .map(|b| b.get(*row).map_or(String::new(), |v| v.to_string()))
+                    .collect()
+            })
+            .collect();
+        if printer.is_none() {
+            printer = Some(TablePrinter::new(out, &columns, &values)?);
+        }
+        let printer = printer.as_ref().unwrap();
+        values
+            .iter()
+            .try_for_each(|row| printer.print_row(out, row))?;
+    }
+    if printer.is_none() {
+        TablePrinter::new(out, &columns, &[])?;
+    }
+    Ok(())
+}
+
+fn export(
+    out: &mut dyn Write,
+    rows: &Rows,
+    filter: Option<&str>,
+    format: ExportFormat,
+    output: Option<&str>,
+) -> Result<(), ExportError> {
+    let parser = open(&rows.file)?;
+    let mut exporter = Exporter::new(&parser);
+    if !rows.columns.is_empty() {
+        exporter.columns(rows.columns.clone());
+    }
+    if let Some(filter) = filter {
+        exporter.filter(parse_filter(&parser, filter)?);
+    }
+    exporter.format(match format {
+        ExportFormat::Csv => Format::Csv,
+        ExportFormat::Jsonl => Format::JsonLines,
+    });
+    match output {
+        Some(output) => exporter.export(std::fs::File::create(output)?)?,
+        None => exporter.export(out)?,
+    };
+    Ok(())
+}
+
+// the postgres type the FDW maps a column type to
+fn pg_type(ty: ColumnType) -> &'static str {
+    match ty {
+        ColumnType::Int => "integer",
+        ColumnType::Float => "real",
+        ColumnType::Str => "varchar",
+        ColumnType::BigInt => "bigint",
+        ColumnType::Double => "double precision",
+        ColumnType::Bool => "boolean",
+        ColumnType::Date => "date",
+        ColumnType::Timestamp => "timestamptz",
+        ColumnType::StrList => "varchar[]",
+    }
+}
+
+fn quote_ident(name: &str) -> String {
+    let plain = name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    match plain {
+        true => name.to_string(),
+        false => format!("\"{}\"", name.replace('"', "\"\"")),
+    }
+}
+
+fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+fn schema(
+    out: &mut dyn Write,
+    file: &str,
+    sql: bool,
+    server: &str,
+    name: Option<&str>,
+) -> Result<(), BuildError> {
+    let parser = open(file)?;
+    if !sql {
+        let rows: Vec<Vec<String>> = parser
+            .columns
+            .iter()
+            .map(|c| vec![c.column_name.clone(), c.column_type.name().to_string()])
+            .collect();
+        TablePrinter::print(out, &headers(&["column", "type"]), &rows)?;
+        return Ok(());
+    }
+    // same layout as db721-gen/operation.sql
+    let table = match name {
+        Some(name) => name.to_string(),
+        None => format!("db721_{}", parser.metadata.table.to_lowercase()),
+    };
+    let filename = std::fs::canonicalize(file)?;
+    let columns: Vec<String> = parser
+        .columns
+        .iter()
+        .map(|c| {
+            format!(
+                "    {:<15} {}",
+                quote_ident(&c.column_name),
+                pg_type(c.column_type)
+            )
+        })
+        .collect();
+    writeln!(
+        out,
+        "CREATE FOREIGN TABLE IF NOT EXISTS {}",
+        quote_ident(&table)
+    )?;
+    writeln!(out, "(")?;
+    writeln!(out, "{}", columns.join(",\n"))?;
+    writeln!(out, ") SERVER {} OPTIONS", quote_ident(server))?;
+    writeln!(out, "(")?;
+    writeln!(
+        out,
+        "    filename {},",
+        quote_literal(&filename.to_string_lossy())
+    )?;
+    writeln!(
+        out,
+        "    tablename {}",
+        quote_literal(&parser.metadata.table)
+    )?;
+    writeln!(out, ");")?;
+    Ok(())
+}
+
+// prints the violations of every file, Ok(false) if there were any
+fn validate(out: &mut dyn Write, files: &[String]) -> Result<bool, BuildError> {
+    let mut valid = true;
+    for file in files {
+        let violations = db721_parser::validate(file)?;
+        if violations.is_empty() {
+            writeln!(out, "{}: ok", file)?;
+        }
+        for violation in &violations {
+            writeln!(out, "{}: {}", file, violation)?;
+        }
+        valid &= violations.is_empty();
+    }
+    Ok(valid)
+}
+
+fn compact(
+    files: &[String],
+    output: Option<&str>,
+    max_values_per_block: Option<u32>,
+    keep: bool,
+) -> Result<(), CompactError> {
+    let output = output.unwrap_or(&files[0]);
+    let mut compaction = Compaction::new(files, output);
+    if let Some(max_values_per_block) = max_values_per_block {
+        compaction.max_values_per_block(max_values_per_block);
+    }
+    let compacted = compaction.keep_inputs(keep).run()?;
+    eprintln!(
+        "Wrote {} rows in {} blocks to '{}', dropped {} deleted rows.",
+        compacted.rows, compacted.blocks, output, compacted.deleted
+    );
+    Ok(())
+}
+
+fn recover(paths: &[String]) -> Result<(), BuildError> {
+    for path in paths {
+        let recovered = match std::path::Path::new(path).is_dir() {
+            true => staging::recover_dir(path.as_ref())?,
+            false => {
+                let file = staging::recover(path)?;
+                staging::recover(&DeleteVector::path(path))? || file
+            }
+        };
+        match recovered {
+            true => eprintln!("Recovered '{}'.", path),
+            false => eprintln!("Nothing to recover in '{}'.", path),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+fn to_parquet(file: &str, output: &str) -> Result<(), ConvertError> {
+    let parser = open(file)?;
+    let out = std::io::BufWriter::new(std::fs::File::create(output)?);
+    let row_groups = db721_parser::parquet::write_parquet(&parser, out)?;
+    eprintln!("Wrote {} row groups to '{}'.", row_groups, output);
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+fn from_parquet(
+    file: &str,
+    output: &str,
+    table: Option<&str>,
+    max_values_per_block: Option<u32>,
+) -> Result<(), ConvertError> {
+    let source = ParquetSource::open(file)?;
+    let table = match table {
+        Some(table) => table.to_string(),
+        None => source.table().unwrap_or_else(|| {
+            std::path::Path::new(output)
+                .file_stem()
+                .map_or(String::new(), |s| s.to_string_lossy().into_owned())
+        }),
+    };
+    let max_values_per_block = max_values_per_block
+        .unwrap_or(source.max_row_group_len().clamp(1, u32::MAX as usize) as u32);
+    // written next to the output and renamed over it once complete, an existing file
+    // is left as it is after an error
+    let tmp = db721_parser::staging::tmp_path(output);
+    let mut staged = db721_parser::Staged::default();
+    staged.push(tmp.clone(), output.to_string());
+    let out = std::io::BufWriter::new(std::fs::File::create(&tmp)?);
+    let mut writer = db721_parser::Writer::new(table, out, max_values_per_block);
+    let rows = source.write_to(&mut writer)?;
+    let mut out = writer.finalize()?;
+    out.flush()?;
+    out.get_ref().sync_all()?;
+    // the rows are new, deletes of those of an existing file do not apply
+    staged.remove(db721_parser::DeleteVector::path(output));
+    let _lock = db721_parser::FileLock::acquire(output)?;
+    staged.publish()?;
+    eprintln!("Wrote {} rows to '{}'.", rows, output);
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let mut out = std::io::stdout().lock();
+    let out = &mut out;
+    let result = match &cli.command {
+        Command::Info { file } => info(out, file),
+        Command::Stats { file, column } => stats(out, file, column),
+        Command::Head { rows, limit } => dump(out, rows, Some(*limit)),
+        Command::Cat { rows } => dump(out, rows, None),
+        Command::Export {
+            rows,
+            filter,
+            format,
+            output,
+        } => match export(out, rows, filter.as_deref(), *format, output.as_deref()) {
+            Ok(()) => Ok(()),
+            Err(ExportError::IoError(e)) => Err(BuildError::IoError(e)),
+            Err(ExportError::BuildError(e)) => Err(e),
+            Err(e) => {
+                eprintln!("db721: {}", e);
+                return ExitCode::FAILURE;
+            }
+        },
+        Command::Schema {
+            file,
+            sql,
+            server,
+            name,
+        } => schema(out, file, *sql, server, name.as_deref()),
+        Command::Validate { files } => match validate(out, files) {
+            Ok(false) => return ExitCode::FAILURE,
+            result => result.map(|_| ()),
+        },
+        Command::Compact {
+            files,
+            output,
+            max_values_per_block,
+            keep,
+        } => match compact(files, output.as_deref(), *max_values_per_block, *keep) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                eprintln!("db721: {}", e);
+                return ExitCode::FAILURE;
+            }
+        },
+        Command::Recover { paths } => recover(paths),
+        #[cfg(feature = "parquet")]
+        Command::ToParquet { file, output } => match to_parquet(file, output) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                eprintln!("db721: {}", e);
+                let _ = std::fs::remove_file(output);
+                return ExitCode::FAILURE;
+            }
+        },
+        #[cfg(feature = "parquet")]
+        Command::FromParquet {
+            file,
+            output,
+            table,
+            max_values_per_block,
+        } => match from_parquet(file, output, table.as_deref(), *max_values_per_block) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                eprintln!("db721: {}", e);
+                return ExitCode::FAILURE;
+            }
+        },
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        // e.g. `db721 cat ... | head`
+        Err(BuildError::IoError(e)) if e.kind() == ErrorKind::BrokenPipe => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("db721: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn farms_path() -> String {
+        format!(
+            "{}/../db721-gen/data-farms.db721",
+            env!("CARGO_MANIFEST_DIR")
+        )
+    }
+
+    fn rows(columns: &[&str]) -> Rows {
+        Rows {
+            file: farms_path(),
+            columns: headers(columns),
+        }
+    }
+
+    #[test]
+    fn it_quotes_identifiers() {
+        assert_eq!(quote_ident("farm_name"), "farm_name");
+        assert_eq!(quote_ident("_col2"), "_col2");
+        assert_eq!(quote_ident("Farm"), "\"Farm\"");
+        assert_eq!(quote_ident("farm name"), "\"farm name\"");
+        assert_eq!(quote_ident("2nd"), "\"2nd\"");
+        assert_eq!(quote_ident("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(quote_literal("O'Brien"), "'O''Brien'");
+    }
+
+    #[test]
+    fn it_maps_column_types() {
+        let types = [
+            (ColumnType::Int, "integer"),
+            (ColumnType::Float, "real"),
+            (ColumnType::Str, "varchar"),
+            (ColumnType::BigInt, "bigint"),
+            (ColumnType::Double, "double precision"),
+            (ColumnType::Bool, "boolean"),
+            (ColumnType::Date, "date"),
+            (ColumnType::Timestamp, "timestamptz"),
+            (ColumnType::StrList, "varchar[]"),
+        ];
+        for (ty, pg) in types {
+            assert_eq!(pg_type(ty), pg);
+        }
+    }
+
+    #[test]
+    fn it_prints_the_ddl_of_operation_sql() {
+        let mut out = Vec::new();
+        schema(&mut out, &farms_path(), true, "polo_server", None).unwrap();
+        let operation = std::fs::read_to_string(format!(
+            "{}/../db721-gen/operation.sql",
+            env!("CARGO_MANIFEST_DIR")
+        ))
+        .unwrap();
+        let start = operation.find("CREATE FOREIGN TABLE").unwrap();
+        let filename = std::fs::canonicalize(farms_path()).unwrap();
+        let expected: Vec<String> = operation[start..]
+            .lines()
+            .map(|line| match line.trim_start().starts_with("filename ") {
+                true => format!("    filename '{}',", filename.to_string_lossy()),
+                false => line.trim_end().to_string(),
+            })
+            .collect();
+        let out = String::from_utf8(out).unwrap();
+        assert_eq!(out.lines().collect::<Vec<_>>(), expected);
+
+        let mut out = Vec::new();
+        schema(&mut out, &farms_path(), true, "my server", Some("Farms")).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("CREATE FOREIGN TABLE IF NOT EXISTS \"Farms\"\n"));
+        assert!(out.contains(") SERVER \"my server\" OPTIONS\n"));
+    }
+
+    #[test]
+    fn it_projects_rows() {
+        let mut out = Vec::new();
+        dump(&mut out, &rows(&["min_age_weeks", "farm_name"]), Some(3)).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            " min_age_weeks | farm_name\n\
+             ---------------+------------------\n \
+             0             | Incubator\n \
+             24            | Eggscellent\n \
+             52            | Eggstraordinaire\n"
+        );
+
+        let mut out = Vec::new();
+        dump(&mut out, &rows(&["farm_name"]), None).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let names: Vec<_> = out.lines().skip(2).map(str::trim).collect();
+        assert_eq!(
+            names,
+            [
+                "Incubator",
+                "Eggscellent",
+                "Eggstraordinaire",
+                "Breakfast Lunch Dinner",
+                "Dish of the Day",
+                "Cheep Birds"
+            ]
+        );
+
+        let mut out = Vec::new();
+        assert!(matches!(
+            dump(&mut out, &rows(&["farm_name", "weight"]), None),
+            Err(BuildError::ColumnNotFound(c)) if c == "weight"
+        ));
+    }
+}
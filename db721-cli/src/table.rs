@@ -0,0 +1,48 @@
+// Prints rows as an aligned table, like psql does:
+//
+//  farm_name | min_age_weeks
+// -----------+---------------
+//  Incubator | 3
+//
+// Rows are printed as they come, so the widths are taken from the header and the
+// rows given to `new`, a longer value later on only widens its own row.
+use std::io::{self, Write};
+
+pub struct TablePrinter {
+    widths: Vec<usize>,
+}
+
+impl TablePrinter {
+    pub fn new(
+        out: &mut dyn Write,
+        headers: &[String],
+        sample: &[Vec<String>],
+    ) -> io::Result<TablePrinter> {
+        let mut widths: Vec<usize> = headers.iter().map(|h| h.chars().count()).collect();
+        for row in sample {
+            for (width, value) in widths.iter_mut().zip(row) {
+                *width = (*width).max(value.chars().count());
+            }
+        }
+        let printer = TablePrinter { widths };
+        printer.print_row(out, headers)?;
+        let separator: Vec<String> = printer.widths.iter().map(|w| "-".repeat(w + 2)).collect();
+        writeln!(out, "{}", separator.join("+"))?;
+        Ok(printer)
+    }
+
+    pub fn print_row(&self, out: &mut dyn Write, row: &[String]) -> io::Result<()> {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(&self.widths)
+            .map(|(value, width)| format!(" {:<width$} ", value, width = width))
+            .collect();
+        writeln!(out, "{}", cells.join("|").trim_end())
+    }
+
+    // print a whole table at once
+    pub fn print(out: &mut dyn Write, headers: &[String], rows: &[Vec<String>]) -> io::Result<()> {
+        let printer = TablePrinter::new(out, headers, rows)?;
+        rows.iter().try_for_each(|row| printer.print_row(out, row))
+    }
+}
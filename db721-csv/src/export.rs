@@ -0,0 +1,309 @@
+use std::io::Write;
+
+use db721_parser::{BuildError, Operator, Parser, Predicate, ScalarValue};
+use serde_json::Value;
+
+/*
+Writes the rows of a db721 file as text, block by block, so that only the projected
+columns of one block are in memory:
+
+    csv     a header in the style of db721-gen/data-farms.csv ("Farm Name" for
+            farm_name), then the values as postgres prints them
+    jsonl   one JSON object per row, keyed by column name. Dates and timestamps are
+            strings as postgres prints them, lists are arrays
+
+The rows can be filtered by a conjunction of comparisons, whose values are parsed as
+the type of their column:
+
+    min_age_weeks >= 10 AND farm_name IN ('Incubator', 'Eggscellent')
+    sexes CONTAINS MALE
+
+The filter prunes blocks by their stats and bloom filters before any is read.
+*/
+
+#[derive(Debug)]
+pub enum ExportError {
+    IoError(std::io::Error),
+    BuildError(BuildError),
+    FilterError(String),
+}
+
+impl From<std::io::Error> for ExportError {
+    fn from(error: std::io::Error) -> Self {
+        ExportError::IoError(error)
+    }
+}
+
+impl From<BuildError> for ExportError {
+    fn from(error: BuildError) -> Self {
+        match error {
+            BuildError::IoError(e) => ExportError::IoError(e),
+            e => ExportError::BuildError(e),
+        }
+    }
+}
+
+impl From<csv::Error> for ExportError {
+    fn from(error: csv::Error) -> Self {
+        match error.into_kind() {
+            csv::ErrorKind::Io(e) => ExportError::IoError(e),
+            kind => ExportError::IoError(std::io::Error::other(format!("{:?}", kind))),
+        }
+    }
+}
+
+impl std::fmt::Display for ExportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExportError::IoError(e) => write!(f, "DB721|IO: {}", e),
+            ExportError::BuildError(e) => write!(f, "{}", e),
+            ExportError::FilterError(e) => write!(f, "DB721|Filter: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ExportError {}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    #[default]
+    Csv,
+    JsonLines,
+}
+
+// the CSV header of a column in the generator's style, farm_name is "Farm Name"
+pub fn header_name(column: &str) -> String {
+    let words: Vec<String> = column
+        .split('_')
+        .filter(|w| !w.is_empty())
+        .map(|w| {
+            let mut chars = w.chars();
+            chars.next().map_or(String::new(), |c| {
+                c.to_uppercase().chain(chars).collect::<String>()
+            })
+        })
+        .collect();
+    words.join(" ")
+}
+
+fn json_value(value: &ScalarValue) -> Value {
+    match value {
+        ScalarValue::Date(_) | ScalarValue::Timestamp(_) => Value::from(value.to_string()),
+        // as short as it prints, 5.65 instead of the f64 closest to the f32
+        ScalarValue::Float(v) => v
+            .to_string()
+            .parse::<f64>()
+            .map_or(Value::Null, Value::from),
+        v => v.to_json(),
+    }
+}
+
+fn tokenize(filter: &str) -> Result<Vec<String>, ExportError> {
+    let mut tokens = Vec::new();
+    let mut chars = filter.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' | ')' | ',' | '=' => tokens.push(c.to_string()),
+            '<' | '>' => match chars.next_if_eq(&'=') {
+                Some(_) => tokens.push(format!("{}=", c)),
+                None => tokens.push(c.to_string()),
+            },
+            // 'it''s' is it's, the quotes are kept to tell literals from keywords
+            '\'' => {
+                let mut literal = String::from("'");
+                loop {
+                    match chars.next() {
+                        Some('\'') if chars.next_if_eq(&'\'').is_none() => break,
+                        Some(c) => literal.push(c),
+                        None => {
+                            return Err(ExportError::FilterError("unterminated string".to_string()))
+                        }
+                    }
+                }
+                tokens.push(literal);
+            }
+            c => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace() && !"()=<>,'".contains(*c))
+                {
+                    word.push(c);
+                }
+                tokens.push(word);
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+// parse a filter into predicates on the columns of `parser`
+pub fn parse_filter(parser: &Parser, filter: &str) -> Result<Vec<Predicate>, ExportError> {
+    let tokens = tokenize(filter)?;
+    let mut tokens = tokens.iter().map(|t| t.as_str());
+    let mut predicates = Vec::new();
+    let unexpected = |token: Option<&str>| {
+        ExportError::FilterError(match token {
+            Some(token) => format!("unexpected {:?}", token),
+            None => "unexpected end of the filter".to_string(),
+        })
+    };
+    loop {
+        let column = tokens.next().ok_or_else(|| unexpected(None))?;
+        let meta = parser
+            .column_meta(column)
+            .ok_or_else(|| ExportError::BuildError(BuildError::ColumnNotFound(column.into())))?;
+        let operator = tokens.next().ok_or_else(|| unexpected(None))?;
+        let operator = match operator.to_ascii_uppercase().as_str() {
+            "=" => Operator::Eq,
+            "<" => Operator::Lt,
+            "<=" => Operator::Le,
+            ">" => Operator::Gt,
+            ">=" => Operator::Ge,
+            "IN" => Operator::In,
+            "CONTAINS" => Operator::Contains,
+            _ => return Err(unexpected(Some(operator))),
+        };
+        // the elements of a list column are compared as str
+        let value_type = match operator {
+            Operator::Contains => db721_parser::ColumnType::Str,
+            _ => meta.column_type,
+        };
+        let value = |token: Option<&str>| {
+            let token = token.ok_or_else(|| unexpected(None))?;
+            let text = token.strip_prefix('\'').unwrap_or(token);
+            ScalarValue::parse(value_type, text).ok_or_else(|| {
+                ExportError::FilterError(format!("{:?} is not a valid {}", text, value_type.name()))
+            })
+        };
+        let values = match operator {
+            Operator::In => {
+                if tokens.next() != Some("(") {
+                    return Err(ExportError::FilterError("expected ( after IN".to_string()));
+                }
+                let mut values = vec![value(tokens.next())?];
+                loop {
+                    match tokens.next() {
+                        Some(",") => values.push(value(tokens.next())?),
+                        Some(")") => break,
+                        token => return Err(unexpected(token)),
+                    }
+                }
+                values
+            }
+            _ => vec![value(tokens.next())?],
+        };
+        predicates.push(Predicate {
+            column: column.to_string(),
+            operator,
+            values,
+        });
+        match tokens.next() {
+            None => return Ok(predicates),
+            Some(token) if token.eq_ignore_ascii_case("AND") => {}
+            token => return Err(unexpected(token)),
+        }
+    }
+}
+
+pub struct Exporter<'a> {
+    parser: &'a Parser,
+    columns: Vec<String>,
+    predicates: Vec<Predicate>,
+    format: Format,
+}
+
+impl<'a> Exporter<'a> {
+    pub fn new(parser: &'a Parser) -> Exporter<'a> {
+        Exporter {
+            parser,
+            columns: parser
+                .columns
+                .iter()
+                .map(|c| c.column_name.clone())
+                .collect(),
+            predicates: Vec::new(),
+            format: Format::Csv,
+        }
+    }
+
+    // only these columns, in this order
+    pub fn columns(&mut self, columns: Vec<String>) -> &mut Self {
+        self.columns = columns;
+        self
+    }
+
+    // only the rows matching all predicates
+    pub fn filter(&mut self, predicates: Vec<Predicate>) -> &mut Self {
+        self.predicates = predicates;
+        self
+    }
+
+    pub fn format(&mut self, format: Format) -> &mut Self {
+        self.format = format;
+        self
+    }
+
+    // write the rows to `out`, returns the number of rows written
+    pub fn export<W: Write>(&self, out: W) -> Result<u64, ExportError> {
+        for name in self
+            .columns
+            .iter()
+            .chain(self.predicates.iter().map(|p| &p.column))
+        {
+            self.parser
+                .column_meta(name)
+                .ok_or_else(|| BuildError::ColumnNotFound(name.clone()))?;
+        }
+        let mut csv = None;
+        let mut json = None;
+        match self.format {
+            Format::Csv => {
+                let mut writer = csv::Writer::from_writer(out);
+                writer.write_record(self.columns.iter().map(|c| header_name(c)))?;
+                csv = Some(writer);
+            }
+            Format::JsonLines => json = Some(std::io::BufWriter::new(out)),
+        }
+        let mut rows = 0;
+        for block in self.parser.prune(&self.predicates) {
+            let selection = self.parser.filter_block(block, &self.predicates)?;
+            if !selection.iter().any(|s| *s) {
+                continue;
+            }
+            let blocks = self
+                .columns
+                .iter()
+                .map(|name| self.parser.read_block(name, block))
+                .collect::<Result<Vec<_>, _>>()?;
+            for (row, _) in selection.iter().enumerate().filter(|(_, s)| **s) {
+                let values: Vec<ScalarValue> = blocks.iter().map(|b| b.get(row).unwrap()).collect();
+                if let Some(csv) = &mut csv {
+                    csv.write_record(values.iter().map(|v| v.to_string()))?;
+                }
+                if let Some(json) = &mut json {
+                    // in the order of the columns, which a serde_json::Map does not keep
+                    for (i, (name, value)) in self.columns.iter().zip(&values).enumerate() {
+                        json.write_all(if i == 0 { b"{" } else { b"," })?;
+                        serde_json::to_writer(&mut *json, name).map_err(std::io::Error::from)?;
+                        json.write_all(b":")?;
+                        serde_json::to_writer(&mut *json, &json_value(value))
+                            .map_err(std::io::Error::from)?;
+                    }
+                    json.write_all(if self.columns.is_empty() {
+                        b"{}\n"
+                    } else {
+                        b"}\n"
+                    })?;
+                }
+                rows += 1;
+            }
+        }
+        if let Some(mut csv) = csv {
+            csv.flush()?;
+        }
+        if let Some(mut json) = json {
+            json.flush()?;
+        }
+        Ok(rows)
+    }
+}
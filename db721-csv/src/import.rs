@@ -0,0 +1,306 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+
+use db721_parser::types::STR_SIZE;
+use db721_parser::{ColumnBlock, ColumnType, ScalarValue, WriteError, Writer};
+
+/*
+Converts CSV, e.g. db721-gen/data-farms.csv, into a db721 file:
+
+    Farm Name,Min Age Weeks,Max Age Weeks
+    Incubator,0,2
+
+The header names the columns, as their lowercased words joined by '_' ("Farm Name"
+is farm_name). Without a header the columns are column_1, column_2, ...
+
+The types of the columns are given as a schema or inferred from the first
+`infer_rows` records, as the first of bool, int, bigint, double, date and timestamp
+that every sampled value parses as, or str. Values are parsed like
+`ScalarValue::parse`, a list[str] as a postgres array literal, {a,"b,c"}.
+
+The input is streamed: the values of each column are gathered until they fill a
+block of Max Values Per Block values, which is then spilled to a temporary file of
+that column in the plain encoding. Only when the input is exhausted are the spilled
+columns written one after another, so at most one block per column is in memory.
+*/
+
+#[derive(Debug)]
+pub enum ImportError {
+    IoError(std::io::Error),
+    CsvError(csv::Error),
+    WriteError(WriteError),
+    SchemaError(String),
+    // line, column name, what is wrong with the value
+    ValueError(u64, String, String),
+}
+
+impl From<std::io::Error> for ImportError {
+    fn from(error: std::io::Error) -> Self {
+        ImportError::IoError(error)
+    }
+}
+
+impl From<csv::Error> for ImportError {
+    fn from(error: csv::Error) -> Self {
+        ImportError::CsvError(error)
+    }
+}
+
+impl From<WriteError> for ImportError {
+    fn from(error: WriteError) -> Self {
+        ImportError::WriteError(error)
+    }
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::IoError(e) => write!(f, "DB721|IO: {}", e),
+            ImportError::CsvError(e) => write!(f, "DB721|CSV: {}", e),
+            ImportError::WriteError(e) => write!(f, "{}", e),
+            ImportError::SchemaError(e) => write!(f, "DB721|Schema: {}", e),
+            ImportError::ValueError(line, c, e) => {
+                write!(f, "DB721|Column: line {}, column {}: {}", line, c, e)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+// the candidates of the inference, in order of preference
+const INFERRED_TYPES: [ColumnType; 6] = [
+    ColumnType::Bool,
+    ColumnType::Int,
+    ColumnType::BigInt,
+    ColumnType::Double,
+    ColumnType::Date,
+    ColumnType::Timestamp,
+];
+
+// the narrowest type that holds all the values
+pub fn infer_type(values: &[&str]) -> ColumnType {
+    INFERRED_TYPES
+        .into_iter()
+        .find(|ty| {
+            !values.is_empty() && values.iter().all(|v| ScalarValue::parse(*ty, v).is_some())
+        })
+        .unwrap_or(ColumnType::Str)
+}
+
+// the db721 name of a CSV header, "Farm Name" is farm_name and "Age (weeks)" age_weeks
+pub fn column_name(header: &str) -> String {
+    let words: Vec<String> = header
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect();
+    words.join("_")
+}
+
+// parse a "name:type,name:type" schema, e.g. farm_name:str,min_age_weeks:float
+pub fn parse_schema(schema: &str) -> Result<Vec<(String, ColumnType)>, ImportError> {
+    schema
+        .split(',')
+        .map(|column| {
+            let (name, ty) = column.split_once(':').ok_or_else(|| {
+                ImportError::SchemaError(format!("expected name:type, found {:?}", column))
+            })?;
+            let ty = ColumnType::from_name(ty.trim())
+                .ok_or_else(|| ImportError::SchemaError(format!("unknown type {:?}", ty)))?;
+            Ok((name.trim().to_string(), ty))
+        })
+        .collect()
+}
+
+// The blocks of one column, spilled to a temporary file
+struct Spool {
+    column_type: ColumnType,
+    file: BufWriter<File>,
+    // the values of the block being gathered
+    values: Vec<ScalarValue>,
+    // number of values and byte length of every spilled block
+    blocks: Vec<(usize, usize)>,
+}
+
+impl Spool {
+    fn new(column_type: ColumnType) -> std::io::Result<Spool> {
+        Ok(Spool {
+            column_type,
+            file: BufWriter::new(tempfile::tempfile()?),
+            values: Vec::new(),
+            blocks: Vec::new(),
+        })
+    }
+
+    fn push(&mut self, value: ScalarValue, block_size: usize) -> std::io::Result<()> {
+        self.values.push(value);
+        if self.values.len() >= block_size {
+            self.spill()?;
+        }
+        Ok(())
+    }
+
+    fn spill(&mut self) -> std::io::Result<()> {
+        if self.values.is_empty() {
+            return Ok(());
+        }
+        let mut bytes = Vec::new();
+        self.values.iter().for_each(|v| v.encode(&mut bytes));
+        self.file.write_all(&bytes)?;
+        self.blocks.push((self.values.len(), bytes.len()));
+        self.values.clear();
+        Ok(())
+    }
+
+    // read the spilled blocks back, one at a time
+    fn into_blocks(
+        mut self,
+    ) -> std::io::Result<impl Iterator<Item = std::io::Result<Vec<ScalarValue>>>> {
+        self.spill()?;
+        let mut file = self.file.into_inner().map_err(|e| e.into_error())?;
+        file.seek(SeekFrom::Start(0))?;
+        let mut file = BufReader::new(file);
+        let column_type = self.column_type;
+        Ok(self.blocks.into_iter().map(move |(num, len)| {
+            let mut bytes = vec![0; len];
+            file.read_exact(&mut bytes)?;
+            let block = ColumnBlock::decode(column_type, &bytes, num)
+                .map_err(|e| std::io::Error::other(e.to_string()))?;
+            Ok((0..num).map(|i| block.get(i).unwrap()).collect())
+        }))
+    }
+}
+
+pub struct CsvImporter {
+    schema: Option<Vec<(String, ColumnType)>>,
+    infer_rows: usize,
+    delimiter: u8,
+    has_headers: bool,
+}
+
+impl Default for CsvImporter {
+    fn default() -> Self {
+        CsvImporter::new()
+    }
+}
+
+impl CsvImporter {
+    pub fn new() -> CsvImporter {
+        CsvImporter {
+            schema: None,
+            infer_rows: 1000,
+            delimiter: b',',
+            has_headers: true,
+        }
+    }
+
+    // names and types of the columns, in the order of the CSV, instead of inferring them
+    pub fn schema(&mut self, schema: Vec<(String, ColumnType)>) -> &mut Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    // number of records the types are inferred from
+    pub fn infer_rows(&mut self, rows: usize) -> &mut Self {
+        self.infer_rows = rows.max(1);
+        self
+    }
+
+    pub fn delimiter(&mut self, delimiter: u8) -> &mut Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    pub fn has_headers(&mut self, enabled: bool) -> &mut Self {
+        self.has_headers = enabled;
+        self
+    }
+
+    // write the CSV as the columns of `writer`, returns the number of rows.
+    // The caller finalizes the writer.
+    pub fn import<R: Read, W: Write>(
+        &self,
+        input: R,
+        writer: &mut Writer<W>,
+    ) -> Result<u64, ImportError> {
+        let mut reader = csv::ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .has_headers(self.has_headers)
+            .from_reader(input);
+        let headers = match self.has_headers {
+            true => Some(reader.headers()?.clone()),
+            false => None,
+        };
+        let mut records = reader.into_records();
+
+        // the sample is kept and written like the records after it
+        let sample_len = match self.schema {
+            Some(_) => 1,
+            None => self.infer_rows,
+        };
+        let sample = records
+            .by_ref()
+            .take(sample_len)
+            .collect::<Result<Vec<_>, _>>()?;
+        let width = match sample.first() {
+            Some(record) => Some(record.len()),
+            None => headers.as_ref().map(|h| h.len()),
+        };
+        let schema = match &self.schema {
+            Some(schema) => schema.clone(),
+            None => (0..width.unwrap_or(0))
+                .map(|i| {
+                    let name = match &headers {
+                        Some(headers) => column_name(headers.get(i).unwrap_or_default()),
+                        None => format!("column_{}", i + 1),
+                    };
+                    let values: Vec<&str> = sample.iter().filter_map(|r| r.get(i)).collect();
+                    (name, infer_type(&values))
+                })
+                .collect(),
+        };
+        if let Some(width) = width.filter(|w| *w != schema.len()) {
+            return Err(ImportError::SchemaError(format!(
+                "{} columns in the schema, {} in the CSV",
+                schema.len(),
+                width
+            )));
+        }
+        for (i, (name, _)) in schema.iter().enumerate() {
+            if name.is_empty() || schema[..i].iter().any(|(n, _)| n == name) {
+                return Err(ImportError::SchemaError(format!(
+                    "invalid or duplicate column name {:?}",
+                    name
+                )));
+            }
+        }
+
+        let block_size = writer.max_values_per_block() as usize;
+        let mut spools = schema
+            .iter()
+            .map(|(_, ty)| Spool::new(*ty))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut rows = 0;
+        for record in sample.into_iter().map(Ok).chain(records) {
+            let record = record?;
+            let line = record.position().map_or(0, |p| p.line());
+            for ((name, ty), (text, spool)) in schema.iter().zip(record.iter().zip(&mut spools)) {
+                let invalid = |e: String| ImportError::ValueError(line, name.clone(), e);
+                let value = ScalarValue::parse(*ty, text)
+                    .ok_or_else(|| invalid(format!("{:?} is not a valid {}", text, ty.name())))?;
+                for s in value.stats_values() {
+                    if matches!(&s, ScalarValue::Str(s) if s.len() >= STR_SIZE) {
+                        return Err(invalid(format!("{} does not fit in {} bytes", s, STR_SIZE)));
+                    }
+                }
+                spool.push(value, block_size)?;
+            }
+            rows += 1;
+        }
+        for ((name, ty), spool) in schema.iter().zip(spools) {
+            writer.write_col_blocks(name, *ty, spool.into_blocks()?)?;
+        }
+        Ok(rows)
+    }
+}
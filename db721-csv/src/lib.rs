@@ -0,0 +1,157 @@
+pub mod export;
+pub mod import;
+
+pub use export::{header_name, parse_filter, ExportError, Exporter, Format};
+pub use import::{column_name, infer_type, parse_schema, CsvImporter, ImportError};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use db721_parser::{ColumnType, ParserBuilder, ScalarValue, Writer};
+    use db721_testing::{gen_path, TmpPath};
+
+    #[test]
+    fn it_imports_csv() {
+        // the same columns as data-farms.db721, in blocks of 4 rows
+        let path = TmpPath::file("csv-farms");
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = Writer::new(String::from("Farm"), file, 4);
+        let schema = parse_schema("farm_name:str,min_age_weeks:float,max_age_weeks:float");
+        let csv = std::fs::File::open(gen_path("data-farms.csv")).unwrap();
+        let rows = CsvImporter::new()
+            .schema(schema.unwrap())
+            .import(csv, &mut writer)
+            .unwrap();
+        writer.finalize().unwrap();
+        assert_eq!(rows, 6);
+        let parser = ParserBuilder::new(path.to_string(), String::from("Farm"))
+            .build()
+            .unwrap();
+        let farms = ParserBuilder::new(gen_path("data-farms.db721"), String::from("Farm"))
+            .build()
+            .unwrap();
+        assert_eq!(parser.num_blocks(), 2);
+        for column in ["farm_name", "min_age_weeks", "max_age_weeks"] {
+            let values: Vec<_> = (0..2)
+                .flat_map(|b| {
+                    let block = parser.read_block(column, b).unwrap();
+                    (0..block.len()).map(move |i| block.get(i).unwrap())
+                })
+                .collect();
+            let expected = farms.read_block(column, 0).unwrap();
+            assert_eq!(
+                values,
+                (0..6).map(|i| expected.get(i).unwrap()).collect::<Vec<_>>()
+            );
+        }
+
+        // types inferred from the first row, errors at the line of the value
+        let csv = "Id,Name,Hatched\n1,Lucy,2023-04-01\n2,Ginger,2023-04-31\n";
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = Writer::new(String::from("Chicken"), file, 4);
+        let error = CsvImporter::new()
+            .infer_rows(1)
+            .import(csv.as_bytes(), &mut writer)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "DB721|Column: line 3, column hatched: \"2023-04-31\" is not a valid date"
+        );
+        let mut writer = Writer::new(String::from("Chicken"), Vec::new(), 4);
+        let csv = csv.replace("04-31", "04-30");
+        CsvImporter::new()
+            .import(csv.as_bytes(), &mut writer)
+            .unwrap();
+        std::fs::write(&path, writer.finalize().unwrap()).unwrap();
+        let parser = ParserBuilder::new(path.to_string(), String::from("Chicken"))
+            .build()
+            .unwrap();
+        let types: Vec<_> = parser.columns.iter().map(|c| c.column_type).collect();
+        assert_eq!(types, [ColumnType::Int, ColumnType::Str, ColumnType::Date]);
+        assert_eq!(
+            parser.read_block("hatched", 0).unwrap().get(1),
+            ScalarValue::parse(ColumnType::Date, "2023-04-30")
+        );
+    }
+
+    #[test]
+    fn it_exports_rows() {
+        let parser = ParserBuilder::new(gen_path("data-farms.db721"), String::from("Farm"))
+            .build()
+            .unwrap();
+        // the header and the values of the generator's CSV
+        let mut csv = Vec::new();
+        let rows = Exporter::new(&parser)
+            .columns(vec![
+                "farm_name".into(),
+                "min_age_weeks".into(),
+                "max_age_weeks".into(),
+            ])
+            .export(&mut csv)
+            .unwrap();
+        assert_eq!(rows, 6);
+        let expected = std::fs::read_to_string(gen_path("data-farms.csv")).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            expected.replace("\r\n", "\n")
+        );
+
+        let filter = "max_age_weeks >= 8 AND farm_name IN ('Eggscellent', 'Dish of the Day')";
+        let mut jsonl = Vec::new();
+        Exporter::new(&parser)
+            .columns(vec!["farm_name".into(), "sexes".into()])
+            .filter(parse_filter(&parser, filter).unwrap())
+            .format(Format::JsonLines)
+            .export(&mut jsonl)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(jsonl).unwrap(),
+            concat!(
+                r#"{"farm_name":"Eggscellent","sexes":["FEMALE"]}"#,
+                "\n",
+                r#"{"farm_name":"Dish of the Day","sexes":["MALE"]}"#,
+                "\n"
+            )
+        );
+        assert!(parse_filter(&parser, "farm_name = 'Incubator").is_err());
+        assert!(parse_filter(&parser, "min_age_weeks < ten").is_err());
+    }
+
+    #[test]
+    fn it_round_trips_lists() {
+        let list =
+            |elems: &[&str]| ScalarValue::StrList(elems.iter().map(|e| e.to_string()).collect());
+        let lists = [
+            list(&["a,b"]),
+            list(&[""]),
+            list(&[]),
+            list(&[r#"{"quoted"}"#, r"back\slash", "NULL"]),
+        ];
+        let path = TmpPath::file("csv-lists");
+        let mut writer = Writer::new(String::from("T"), Vec::new(), 4);
+        writer.write_col("l", ColumnType::StrList, &lists).unwrap();
+        std::fs::write(&path, writer.finalize().unwrap()).unwrap();
+        let parser = ParserBuilder::new(path.to_string(), String::from("T"))
+            .build()
+            .unwrap();
+
+        let mut csv = Vec::new();
+        Exporter::new(&parser).export(&mut csv).unwrap();
+        let mut writer = Writer::new(String::from("T"), Vec::new(), 4);
+        CsvImporter::new()
+            .schema(parse_schema("l:list[str]").unwrap())
+            .import(csv.as_slice(), &mut writer)
+            .unwrap();
+        std::fs::write(&path, writer.finalize().unwrap()).unwrap();
+        let parser = ParserBuilder::new(path.to_string(), String::from("T"))
+            .build()
+            .unwrap();
+        let block = parser.read_block("l", 0).unwrap();
+        let values: Vec<_> = (0..block.len()).map(|i| block.get(i).unwrap()).collect();
+        assert_eq!(values, lists);
+
+        // list literals in filters are read the same way
+        let filter = parse_filter(&parser, r#"l = '{"a,b"}'"#).unwrap();
+        assert_eq!(filter[0].values, [lists[0].clone()]);
+    }
+}
@@ -0,0 +1,120 @@
+use clap::{Parser as CliParser, ValueEnum};
+use db721_csv::{parse_schema, CsvImporter, ImportError};
+use db721_parser::staging::tmp_path;
+use db721_parser::{Checksum, Codec, DeleteVector, FileLock, Staged, Writer};
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::process::ExitCode;
+
+/* Convert CSV into a db721 file, reading the CSV only once:
+
+    csv2db721 data-farms.csv data-farms.db721 --table Farm \
+        --schema farm_name:str,min_age_weeks:float,max_age_weeks:float
+    generate | csv2db721 - data-chickens.db721 --table Chicken --max-values-per-block 50000
+*/
+
+#[derive(CliParser)]
+#[command(name = "csv2db721", version, about = "Convert CSV into a db721 file")]
+struct Cli {
+    /// CSV file, - for stdin
+    input: String,
+    output: String,
+    /// Name of the table, the name of the output file by default
+    #[arg(long)]
+    table: Option<String>,
+    /// Names and types of the columns, like farm_name:str,min_age_weeks:float.
+    /// Inferred from the header and the first rows by default
+    #[arg(long)]
+    schema: Option<String>,
+    /// Number of rows the types are inferred from
+    #[arg(long, default_value_t = 1000)]
+    infer_rows: usize,
+    #[arg(long, default_value_t = 50000)]
+    max_values_per_block: u32,
+    #[arg(long, default_value_t = ',')]
+    delimiter: char,
+    /// The first line is a row, not the header
+    #[arg(long)]
+    no_header: bool,
+    #[arg(long, value_enum, default_value_t = Compression::None)]
+    compression: Compression,
+    /// Keep every block in the plain encoding, rather than the smallest one
+    #[arg(long)]
+    plain: bool,
+    #[arg(long)]
+    bloom_filter: bool,
+    /// Store CRC32C checksums of the blocks and the metadata
+    #[arg(long)]
+    checksum: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Compression {
+    None,
+    Lz4,
+    Zstd,
+}
+
+fn convert(cli: &Cli) -> Result<u64, ImportError> {
+    let mut importer = CsvImporter::new();
+    importer
+        .infer_rows(cli.infer_rows)
+        .delimiter(cli.delimiter as u8)
+        .has_headers(!cli.no_header);
+    if let Some(schema) = &cli.schema {
+        importer.schema(parse_schema(schema)?);
+    }
+    let input: Box<dyn Read> = match cli.input.as_str() {
+        "-" => Box::new(std::io::stdin().lock()),
+        file => Box::new(std::fs::File::open(file)?),
+    };
+    let table = match &cli.table {
+        Some(table) => table.clone(),
+        None => Path::new(&cli.output)
+            .file_stem()
+            .map_or(String::new(), |s| s.to_string_lossy().into_owned()),
+    };
+    // written next to the output and renamed over it once complete, an existing file
+    // is left as it is after an error
+    let tmp = tmp_path(&cli.output);
+    let mut staged = Staged::default();
+    staged.push(tmp.clone(), cli.output.clone());
+    let output = BufWriter::new(std::fs::File::create(&tmp)?);
+    let mut writer = Writer::new(table, output, cli.max_values_per_block);
+    writer
+        .compression(match cli.compression {
+            Compression::None => Codec::None,
+            Compression::Lz4 => Codec::Lz4,
+            Compression::Zstd => Codec::Zstd,
+        })
+        .choose_encoding(!cli.plain)
+        .bloom_filter(cli.bloom_filter)
+        .checksum(cli.checksum.then_some(Checksum::Crc32c));
+    let rows = importer.import(input, &mut writer)?;
+    let mut out = writer.finalize()?;
+    out.flush()?;
+    out.get_ref().sync_all()?;
+    // the rows are new, deletes of those of an existing file do not apply
+    staged.remove(DeleteVector::path(&cli.output));
+    let _lock = FileLock::acquire(&cli.output)?;
+    staged.publish()?;
+    Ok(rows)
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    if !cli.delimiter.is_ascii() {
+        eprintln!("csv2db721: the delimiter must be an ASCII character");
+        return ExitCode::FAILURE;
+    }
+    match convert(&cli) {
+        Ok(rows) => {
+            eprintln!("Wrote {} rows to '{}'.", rows, cli.output);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("csv2db721: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
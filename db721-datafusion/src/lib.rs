@@ -0,0 +1,644 @@
+use std::any::Any;
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use datafusion::arrow::array::BooleanArray;
+use datafusion::arrow::compute::filter_record_batch;
+use datafusion::arrow::datatypes::{DataType, SchemaRef};
+use datafusion::catalog::{DynamicFileCatalog, Session, TableProvider, UrlTableFactory};
+use datafusion::common::stats::Precision;
+use datafusion::common::{ColumnStatistics, DataFusionError, Result, Statistics};
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::logical_expr::{Between, BinaryExpr, Cast, Expr, Operator as BinaryOperator};
+use datafusion::logical_expr::{TableProviderFilterPushDown, TableType};
+use datafusion::physical_expr::EquivalenceProperties;
+use datafusion::physical_plan::execution_plan::{Boundedness, EmissionType};
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::{
+    DisplayAs, DisplayFormatType, ExecutionPlan, Partitioning, PlanProperties,
+};
+use datafusion::prelude::SessionContext;
+use datafusion::scalar::ScalarValue as DfScalar;
+use db721_parser::arrow::arrow_type;
+use db721_parser::{
+    BuildError, ColumnType, Operator, Parser, ParserBuilder, Predicate, ScalarValue,
+};
+
+/*
+A DataFusion table over a db721 file:
+
+    let ctx = SessionContext::new();
+    ctx.register_table("chickens", Arc::new(Db721Table::open("data-chickens.db721")?))?;
+    ctx.sql("SELECT farm_name, count(*) FROM chickens WHERE weight_g > 2000 GROUP BY 1").await?;
+
+or, with `with_db721_files`, over any file named in the query:
+
+    SELECT * FROM 'data-chickens.db721' LIMIT 10
+
+Pushed down to the scan:
+    projection  only the projected columns are read, see `Parser::record_batch`
+    filters     comparisons of a column with a literal (=, <, <=, >, >=, BETWEEN, IN)
+                and array_has(list column, literal) become predicates, which prune
+                blocks by their stats and bloom filters and then filter the rows of
+                the remaining blocks. They are Inexact, DataFusion rechecks them
+    limit       the scan stops once it has produced enough rows
+    statistics  the row count and the min/max of every column from the block stats
+
+The blocks left after pruning are split into up to `target_partitions` partitions of
+consecutive blocks.
+*/
+
+fn external(error: BuildError) -> DataFusionError {
+    DataFusionError::External(Box::new(error))
+}
+
+// a literal as a value of a column of type `ty`, None if it does not convert exactly
+fn to_scalar(value: &DfScalar, ty: ColumnType) -> Option<ScalarValue> {
+    let cast = value.cast_to(&arrow_type(ty)).ok()?;
+    // e.g. 10.5 must not become 10 for an int column
+    if cast.cast_to(&value.data_type()).ok()? != *value {
+        return None;
+    }
+    Some(match cast {
+        DfScalar::Int32(Some(v)) => ScalarValue::Int(v),
+        DfScalar::Float32(Some(v)) => ScalarValue::Float(v),
+        DfScalar::Utf8(Some(v)) => ScalarValue::Str(v),
+        DfScalar::Int64(Some(v)) => ScalarValue::BigInt(v),
+        DfScalar::Float64(Some(v)) => ScalarValue::Double(v),
+        DfScalar::Boolean(Some(v)) => ScalarValue::Bool(v),
+        DfScalar::Date32(Some(v)) => ScalarValue::Date(v),
+        DfScalar::TimestampMicrosecond(Some(v), _) => ScalarValue::Timestamp(v),
+        _ => return None,
+    })
+}
+
+fn to_df_scalar(value: &ScalarValue) -> Option<DfScalar> {
+    Some(match value {
+        ScalarValue::Int(v) => DfScalar::Int32(Some(*v)),
+        ScalarValue::Float(v) => DfScalar::Float32(Some(*v)),
+        ScalarValue::Str(v) => DfScalar::Utf8(Some(v.clone())),
+        ScalarValue::BigInt(v) => DfScalar::Int64(Some(*v)),
+        ScalarValue::Double(v) => DfScalar::Float64(Some(*v)),
+        ScalarValue::Bool(v) => DfScalar::Boolean(Some(*v)),
+        ScalarValue::Date(v) => DfScalar::Date32(Some(*v)),
+        ScalarValue::Timestamp(v) => DfScalar::TimestampMicrosecond(Some(*v), Some("UTC".into())),
+        ScalarValue::StrList(_) => return None,
+    })
+}
+
+// the predicates equivalent to a filter, None if it cannot be pushed down
+pub fn to_predicates(parser: &Parser, filter: &Expr) -> Option<Vec<Predicate>> {
+    fn column(parser: &Parser, expr: &Expr) -> Option<(String, ColumnType)> {
+        match expr {
+            Expr::Column(column) => {
+                let meta = parser.column_meta(&column.name)?;
+                Some((column.name.clone(), meta.column_type))
+            }
+            // `int_col < 2.0` compares CAST(int_col AS DOUBLE), which is exact for int
+            // and float, so the comparison holds for the column itself
+            Expr::Cast(Cast { expr, data_type }) => {
+                let (name, ty) = column(parser, expr)?;
+                let widened = matches!(
+                    (ty, data_type),
+                    (ColumnType::Int, DataType::Int64 | DataType::Float64)
+                        | (ColumnType::Float, DataType::Float64)
+                );
+                widened.then_some((name, ty))
+            }
+            _ => None,
+        }
+    }
+    let column = |expr: &Expr| column(parser, expr);
+    let literal = |expr: &Expr, ty: ColumnType| match expr {
+        Expr::Literal(value) => to_scalar(value, ty),
+        _ => None,
+    };
+    match filter {
+        Expr::BinaryExpr(BinaryExpr { left, op, right }) => {
+            if *op == BinaryOperator::And {
+                let mut predicates = to_predicates(parser, left)?;
+                predicates.extend(to_predicates(parser, right)?);
+                return Some(predicates);
+            }
+            // `5 < col` is `col > 5`
+            let (name, ty, value, op) = match (column(left), column(right)) {
+                (Some((name, ty)), None) => (name, ty, right, *op),
+                (None, Some((name, ty))) => (name, ty, left, op.swap()?),
+                _ => return None,
+            };
+            let operator = match op {
+                BinaryOperator::Eq => Operator::Eq,
+                BinaryOperator::Lt => Operator::Lt,
+                BinaryOperator::LtEq => Operator::Le,
+                BinaryOperator::Gt => Operator::Gt,
+                BinaryOperator::GtEq => Operator::Ge,
+                _ => return None,
+            };
+            if ty == ColumnType::StrList {
+                return None;
+            }
+            Some(vec![Predicate::new(&name, operator, literal(value, ty)?)])
+        }
+        Expr::Between(Between {
+            expr,
+            negated: false,
+            low,
+            high,
+        }) => {
+            let (name, ty) = column(expr).filter(|(_, ty)| *ty != ColumnType::StrList)?;
+            Some(vec![
+                Predicate::new(&name, Operator::Ge, literal(low, ty)?),
+                Predicate::new(&name, Operator::Le, literal(high, ty)?),
+            ])
+        }
+        Expr::InList(list) if !list.negated => {
+            let (name, ty) = column(&list.expr).filter(|(_, ty)| *ty != ColumnType::StrList)?;
+            let values = list
+                .list
+                .iter()
+                .map(|v| literal(v, ty))
+                .collect::<Option<_>>()?;
+            Some(vec![Predicate::in_list(&name, values)])
+        }
+        // `'MALE' = ANY(sexes)` in postgres
+        Expr::ScalarFunction(function) if function.name() == "array_has" => {
+            let (name, ty) = column(function.args.first()?)?;
+            if ty != ColumnType::StrList {
+                return None;
+            }
+            let value = literal(function.args.get(1)?, ColumnType::Str)?;
+            Some(vec![Predicate::new(&name, Operator::Contains, value)])
+        }
+        _ => None,
+    }
+}
+
+// the min and max of a column over the blocks, None if any block lacks them
+fn column_bounds(
+    parser: &Parser,
+    name: &str,
+    blocks: &[usize],
+) -> Option<(ScalarValue, ScalarValue)> {
+    let mut bounds: Option<(ScalarValue, ScalarValue)> = None;
+    for block in blocks {
+        let stats = parser.block_stats(name, *block)?;
+        if stats.num == 0 {
+            continue;
+        }
+        let (min, max) = (stats.min.clone()?, stats.max.clone()?);
+        bounds = Some(match bounds {
+            None => (min, max),
+            Some((lo, hi)) => (
+                match min.compare(&lo)? {
+                    Ordering::Less => min,
+                    _ => lo,
+                },
+                match max.compare(&hi)? {
+                    Ordering::Greater => max,
+                    _ => hi,
+                },
+            ),
+        });
+    }
+    bounds
+}
+
+// the statistics of `columns` over `blocks`, exact when all blocks are read unfiltered
+fn statistics(parser: &Parser, columns: &[String], blocks: &[usize], exact: bool) -> Statistics {
+    let deleted: usize = blocks.iter().map(|b| parser.block_num_deleted(*b)).sum();
+    let num_rows = blocks.iter().map(|b| parser.block_len(*b)).sum::<usize>() - deleted;
+    // the bounds may come from deleted rows
+    let exact_bounds = exact && deleted == 0;
+    let precision = |v: Option<DfScalar>| match (v, exact_bounds) {
+        (Some(v), true) => Precision::Exact(v),
+        (Some(v), false) => Precision::Inexact(v),
+        (None, _) => Precision::Absent,
+    };
+    let column_statistics = columns
+        .iter()
+        .map(|name| {
+            let list = parser.column_meta(name).map(|m| m.column_type) == Some(ColumnType::StrList);
+            // the stats of a list column are those of its elements
+            let bounds = column_bounds(parser, name, blocks).filter(|_| !list);
+            let (min, max) = match &bounds {
+                Some((min, max)) => (to_df_scalar(min), to_df_scalar(max)),
+                None => (None, None),
+            };
+            ColumnStatistics {
+                null_count: Precision::Exact(0),
+                max_value: precision(max),
+                min_value: precision(min),
+                sum_value: Precision::Absent,
+                distinct_count: Precision::Absent,
+            }
+        })
+        .collect();
+    Statistics {
+        num_rows: match exact {
+            true => Precision::Exact(num_rows),
+            false => Precision::Inexact(num_rows),
+        },
+        total_byte_size: Precision::Absent,
+        column_statistics,
+    }
+}
+
+// A db721 file as a DataFusion table
+pub struct Db721Table {
+    parser: Arc<Parser>,
+    schema: SchemaRef,
+}
+
+impl fmt::Debug for Db721Table {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Db721Table")
+            .field("table", &self.parser.metadata.table)
+            .finish()
+    }
+}
+
+impl Db721Table {
+    pub fn open(filename: &str) -> Result<Db721Table, BuildError> {
+        let parser = ParserBuilder::new(filename.to_string(), String::new()).build()?;
+        Db721Table::try_new(Arc::new(parser))
+    }
+
+    pub fn try_new(parser: Arc<Parser>) -> Result<Db721Table, BuildError> {
+        let schema = parser.arrow_schema(None)?;
+        Ok(Db721Table { parser, schema })
+    }
+}
+
+#[async_trait]
+impl TableProvider for Db721Table {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn schema(&self) -> SchemaRef {
+        self.schema.clone()
+    }
+
+    fn table_type(&self) -> TableType {
+        TableType::Base
+    }
+
+    fn supports_filters_pushdown(
+        &self,
+        filters: &[&Expr],
+    ) -> Result<Vec<TableProviderFilterPushDown>> {
+        Ok(filters
+            .iter()
+            .map(|filter| match to_predicates(&self.parser, filter) {
+                Some(_) => TableProviderFilterPushDown::Inexact,
+                None => TableProviderFilterPushDown::Unsupported,
+            })
+            .collect())
+    }
+
+    fn statistics(&self) -> Option<Statistics> {
+        let columns: Vec<String> = self
+            .schema
+            .fields()
+            .iter()
+            .map(|f| f.name().clone())
+            .collect();
+        let blocks: Vec<usize> = (0..self.parser.num_blocks()).collect();
+        Some(statistics(&self.parser, &columns, &blocks, true))
+    }
+
+    async fn scan(
+        &self,
+        state: &dyn Session,
+        projection: Option<&Vec<usize>>,
+        filters: &[Expr],
+        limit: Option<usize>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        let schema = match projection {
+            Some(projection) => Arc::new(self.schema.project(projection)?),
+            None => self.schema.clone(),
+        };
+        let predicates: Vec<Predicate> = filters
+            .iter()
+            .filter_map(|filter| to_predicates(&self.parser, filter))
+            .flatten()
+            .collect();
+        let blocks = self.parser.prune(&predicates);
+        let partitions = state
+            .config()
+            .target_partitions()
+            .clamp(1, blocks.len().max(1));
+        Ok(Arc::new(Db721Exec::new(
+            self.parser.clone(),
+            schema,
+            blocks,
+            predicates,
+            limit,
+            partitions,
+        )))
+    }
+}
+
+// Reads blocks of a db721 file, see `Db721Table::scan`
+pub struct Db721Exec {
+    parser: Arc<Parser>,
+    schema: SchemaRef,
+    columns: Vec<String>,
+    // the blocks left after pruning, consecutive runs of them per partition
+    partitions: Vec<Vec<usize>>,
+    predicates: Vec<Predicate>,
+    limit: Option<usize>,
+    properties: PlanProperties,
+}
+
+impl Db721Exec {
+    fn new(
+        parser: Arc<Parser>,
+        schema: SchemaRef,
+        blocks: Vec<usize>,
+        predicates: Vec<Predicate>,
+        limit: Option<usize>,
+        partitions: usize,
+    ) -> Db721Exec {
+        let columns = schema.fields().iter().map(|f| f.name().clone()).collect();
+        let partitions: Vec<Vec<usize>> = match blocks.len() {
+            0 => vec![Vec::new()],
+            len => blocks
+                .chunks(len.div_ceil(partitions))
+                .map(|c| c.to_vec())
+                .collect(),
+        };
+        let properties = PlanProperties::new(
+            EquivalenceProperties::new(schema.clone()),
+            Partitioning::UnknownPartitioning(partitions.len()),
+            EmissionType::Incremental,
+            Boundedness::Bounded,
+        );
+        Db721Exec {
+            parser,
+            schema,
+            columns,
+            partitions,
+            predicates,
+            limit,
+            properties,
+        }
+    }
+
+    pub fn blocks(&self) -> Vec<usize> {
+        self.partitions.concat()
+    }
+}
+
+impl fmt::Debug for Db721Exec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Db721Exec")
+            .field("table", &self.parser.metadata.table)
+            .field("columns", &self.columns)
+            .field("partitions", &self.partitions)
+            .field("predicates", &self.predicates)
+            .field("limit", &self.limit)
+            .finish()
+    }
+}
+
+impl DisplayAs for Db721Exec {
+    fn fmt_as(&self, _t: DisplayFormatType, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Db721Exec: table={}, blocks={}/{}, predicates={}",
+            self.parser.metadata.table,
+            self.blocks().len(),
+            self.parser.num_blocks(),
+            self.predicates.len()
+        )?;
+        if let Some(limit) = self.limit {
+            write!(f, ", limit={}", limit)?;
+        }
+        Ok(())
+    }
+}
+
+impl ExecutionPlan for Db721Exec {
+    fn name(&self) -> &str {
+        "Db721Exec"
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn properties(&self) -> &PlanProperties {
+        &self.properties
+    }
+
+    fn children(&self) -> Vec<&Arc<dyn ExecutionPlan>> {
+        Vec::new()
+    }
+
+    fn with_new_children(
+        self: Arc<Self>,
+        _children: Vec<Arc<dyn ExecutionPlan>>,
+    ) -> Result<Arc<dyn ExecutionPlan>> {
+        Ok(self)
+    }
+
+    fn execute(
+        &self,
+        partition: usize,
+        _context: Arc<TaskContext>,
+    ) -> Result<SendableRecordBatchStream> {
+        let parser = self.parser.clone();
+        let columns = self.columns.clone();
+        let predicates = self.predicates.clone();
+        let mut remaining = self.limit.unwrap_or(usize::MAX);
+        let blocks = self.partitions.get(partition).cloned().unwrap_or_default();
+        let batches = blocks.into_iter().map_while(move |block| {
+            if remaining == 0 {
+                return None;
+            }
+            let batch = (|| {
+                let mut batch = parser
+                    .record_batch(block, Some(&columns))
+                    .map_err(external)?;
+                if !predicates.is_empty() {
+                    let start = parser.block_start(block);
+                    // the batch holds the rows left after deletes, so does the selection
+                    let selection: Vec<bool> = parser
+                        .filter_block(block, &predicates)
+                        .map_err(external)?
+                        .into_iter()
+                        .enumerate()
+                        .filter(|(row, _)| !parser.deletes.is_deleted(start + row))
+                        .map(|(_, selected)| selected)
+                        .collect();
+                    if selection.iter().any(|s| !*s) {
+                        batch = filter_record_batch(&batch, &BooleanArray::from(selection))?;
+                    }
+                }
+                let len = batch.num_rows().min(remaining);
+                remaining -= len;
+                Ok(batch.slice(0, len))
+            })();
+            Some(batch)
+        });
+        Ok(Box::pin(RecordBatchStreamAdapter::new(
+            self.schema.clone(),
+            futures::stream::iter(batches),
+        )))
+    }
+
+    fn statistics(&self) -> Result<Statistics> {
+        let blocks = self.blocks();
+        let exact = self.predicates.is_empty() && blocks.len() == self.parser.num_blocks();
+        let mut statistics = statistics(&self.parser, &self.columns, &blocks, exact);
+        if let Some(limit) = self.limit {
+            statistics.num_rows = statistics.num_rows.min(&Precision::Exact(limit));
+        }
+        Ok(statistics)
+    }
+
+    fn supports_limit_pushdown(&self) -> bool {
+        true
+    }
+
+    fn with_fetch(&self, limit: Option<usize>) -> Option<Arc<dyn ExecutionPlan>> {
+        Some(Arc::new(Db721Exec {
+            parser: self.parser.clone(),
+            schema: self.schema.clone(),
+            columns: self.columns.clone(),
+            partitions: self.partitions.clone(),
+            predicates: self.predicates.clone(),
+            limit,
+            properties: self.properties.clone(),
+        }))
+    }
+
+    fn fetch(&self) -> Option<usize> {
+        self.limit
+    }
+}
+
+// Opens the files named in queries, `SELECT * FROM 'data-chickens.db721'`
+#[derive(Debug, Default)]
+pub struct Db721UrlTableFactory {}
+
+#[async_trait]
+impl UrlTableFactory for Db721UrlTableFactory {
+    async fn try_new(&self, url: &str) -> Result<Option<Arc<dyn TableProvider>>> {
+        if !url.ends_with(".db721") {
+            return Ok(None);
+        }
+        let table = Db721Table::open(url).map_err(external)?;
+        Ok(Some(Arc::new(table)))
+    }
+}
+
+// `ctx` with tables for the db721 files named in queries
+pub fn with_db721_files(ctx: SessionContext) -> SessionContext {
+    let catalog_list = Arc::new(DynamicFileCatalog::new(
+        ctx.state().catalog_list().clone(),
+        Arc::new(Db721UrlTableFactory::default()),
+    ));
+    ctx.into_state_builder()
+        .with_catalog_list(catalog_list)
+        .build()
+        .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use datafusion::arrow::array::AsArray;
+    use datafusion::arrow::datatypes::Int64Type;
+    use datafusion::physical_plan::displayable;
+    use db721_parser::Writer;
+    use db721_testing::{gen_path, TmpPath};
+
+    fn farms_path() -> String {
+        gen_path("data-farms.db721")
+    }
+
+    async fn count(ctx: &SessionContext, sql: &str) -> i64 {
+        let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        batches[0].column(0).as_primitive::<Int64Type>().value(0)
+    }
+
+    #[tokio::test]
+    async fn it_queries_files() {
+        let ctx = with_db721_files(SessionContext::new());
+        let table = format!("'{}'", farms_path());
+        let sql = format!("SELECT count(*) FROM {} WHERE max_age_weeks > 100", table);
+        assert_eq!(count(&ctx, &sql).await, 2);
+        let sql = format!(
+            "SELECT count(*) FROM {} WHERE array_has(sexes, 'MALE') AND farm_name IN ('Incubator', 'Eggscellent')",
+            table
+        );
+        assert_eq!(count(&ctx, &sql).await, 1);
+
+        // 10 blocks of 10 rows, the filter leaves 2 of them
+        let ids: Vec<_> = (0..100).map(ScalarValue::Int).collect();
+        let weights: Vec<_> = (0..100)
+            .map(|i| ScalarValue::Double(i as f64 / 2.0))
+            .collect();
+        let tmp = TmpPath::file("datafusion");
+        let path = tmp.to_string();
+        let file = std::fs::File::create(&path).unwrap();
+        let mut writer = Writer::new(String::from("Chicken"), file, 10);
+        writer
+            .write_col("identifier", ColumnType::Int, &ids)
+            .unwrap();
+        writer
+            .write_col("weight", ColumnType::Double, &weights)
+            .unwrap();
+        writer.finalize().unwrap();
+        let table = Db721Table::open(&path).unwrap();
+        let statistics = table.statistics().unwrap();
+        assert_eq!(statistics.num_rows, Precision::Exact(100));
+        assert_eq!(
+            statistics.column_statistics[1].max_value,
+            Precision::Exact(DfScalar::Float64(Some(49.5)))
+        );
+        ctx.register_table("chickens", Arc::new(table)).unwrap();
+        let sql = "SELECT identifier FROM chickens WHERE 15 <= identifier AND identifier < 25.0";
+        let plan = ctx
+            .sql(sql)
+            .await
+            .unwrap()
+            .create_physical_plan()
+            .await
+            .unwrap();
+        let plan = displayable(plan.as_ref()).indent(false).to_string();
+        assert!(plan.contains("blocks=2/10, predicates=2"), "{}", plan);
+        let sql = "SELECT count(*) FROM chickens WHERE identifier BETWEEN 15 AND 24";
+        assert_eq!(count(&ctx, sql).await, 10);
+        // 10.5 is no int, which leaves the filter to DataFusion
+        let sql = "SELECT count(*) FROM chickens WHERE identifier > 10.5";
+        assert_eq!(count(&ctx, sql).await, 89);
+
+        let sql = "SELECT * FROM chickens LIMIT 3";
+        let plan = ctx
+            .sql(sql)
+            .await
+            .unwrap()
+            .create_physical_plan()
+            .await
+            .unwrap();
+        let plan = displayable(plan.as_ref()).indent(false).to_string();
+        assert!(plan.contains("limit=3"), "{}", plan);
+        let batches = ctx.sql(sql).await.unwrap().collect().await.unwrap();
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 3);
+
+        // deleted rows are neither counted nor matched
+        db721_parser::delete_rows(&path, &[0, 16, 20]).unwrap();
+        ctx.deregister_table("chickens").unwrap();
+        let table = Db721Table::open(&path).unwrap();
+        assert_eq!(table.statistics().unwrap().num_rows, Precision::Exact(97));
+        ctx.register_table("chickens", Arc::new(table)).unwrap();
+        assert_eq!(count(&ctx, "SELECT count(*) FROM chickens").await, 97);
+        let sql = "SELECT count(*) FROM chickens WHERE identifier BETWEEN 15 AND 24";
+        assert_eq!(count(&ctx, sql).await, 8);
+    }
+}
@@ -179,7 +179,7 @@ class Db721BlockStatistics:
             "min": None,
             "max": None,
         }
-        if self.col_type == "str":
+        if self.col_type in ("str", "list[str]"):
             self.stats["min_len"] = None
             self.stats["max_len"] = None
 
@@ -191,11 +191,13 @@ class Db721BlockStatistics:
                 self.stats[stat_name] = op(val, self.stats[stat_name])
 
         self.stats["num"] += 1
-        _update_key(min, "min", col_val)
-        _update_key(max, "max", col_val)
-        if self.col_type == "str":
-            _update_key(min, "min_len", len(col_val))
-            _update_key(max, "max_len", len(col_val))
+        # For lists, the stats are taken over the elements.
+        for val in col_val if self.col_type == "list[str]" else [col_val]:
+            _update_key(min, "min", val)
+            _update_key(max, "max", val)
+            if self.col_type in ("str", "list[str]"):
+                _update_key(min, "min_len", len(val))
+                _update_key(max, "max_len", len(val))
 
 
 class Db721Serializer:
@@ -204,10 +206,12 @@ class Db721Serializer:
         self.outfile = outfile
         self.max_values_per_block = max_values_per_block
         self._num_values = 0
+        self._block_size = 0
         self._current_block_offset = 0
 
     def _new_block(self):
         self._num_values = 0
+        self._block_size = 0
         self._current_block_offset += 1
 
     def _serialize(self, col_type: str, col_val):
@@ -215,10 +219,26 @@ class Db721Serializer:
         if col_type == "str":
             assert len(col_val) < 32, "Only support fixed-length null-terminated 32-byte strings."
             bytes_val = col_val.ljust(32, "\0").encode("ASCII")
+        elif col_type == "list[str]":
+            # Element count, then the elements as "str".
+            assert all(len(val) < 32 for val in col_val), "Only support fixed-length null-terminated 32-byte strings."
+            bytes_val = pack("I", len(col_val)) + b"".join(val.ljust(32, "\0").encode("ASCII") for val in col_val)
         elif col_type == "int":
             bytes_val = pack("i", col_val)
         elif col_type == "float":
             bytes_val = pack("f", col_val)
+        elif col_type == "bigint":
+            bytes_val = pack("q", col_val)
+        elif col_type == "double":
+            bytes_val = pack("d", col_val)
+        elif col_type == "bool":
+            bytes_val = pack("?", col_val)
+        elif col_type == "date":
+            # days since 1970-01-01
+            bytes_val = pack("i", col_val)
+        elif col_type == "timestamp":
+            # microseconds since 1970-01-01 UTC
+            bytes_val = pack("q", col_val)
         else:
             raise RuntimeError(f"Bad type: {col_type}, for {col_val}")
 
@@ -226,11 +246,13 @@ class Db721Serializer:
             return False
         self.outfile.write(bytes_val)
         self._num_values += 1
+        self._block_size += len(bytes_val)
         return True
 
     def write_col(self, col_name: str, col_type: str, col_contents):
         assert col_name not in self.json_schema, "Duplicate column?"
         self._num_values = 0
+        self._block_size = 0
         self._current_block_offset = 0
 
         self.json_schema["Columns"][col_name] = {
@@ -240,6 +262,13 @@ class Db721Serializer:
             "start_offset": self.outfile.tell(),
         }
 
+        def _block_stats():
+            stats = col_stats.stats.copy()
+            # Variable-width blocks cannot be located from "num" alone.
+            if col_type == "list[str]":
+                stats["size"] = self._block_size
+            return stats
+
         col_stats = Db721BlockStatistics(col_type)
         for i, col_val in enumerate(col_contents):
             while True:
@@ -250,19 +279,19 @@ class Db721Serializer:
                 else:
                     # Ran out of space, start a new block.
                     self.json_schema["Columns"][col_name]["block_stats"][
-                        self._current_block_offset] = col_stats.stats.copy()
+                        self._current_block_offset] = _block_stats()
                     col_stats.reset()
                     self._new_block()
 
         num_blocks = self._current_block_offset + 1
-        self.json_schema["Columns"][col_name]["block_stats"][self._current_block_offset] = col_stats.stats.copy()
+        self.json_schema["Columns"][col_name]["block_stats"][self._current_block_offset] = _block_stats()
         self.json_schema["Columns"][col_name]["num_blocks"] = num_blocks
         return num_blocks
 
     def finalize(self):
         json_schema = json.dumps(self.json_schema)
         self.outfile.write(json_schema.encode("ASCII"))
-        self.outfile.write(pack("i", len(json_schema)))
+        self.outfile.write(pack("<I", len(json_schema)))
 
 
 def main():
@@ -302,8 +331,7 @@ def main():
     with open(db721_file_farms, "wb") as f:
         serializer = Db721Serializer("Farm", f)
         serializer.write_col("farm_name", "str", [farm.farm_name for farm in all_farms])
-        # TODO(WAN): list and map types are probably too difficult, we stripped out this code.
-        # serializer.write_col(table_name, "Sexes", "list[str]", [farm.sexes for farm in farms])
+        serializer.write_col("sexes", "list[str]", [farm.sexes for farm in all_farms])
         serializer.write_col("min_age_weeks", "float", [farm.min_age_weeks for farm in all_farms])
         serializer.write_col("max_age_weeks", "float", [farm.max_age_weeks for farm in all_farms])
         pre_header_tell = f.tell()
@@ -10,9 +10,11 @@ CREATE SERVER IF NOT EXISTS polo_server
 CREATE FOREIGN TABLE IF NOT EXISTS db721_farm
 (                                   
     farm_name       varchar,
+    sexes           varchar[],
     min_age_weeks   real,
     max_age_weeks   real
 ) SERVER polo_server OPTIONS
 (
+    filename '/home/polo/Polo/PostgreSQL-FDW/db721-gen/data-farms.db721',
     tablename 'Farm'
 );
@@ -0,0 +1,183 @@
+use crate::random::PyRandom;
+
+/*
+The chickens and farms of the ChickenFarm benchmark.
+
+The weight of a chicken of up to 6 weeks follows one of three growth curves, picked at
+random, with the parameters for broilers of
+
+    M. Topal & Ş. C. Bolukbasi (2008),
+    Comparison of Nonlinear Growth Curve Models in Broiler Chickens,
+    Journal of Applied Animal Research
+    https://www.tandfonline.com/doi/pdf/10.1080/09712119.2008.9706960
+
+    GOMPERTZ    A * exp(-B * exp(-k * t))
+    MMF         (A * B + k * t^D) / (B + t^D)
+    WEIBULL     A - B * exp(-k * t^D)
+
+Older chickens, for which the curves are absurd, weigh between 1800 and
+2100 + 200 * (years of age) grams, at most 4500 (RNG). Up to 445 grams of noise are
+added to every weight, which is then rounded to 2 decimals.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sex {
+    Female,
+    Male,
+}
+
+impl Sex {
+    pub const ALL: [Sex; 2] = [Sex::Female, Sex::Male];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Sex::Female => "FEMALE",
+            Sex::Male => "MALE",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WeightModel {
+    Gompertz,
+    Weibull,
+    Mmf,
+    // uniformly random, for chickens older than 6 weeks
+    Rng,
+}
+
+impl WeightModel {
+    // the growth curves, in the order they are picked from
+    pub const CURVES: [WeightModel; 3] = [
+        WeightModel::Gompertz,
+        WeightModel::Weibull,
+        WeightModel::Mmf,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            WeightModel::Gompertz => "GOMPERTZ",
+            WeightModel::Weibull => "WEIBULL",
+            WeightModel::Mmf => "MMF",
+            WeightModel::Rng => "RNG",
+        }
+    }
+
+    // the weight in grams at `t` weeks, None for Rng
+    pub fn weight(&self, sex: Sex, t: f64) -> Option<f64> {
+        Some(match (self, sex) {
+            (WeightModel::Gompertz, Sex::Female) => gompertz(6282.347, 5.313, 0.268, t),
+            (WeightModel::Gompertz, Sex::Male) => gompertz(5453.802, 4.916, 0.265, t),
+            (WeightModel::Mmf, Sex::Female) => mmf(41.542, 275.155, 15222.91, 2.123, t),
+            (WeightModel::Mmf, Sex::Male) => mmf(38.714, 424.566, 31247.15, 1.871, t),
+            (WeightModel::Weibull, Sex::Female) => weibull(8635.340, 8594.225, 0.006, 2.110, t),
+            (WeightModel::Weibull, Sex::Male) => weibull(17435.182, 17396.753, 0.004, 1.865, t),
+            (WeightModel::Rng, _) => return None,
+        })
+    }
+}
+
+fn gompertz(a: f64, b: f64, k: f64, t: f64) -> f64 {
+    a * (-b * (-k * t).exp()).exp()
+}
+
+fn mmf(a: f64, b: f64, k: f64, d: f64, t: f64) -> f64 {
+    (a * b + k * t.powf(d)) / (b + t.powf(d))
+}
+
+fn weibull(a: f64, b: f64, k: f64, d: f64, t: f64) -> f64 {
+    a - b * (-k * t.powf(d)).exp()
+}
+
+// rounded like Python's round(value, 2), i.e. on the exact decimal value
+pub fn round2(value: f64) -> f64 {
+    format!("{:.2}", value).parse().unwrap()
+}
+
+pub const MIN_AGE_WEEKS: u32 = 0;
+pub const MAX_AGE_WEEKS: u32 = 52 * 12;
+
+// the model and the weight of a chicken
+pub fn weight_grams(sex: Sex, age_weeks: f64, rand: &mut PyRandom) -> (WeightModel, f64) {
+    let (model, weight) = if (0.0..=6.0).contains(&age_weeks) {
+        let model = *rand.choice(&WeightModel::CURVES);
+        (model, model.weight(sex, age_weeks).unwrap())
+    } else {
+        let max_weight = (2100.0 + 200.0 * (age_weeks / 52.0).floor()).min(4500.0);
+        (WeightModel::Rng, rand.uniform(1800.0, max_weight))
+    };
+    // so that the weight isn't too predictable
+    let noise = rand.uniform(0.0, 445.0);
+    (model, round2(weight + noise))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chicken {
+    pub identifier: i32,
+    pub farm_name: String,
+    pub weight_model: WeightModel,
+    pub sex: Sex,
+    pub age_weeks: f64,
+    pub weight_grams: f64,
+    pub notes: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mutation {
+    // woody breast, a heavier chicken noted as WOODY
+    Woody,
+}
+
+impl Mutation {
+    pub fn apply(&self, chicken: &mut Chicken, rand: &mut PyRandom) {
+        match self {
+            Mutation::Woody => {
+                chicken.notes = String::from("WOODY");
+                chicken.weight_grams += rand.uniform(300.0, 600.0);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChickenFarm {
+    pub farm_name: String,
+    pub sexes: Vec<Sex>,
+    pub min_age_weeks: u32,
+    pub max_age_weeks: u32,
+    pub mutation: Option<Mutation>,
+}
+
+impl ChickenFarm {
+    // a farm of chickens of both sexes and any age
+    pub fn new(farm_name: &str) -> ChickenFarm {
+        ChickenFarm {
+            farm_name: farm_name.to_string(),
+            sexes: Sex::ALL.to_vec(),
+            min_age_weeks: MIN_AGE_WEEKS,
+            max_age_weeks: MAX_AGE_WEEKS,
+            mutation: None,
+        }
+    }
+
+    // a chicken of the farm, the same for the same seed
+    pub fn generate_chicken(&self, identifier: i32, seed: u64) -> Chicken {
+        let mut rand = PyRandom::new(seed);
+        let sex = *rand.choice(&self.sexes);
+        let age_weeks = round2(rand.uniform(self.min_age_weeks as f64, self.max_age_weeks as f64));
+        let (weight_model, weight_grams) = weight_grams(sex, age_weeks, &mut rand);
+        let mut chicken = Chicken {
+            identifier,
+            farm_name: self.farm_name.clone(),
+            weight_model,
+            sex,
+            age_weeks,
+            weight_grams,
+            notes: String::new(),
+        };
+        if let Some(mutation) = self.mutation {
+            mutation.apply(&mut chicken, &mut rand);
+        }
+        chicken
+    }
+}
@@ -0,0 +1,564 @@
+use std::borrow::Borrow;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use db721_parser::staging::tmp_path;
+use db721_parser::{ColumnType, DeleteVector, FileLock, ScalarValue, Staged, WriteError, Writer};
+
+use crate::farm::{Chicken, ChickenFarm, Mutation, Sex};
+use crate::random::PyRandom;
+
+/*
+Generates the data of the ChickenFarm benchmark, the same as chicken_farm_gen.py for
+the same seed:
+
+    data-farms.db721        table Farm: farm_name, sexes, min_age_weeks, max_age_weeks
+    data-chickens.db721     table Chicken: identifier, farm_name, weight_model, sex,
+                            age_weeks, weight_g, notes
+    data-farms.csv          the farms, without sexes
+    data-chickens.csv       the chickens
+
+The chickens are generated in runs, each from a set of farms, so that consecutive
+blocks benefit differently from optimizations such as predicate pushdown. Every chicken
+is drawn from its own generator, seeded with the seed plus its identifier; the farm of
+each is picked by a generator seeded with the seed.
+
+The layout orders the chickens before they are written, to measure pruning across
+orders of the same data:
+
+    clustered       in the order of the runs, the default
+    random          shuffled, by the generator that picked the farms
+    sorted:COLUMN   by the values of a column of data-chickens, e.g. sorted:weight_g
+
+Only random and sorted hold all chickens in memory. Clustered chickens are generated
+anew for every column, a block at a time: the seeds make every pass the same.
+*/
+
+pub const SEED: u64 = 15721;
+pub const MAX_VALUES_PER_BLOCK: u32 = 50000;
+
+#[derive(Debug)]
+pub enum GenerateError {
+    IoError(std::io::Error),
+    WriteError(WriteError),
+    CsvError(csv::Error),
+    // e.g. a run of an unknown farm
+    ConfigError(String),
+}
+
+impl From<std::io::Error> for GenerateError {
+    fn from(error: std::io::Error) -> Self {
+        GenerateError::IoError(error)
+    }
+}
+
+impl From<WriteError> for GenerateError {
+    fn from(error: WriteError) -> Self {
+        GenerateError::WriteError(error)
+    }
+}
+
+impl From<csv::Error> for GenerateError {
+    fn from(error: csv::Error) -> Self {
+        GenerateError::CsvError(error)
+    }
+}
+
+impl std::fmt::Display for GenerateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GenerateError::IoError(e) => write!(f, "DB721|IO: {}", e),
+            GenerateError::WriteError(e) => write!(f, "{}", e),
+            GenerateError::CsvError(e) => write!(f, "DB721|CSV: {}", e),
+            GenerateError::ConfigError(e) => write!(f, "DB721|Config: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for GenerateError {}
+
+// the farms of the benchmark
+pub fn farms() -> Vec<ChickenFarm> {
+    vec![
+        ChickenFarm {
+            max_age_weeks: 2,
+            ..ChickenFarm::new("Incubator")
+        },
+        ChickenFarm {
+            sexes: vec![Sex::Female],
+            min_age_weeks: 4 * 6,
+            max_age_weeks: 52 * 3,
+            ..ChickenFarm::new("Eggscellent")
+        },
+        ChickenFarm {
+            sexes: vec![Sex::Female],
+            min_age_weeks: 52,
+            max_age_weeks: 52 * 3,
+            ..ChickenFarm::new("Eggstraordinaire")
+        },
+        ChickenFarm {
+            max_age_weeks: 6,
+            ..ChickenFarm::new("Breakfast Lunch Dinner")
+        },
+        ChickenFarm {
+            sexes: vec![Sex::Male],
+            max_age_weeks: 8,
+            ..ChickenFarm::new("Dish of the Day")
+        },
+        ChickenFarm {
+            max_age_weeks: 6,
+            mutation: Some(Mutation::Woody),
+            ..ChickenFarm::new("Cheep Birds")
+        },
+    ]
+}
+
+// Chickens from farms picked at random, `num_chickens` of them at scale factor 1
+#[derive(Debug, Clone, PartialEq)]
+pub struct Run {
+    pub num_chickens: u64,
+    // names of the farms
+    pub farms: Vec<String>,
+}
+
+impl Run {
+    pub fn new(num_chickens: u64, farms: &[&str]) -> Run {
+        Run {
+            num_chickens,
+            farms: farms.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    // parse "30000:Eggscellent,Eggstraordinaire"
+    pub fn parse(text: &str) -> Option<Run> {
+        let (num, farms) = text.split_once(':')?;
+        let farms: Vec<&str> = farms.split(',').map(|f| f.trim()).collect();
+        if farms.iter().any(|f| f.is_empty()) {
+            return None;
+        }
+        Some(Run::new(num.trim().parse().ok()?, &farms))
+    }
+}
+
+// the runs of the benchmark
+pub fn runs() -> Vec<Run> {
+    vec![
+        Run::new(50000, &["Cheep Birds"]),
+        Run::new(30000, &["Eggscellent", "Eggstraordinaire"]),
+        Run::new(
+            30000,
+            &[
+                "Breakfast Lunch Dinner",
+                "Dish of the Day",
+                "Cheep Birds",
+                "Eggscellent",
+            ],
+        ),
+        Run::new(10000, &["Incubator"]),
+    ]
+}
+
+// the columns of data-chickens.db721, in order
+pub const CHICKEN_COLUMNS: [&str; 7] = [
+    "identifier",
+    "farm_name",
+    "weight_model",
+    "sex",
+    "age_weeks",
+    "weight_g",
+    "notes",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Layout {
+    #[default]
+    Clustered,
+    Random,
+    // by one of CHICKEN_COLUMNS
+    Sorted(String),
+}
+
+impl Layout {
+    // parse "clustered", "random" or "sorted:COLUMN"
+    pub fn parse(text: &str) -> Option<Layout> {
+        match text.split_once(':') {
+            None if text == "clustered" => Some(Layout::Clustered),
+            None if text == "random" => Some(Layout::Random),
+            Some(("sorted", column)) if CHICKEN_COLUMNS.contains(&column) => {
+                Some(Layout::Sorted(column.to_string()))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Chicken {
+    // compare by one of CHICKEN_COLUMNS, by weight model name and sex name for those
+    pub fn compare_by(&self, other: &Chicken, column: &str) -> Ordering {
+        match column {
+            "identifier" => self.identifier.cmp(&other.identifier),
+            "farm_name" => self.farm_name.cmp(&other.farm_name),
+            "weight_model" => self.weight_model.name().cmp(other.weight_model.name()),
+            "sex" => self.sex.name().cmp(other.sex.name()),
+            "age_weeks" => self.age_weeks.total_cmp(&other.age_weeks),
+            "weight_g" => self.weight_grams.total_cmp(&other.weight_grams),
+            "notes" => self.notes.cmp(&other.notes),
+            _ => Ordering::Equal,
+        }
+    }
+}
+
+// how Python prints a float, 2.0 rather than 2
+pub fn python_float(value: f64) -> String {
+    let text = value.to_string();
+    match text.contains(['.', 'e', 'N', 'i']) {
+        true => text,
+        false => text + ".0",
+    }
+}
+
+// CRLF like Python's csv module
+fn csv_writer<W: Write>(out: W) -> csv::Writer<W> {
+    csv::WriterBuilder::new()
+        .terminator(csv::Terminator::CRLF)
+        .from_writer(out)
+}
+
+pub struct Generator {
+    seed: u64,
+    scale_factor: u64,
+    farms: Vec<ChickenFarm>,
+    runs: Vec<Run>,
+    layout: Layout,
+    max_values_per_block: u32,
+}
+
+impl Default for Generator {
+    fn default() -> Self {
+        Generator::new()
+    }
+}
+
+impl Generator {
+    pub fn new() -> Generator {
+        Generator {
+            seed: SEED,
+            scale_factor: 1,
+            farms: farms(),
+            runs: runs(),
+            layout: Layout::Clustered,
+            max_values_per_block: MAX_VALUES_PER_BLOCK,
+        }
+    }
+
+    pub fn seed(&mut self, seed: u64) -> &mut Self {
+        self.seed = seed;
+        self
+    }
+
+    // multiplies the number of chickens of every run
+    pub fn scale_factor(&mut self, scale_factor: u64) -> &mut Self {
+        self.scale_factor = scale_factor;
+        self
+    }
+
+    // the runs of the chickens, in order, instead of those of the benchmark
+    pub fn runs(&mut self, runs: Vec<Run>) -> &mut Self {
+        self.runs = runs;
+        self
+    }
+
+    pub fn layout(&mut self, layout: Layout) -> &mut Self {
+        self.layout = layout;
+        self
+    }
+
+    pub fn max_values_per_block(&mut self, max_values_per_block: u32) -> &mut Self {
+        self.max_values_per_block = max_values_per_block;
+        self
+    }
+
+    pub fn farms(&self) -> &[ChickenFarm] {
+        &self.farms
+    }
+
+    // the chickens of all runs numbered from 1, in the order of the layout
+    pub fn chickens(&self) -> Result<Vec<Chicken>, GenerateError> {
+        let mut clustered = self.clustered()?;
+        let mut chickens: Vec<_> = clustered.by_ref().collect();
+        match &self.layout {
+            Layout::Clustered => {}
+            Layout::Random => clustered.rand.shuffle(&mut chickens),
+            Layout::Sorted(column) => chickens.sort_by(|a, b| a.compare_by(b, column)),
+        }
+        Ok(chickens)
+    }
+
+    // the chickens of all runs numbered from 1, in the order of the runs
+    pub fn clustered(&self) -> Result<Chickens<'_>, GenerateError> {
+        let mut runs = Vec::with_capacity(self.runs.len());
+        let mut total: u64 = 0;
+        for run in &self.runs {
+            let farms = run
+                .farms
+                .iter()
+                .map(|name| {
+                    self.farms
+                        .iter()
+                        .find(|f| &f.farm_name == name)
+                        .ok_or_else(|| {
+                            GenerateError::ConfigError(format!("unknown farm {:?}", name))
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            if farms.is_empty() {
+                return Err(GenerateError::ConfigError(
+                    "a run without farms".to_string(),
+                ));
+            }
+            let too_many = || {
+                GenerateError::ConfigError(format!(
+                    "more than {} chickens, the identifiers are int",
+                    i32::MAX
+                ))
+            };
+            let num_chickens = run
+                .num_chickens
+                .checked_mul(self.scale_factor)
+                .ok_or_else(too_many)?;
+            total = total
+                .checked_add(num_chickens)
+                .filter(|total| *total <= i32::MAX as u64)
+                .ok_or_else(too_many)?;
+            runs.push((farms, num_chickens));
+        }
+        if self.seed.checked_add(total).is_none() {
+            return Err(GenerateError::ConfigError(format!(
+                "seed {} plus the identifiers of {} chickens overflows",
+                self.seed, total
+            )));
+        }
+        Ok(Chickens {
+            seed: self.seed,
+            rand: PyRandom::new(self.seed),
+            runs: runs.into_iter(),
+            farms: Vec::new(),
+            left: 0,
+            identifier: 0,
+        })
+    }
+
+    pub fn write_farms<W: Write>(&self, out: W) -> Result<W, GenerateError> {
+        let farms = &self.farms;
+        let mut writer = Writer::new(String::from("Farm"), out, self.max_values_per_block);
+        let column = |f: &dyn Fn(&ChickenFarm) -> ScalarValue| -> Vec<ScalarValue> {
+            farms.iter().map(f).collect()
+        };
+        writer.write_col(
+            "farm_name",
+            ColumnType::Str,
+            &column(&|f| ScalarValue::Str(f.farm_name.clone())),
+        )?;
+        writer.write_col(
+            "sexes",
+            ColumnType::StrList,
+            &column(&|f| {
+                ScalarValue::StrList(f.sexes.iter().map(|s| s.name().to_string()).collect())
+            }),
+        )?;
+        writer.write_col(
+            "min_age_weeks",
+            ColumnType::Float,
+            &column(&|f| ScalarValue::Float(f.min_age_weeks as f32)),
+        )?;
+        writer.write_col(
+            "max_age_weeks",
+            ColumnType::Float,
+            &column(&|f| ScalarValue::Float(f.max_age_weeks as f32)),
+        )?;
+        Ok(writer.finalize()?)
+    }
+
+    // the chickens of `chickens`, which is called for every column, so that they can be
+    // generated anew rather than held in memory
+    pub fn write_chickens<W, I>(
+        &self,
+        chickens: impl Fn() -> Result<I, GenerateError>,
+        out: W,
+    ) -> Result<W, GenerateError>
+    where
+        W: Write,
+        I: IntoIterator,
+        I::Item: Borrow<Chicken>,
+    {
+        let mut writer = Writer::new(String::from("Chicken"), out, self.max_values_per_block);
+        // `take(0)` would end every column at once, write_all rejects 0
+        let block_size = (self.max_values_per_block as usize).max(1);
+        let mut column = |name: &str,
+                          ty: ColumnType,
+                          f: &dyn Fn(&Chicken) -> ScalarValue|
+         -> Result<(), GenerateError> {
+            let mut chickens = chickens()?.into_iter();
+            let blocks = std::iter::from_fn(|| {
+                let block: Vec<_> = chickens
+                    .by_ref()
+                    .take(block_size)
+                    .map(|c| f(c.borrow()))
+                    .collect();
+                (!block.is_empty()).then_some(Ok::<_, WriteError>(block))
+            });
+            writer.write_col_blocks(name, ty, blocks)?;
+            Ok(())
+        };
+        let str = |s: &str| ScalarValue::Str(s.to_string());
+        column("identifier", ColumnType::Int, &|c| {
+            ScalarValue::Int(c.identifier)
+        })?;
+        column("farm_name", ColumnType::Str, &|c| str(&c.farm_name))?;
+        column("weight_model", ColumnType::Str, &|c| {
+            str(c.weight_model.name())
+        })?;
+        column("sex", ColumnType::Str, &|c| str(c.sex.name()))?;
+        column("age_weeks", ColumnType::Float, &|c| {
+            ScalarValue::Float(c.age_weeks as f32)
+        })?;
+        column("weight_g", ColumnType::Float, &|c| {
+            ScalarValue::Float(c.weight_grams as f32)
+        })?;
+        column("notes", ColumnType::Str, &|c| str(&c.notes))?;
+        Ok(writer.finalize()?)
+    }
+
+    pub fn write_farms_csv<W: Write>(&self, out: W) -> Result<(), GenerateError> {
+        let mut writer = csv_writer(out);
+        writer.write_record(["Farm Name", "Min Age Weeks", "Max Age Weeks"])?;
+        for farm in &self.farms {
+            writer.write_record([
+                farm.farm_name.clone(),
+                farm.min_age_weeks.to_string(),
+                farm.max_age_weeks.to_string(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn write_chickens_csv<W: Write>(
+        &self,
+        chickens: impl IntoIterator<Item = impl Borrow<Chicken>>,
+        out: W,
+    ) -> Result<(), GenerateError> {
+        let mut writer = csv_writer(out);
+        writer.write_record([
+            "Identifier",
+            "Farm Name",
+            "Weight Model",
+            "Sex",
+            "Age (weeks)",
+            "Weight (g)",
+            "Notes",
+        ])?;
+        for chicken in chickens {
+            let chicken = chicken.borrow();
+            writer.write_record([
+                chicken.identifier.to_string(),
+                chicken.farm_name.clone(),
+                chicken.weight_model.name().to_string(),
+                chicken.sex.name().to_string(),
+                python_float(chicken.age_weeks),
+                python_float(chicken.weight_grams),
+                chicken.notes.clone(),
+            ])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    // write the four files to `dir`, returns their paths. They are written next to
+    // their paths and renamed into place together, with the delete vectors of the
+    // tables they replace removed.
+    pub fn write_all(&self, dir: &Path) -> Result<Vec<PathBuf>, GenerateError> {
+        if self.max_values_per_block == 0 {
+            return Err(GenerateError::ConfigError(
+                "max values per block must be positive".to_string(),
+            ));
+        }
+        std::fs::create_dir_all(dir)?;
+        // validates the runs before any file is created
+        self.clustered()?;
+        let chickens = match self.layout {
+            Layout::Clustered => None,
+            _ => Some(self.chickens()?),
+        };
+        let paths = [
+            "data-farms.db721",
+            "data-chickens.db721",
+            "data-farms.csv",
+            "data-chickens.csv",
+        ]
+        .map(|name| dir.join(name).to_string_lossy().into_owned());
+        let mut staged = Staged::default();
+        let mut create = |path: &str| -> std::io::Result<BufWriter<File>> {
+            let tmp = tmp_path(path);
+            staged.push(tmp.clone(), path.to_string());
+            Ok(BufWriter::new(File::create(tmp)?))
+        };
+        sync(self.write_farms(create(&paths[0])?)?)?;
+        let out = create(&paths[1])?;
+        sync(match &chickens {
+            Some(chickens) => self.write_chickens(|| Ok(chickens), out)?,
+            None => self.write_chickens(|| self.clustered(), out)?,
+        })?;
+        let mut out = create(&paths[2])?;
+        self.write_farms_csv(&mut out)?;
+        sync(out)?;
+        let mut out = create(&paths[3])?;
+        match &chickens {
+            Some(chickens) => self.write_chickens_csv(chickens, &mut out)?,
+            None => self.write_chickens_csv(self.clustered()?, &mut out)?,
+        }
+        sync(out)?;
+        // the rows are new, deletes of the old ones do not apply
+        for path in &paths[..2] {
+            staged.remove(DeleteVector::path(path));
+        }
+        let _locks = FileLock::acquire_all(paths[..2].iter().map(String::as_str))?;
+        staged.publish()?;
+        Ok(paths.into_iter().map(PathBuf::from).collect())
+    }
+}
+
+fn sync(mut out: BufWriter<File>) -> std::io::Result<()> {
+    out.flush()?;
+    out.get_ref().sync_all()
+}
+
+// the chickens of the runs in their order, generated one at a time
+pub struct Chickens<'a> {
+    seed: u64,
+    // picks the farms, then shuffles the random layout
+    rand: PyRandom,
+    // the farms of every run left and its number of chickens
+    runs: std::vec::IntoIter<(Vec<&'a ChickenFarm>, u64)>,
+    farms: Vec<&'a ChickenFarm>,
+    left: u64,
+    // of the last chicken, `Generator::clustered` checked that the last fits
+    identifier: i32,
+}
+
+impl Iterator for Chickens<'_> {
+    type Item = Chicken;
+
+    fn next(&mut self) -> Option<Chicken> {
+        while self.left == 0 {
+            (self.farms, self.left) = self.runs.next()?;
+        }
+        self.left -= 1;
+        self.identifier += 1;
+        let farm = self.rand.choice(&self.farms);
+        let seed = self.seed + self.identifier as u64;
+        Some(farm.generate_chicken(self.identifier, seed))
+    }
+}
@@ -1,14 +1,155 @@
-pub fn add(left: usize, right: usize) -> usize {
-    left + right
-}
+pub mod farm;
+pub mod generate;
+pub mod random;
+
+pub use farm::{Chicken, ChickenFarm, Mutation, Sex, WeightModel};
+pub use generate::{farms, runs, Chickens, GenerateError, Generator, Layout, Run, CHICKEN_COLUMNS};
+pub use random::PyRandom;
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use db721_parser::{delete_rows, DeleteVector, ParserBuilder};
+    use db721_testing::TmpPath;
+
+    #[test]
+    fn it_matches_python_random() {
+        // random.Random(15721).random(), .uniform(0, 445) and .choice(range(6)) x3
+        let mut rand = PyRandom::new(15721);
+        assert_eq!(rand.random(), 0.7490809338198078);
+        assert_eq!(rand.uniform(0.0, 445.0), 256.49181979241223);
+        let choices: Vec<_> = (0..3).map(|_| *rand.choice(&[0, 1, 2, 3, 4, 5])).collect();
+        assert_eq!(choices, vec![3, 1, 4]);
+    }
+
+    #[test]
+    fn it_generates_the_python_data() {
+        // as in data-chickens.csv of chicken_farm_gen.py
+        let generator = Generator::new();
+        let chickens = generator.chickens().unwrap();
+        assert_eq!(chickens.len(), 120000);
+        let mut csv = Vec::new();
+        generator
+            .write_chickens_csv(&chickens[..1], &mut csv)
+            .unwrap();
+        assert!(String::from_utf8(csv)
+            .unwrap()
+            .ends_with("\r\n1,Cheep Birds,MMF,FEMALE,5.65,2760.2077844478986,WOODY\r\n"));
+        let chicken = &chickens[50000];
+        assert_eq!(
+            (
+                chicken.farm_name.as_str(),
+                chicken.weight_model,
+                chicken.age_weeks
+            ),
+            ("Eggscellent", WeightModel::Rng, 102.44)
+        );
+        assert_eq!(chickens[119999].weight_grams, 446.79);
+
+        let mut csv = Vec::new();
+        generator.write_farms_csv(&mut csv).unwrap();
+        let path = format!("{}/data-farms.csv", env!("CARGO_MANIFEST_DIR"));
+        assert_eq!(csv, std::fs::read(path).unwrap());
+    }
+
+    #[test]
+    fn it_lays_out_runs() {
+        let mut generator = Generator::new();
+        generator.seed(7).scale_factor(2).runs(vec![
+            Run::parse("5:Incubator").unwrap(),
+            Run::parse("5: Eggscellent, Eggstraordinaire").unwrap(),
+        ]);
+        let clustered = generator.chickens().unwrap();
+        assert_eq!(clustered.len(), 20);
+        assert!(clustered[..10].iter().all(|c| c.farm_name == "Incubator"));
+        assert_eq!(clustered[19].identifier, 20);
+
+        generator.layout(Layout::Random);
+        let random = generator.chickens().unwrap();
+        assert_ne!(random, clustered);
+        let mut ids: Vec<_> = random.iter().map(|c| c.identifier).collect();
+        ids.sort();
+        assert_eq!(ids, (1..=20).collect::<Vec<_>>());
+
+        generator.layout(Layout::parse("sorted:weight_g").unwrap());
+        let sorted = generator.chickens().unwrap();
+        assert!(sorted
+            .windows(2)
+            .all(|w| w[0].weight_grams <= w[1].weight_grams));
+        assert_eq!(Layout::parse("sorted:color"), None);
+
+        // the clustered chickens generated anew for every column are the same
+        generator.layout(Layout::Clustered).max_values_per_block(3);
+        let (streamed, held) = (TmpPath::file("streamed"), TmpPath::file("held"));
+        let write = |path: &str, data: Vec<u8>| {
+            std::fs::write(path, data).unwrap();
+            ParserBuilder::new(path.to_string(), String::from("Chicken"))
+                .build()
+                .unwrap()
+        };
+        let streamed = write(
+            &streamed,
+            generator
+                .write_chickens(|| generator.clustered(), Vec::new())
+                .unwrap(),
+        );
+        let held = write(
+            &held,
+            generator
+                .write_chickens(|| Ok(&clustered), Vec::new())
+                .unwrap(),
+        );
+        assert_eq!(streamed.num_blocks(), 7);
+        for column in CHICKEN_COLUMNS {
+            for block in 0..7 {
+                assert_eq!(
+                    streamed.read_block(column, block).unwrap(),
+                    held.read_block(column, block).unwrap()
+                );
+            }
+        }
+
+        generator.runs(vec![Run::new(1, &["Nowhere"])]);
+        assert!(matches!(
+            generator.chickens(),
+            Err(GenerateError::ConfigError(_))
+        ));
+        // the identifiers are int
+        let max = i32::MAX as u64;
+        for (runs, scale_factor) in [
+            (vec![Run::new(max / 2 + 1, &["Incubator"])], 2),
+            (vec![Run::new(u64::MAX / 2 + 1, &["Incubator"])], 2),
+            (
+                vec![Run::new(max, &["Incubator"]), Run::new(1, &["Incubator"])],
+                1,
+            ),
+        ] {
+            generator.runs(runs).scale_factor(scale_factor);
+            assert!(matches!(
+                generator.clustered(),
+                Err(GenerateError::ConfigError(_))
+            ));
+        }
+        generator.runs(vec![Run::new(max, &["Incubator"])]);
+        assert_eq!(generator.clustered().unwrap().nth(2).unwrap().identifier, 3);
+        generator.seed(u64::MAX);
+        assert!(generator.clustered().is_err());
+    }
 
     #[test]
-    fn it_works() {
-        let result = add(2, 2);
-        assert_eq!(result, 4);
+    fn it_replaces_tables_with_deletes() {
+        let dir = TmpPath::dir("gen");
+        let mut generator = Generator::new();
+        generator.runs(vec![Run::new(10, &["Incubator"])]);
+        generator.write_all(dir.as_ref()).unwrap();
+        let chickens = dir.join("data-chickens.db721");
+        delete_rows(&chickens, &[0, 1]).unwrap();
+        // the new rows do not take the deletes of the old ones
+        generator.seed(7).write_all(dir.as_ref()).unwrap();
+        assert!(!std::path::Path::new(&DeleteVector::path(&chickens)).exists());
+        let parser = ParserBuilder::new(chickens, String::from("Chicken"))
+            .build()
+            .unwrap();
+        assert_eq!((parser.num_rows(), parser.num_live_rows()), (10, 10));
     }
 }
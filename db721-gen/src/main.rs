@@ -0,0 +1,74 @@
+use clap::Parser as CliParser;
+use db721_gen::{Generator, Layout, Run};
+use std::path::PathBuf;
+use std::process::ExitCode;
+
+/* Generate the ChickenFarm data, data-{farms,chickens}.{db721,csv}:
+
+    db721-gen               into the current directory
+    db721-gen /tmp/gen --scale-factor 10 --layout sorted:weight_g
+    db721-gen /tmp/gen --run 100000:Incubator --run "20000:Eggscellent,Cheep Birds" \
+        --layout random --max-values-per-block 10000
+*/
+
+#[derive(CliParser)]
+#[command(name = "db721-gen", version, about = "Generate the ChickenFarm data")]
+struct Cli {
+    /// Directory of the generated files, created if missing
+    #[arg(default_value = ".")]
+    dir: PathBuf,
+    /// Multiplies the number of chickens of every run
+    #[arg(long, default_value_t = 1)]
+    scale_factor: u64,
+    #[arg(long, default_value_t = db721_gen::generate::SEED)]
+    seed: u64,
+    #[arg(long, default_value_t = db721_gen::generate::MAX_VALUES_PER_BLOCK)]
+    max_values_per_block: u32,
+    /// A run of chickens from farms picked at random, like
+    /// "30000:Eggscellent,Eggstraordinaire". Repeat for more runs, the runs of the
+    /// benchmark by default
+    #[arg(long = "run", value_parser = parse_run)]
+    runs: Vec<Run>,
+    /// Order of the chickens: clustered (by run), random or sorted:COLUMN
+    #[arg(long, default_value = "clustered", value_parser = parse_layout)]
+    layout: Layout,
+}
+
+fn parse_run(text: &str) -> Result<Run, String> {
+    Run::parse(text).ok_or_else(|| format!("expected COUNT:FARM,FARM..., found {:?}", text))
+}
+
+fn parse_layout(text: &str) -> Result<Layout, String> {
+    Layout::parse(text).ok_or_else(|| {
+        format!(
+            "expected clustered, random or sorted:COLUMN with a column of {}",
+            db721_gen::CHICKEN_COLUMNS.join(", ")
+        )
+    })
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let mut generator = Generator::new();
+    generator
+        .scale_factor(cli.scale_factor)
+        .seed(cli.seed)
+        .max_values_per_block(cli.max_values_per_block)
+        .layout(cli.layout.clone());
+    if !cli.runs.is_empty() {
+        generator.runs(cli.runs.clone());
+    }
+    match generator.write_all(&cli.dir) {
+        Ok(paths) => {
+            for path in paths {
+                let bytes = std::fs::metadata(&path).map_or(0, |m| m.len());
+                eprintln!("Wrote {} bytes to '{}'.", bytes, path.display());
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("db721-gen: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
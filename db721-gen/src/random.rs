@@ -0,0 +1,129 @@
+/*
+The Mersenne Twister (MT19937) as seeded and used by Python's `random.Random`, so that
+a seed yields the same chickens as chicken_farm_gen.py:
+
+    Random(seed)            init_by_array with the 32-bit words of the seed
+    random()                53 random bits from two outputs, in [0, 1)
+    uniform(a, b)           a + (b - a) * random()
+    choice(seq)             seq[randbelow(len)], where randbelow draws bit_length(len)
+                            bits until the result is below len
+    shuffle(seq)            Fisher-Yates from the back, swapping seq[i] with
+                            seq[randbelow(i + 1)]
+*/
+
+const N: usize = 624;
+const M: usize = 397;
+const MATRIX_A: u32 = 0x9908_b0df;
+const UPPER_MASK: u32 = 0x8000_0000;
+const LOWER_MASK: u32 = 0x7fff_ffff;
+
+#[derive(Clone)]
+pub struct PyRandom {
+    mt: [u32; N],
+    mti: usize,
+}
+
+impl PyRandom {
+    pub fn new(seed: u64) -> PyRandom {
+        // the words of the seed, least significant first, at least one
+        let key: Vec<u32> = match seed >> 32 {
+            0 => vec![seed as u32],
+            high => vec![seed as u32, high as u32],
+        };
+        let mut random = PyRandom { mt: [0; N], mti: N };
+        random.init_by_array(&key);
+        random
+    }
+
+    fn init_genrand(&mut self, seed: u32) {
+        self.mt[0] = seed;
+        for i in 1..N {
+            let prev = self.mt[i - 1];
+            self.mt[i] = 1_812_433_253u32
+                .wrapping_mul(prev ^ (prev >> 30))
+                .wrapping_add(i as u32);
+        }
+        self.mti = N;
+    }
+
+    fn init_by_array(&mut self, key: &[u32]) {
+        self.init_genrand(19_650_218);
+        let (mut i, mut j) = (1, 0);
+        for _ in 0..N.max(key.len()) {
+            let prev = self.mt[i - 1];
+            self.mt[i] = (self.mt[i] ^ (prev ^ (prev >> 30)).wrapping_mul(1_664_525))
+                .wrapping_add(key[j])
+                .wrapping_add(j as u32);
+            i += 1;
+            j += 1;
+            if i >= N {
+                self.mt[0] = self.mt[N - 1];
+                i = 1;
+            }
+            if j >= key.len() {
+                j = 0;
+            }
+        }
+        for _ in 0..N - 1 {
+            let prev = self.mt[i - 1];
+            self.mt[i] = (self.mt[i] ^ (prev ^ (prev >> 30)).wrapping_mul(1_566_083_941))
+                .wrapping_sub(i as u32);
+            i += 1;
+            if i >= N {
+                self.mt[0] = self.mt[N - 1];
+                i = 1;
+            }
+        }
+        self.mt[0] = 0x8000_0000;
+    }
+
+    fn next_u32(&mut self) -> u32 {
+        if self.mti >= N {
+            for k in 0..N {
+                let y = (self.mt[k] & UPPER_MASK) | (self.mt[(k + 1) % N] & LOWER_MASK);
+                let mag = if y & 1 == 0 { 0 } else { MATRIX_A };
+                self.mt[k] = self.mt[(k + M) % N] ^ (y >> 1) ^ mag;
+            }
+            self.mti = 0;
+        }
+        let mut y = self.mt[self.mti];
+        self.mti += 1;
+        y ^= y >> 11;
+        y ^= (y << 7) & 0x9d2c_5680;
+        y ^= (y << 15) & 0xefc6_0000;
+        y ^ (y >> 18)
+    }
+
+    // a float in [0, 1)
+    pub fn random(&mut self) -> f64 {
+        let a = (self.next_u32() >> 5) as f64;
+        let b = (self.next_u32() >> 6) as f64;
+        (a * 67_108_864.0 + b) * (1.0 / 9_007_199_254_740_992.0)
+    }
+
+    pub fn uniform(&mut self, a: f64, b: f64) -> f64 {
+        a + (b - a) * self.random()
+    }
+
+    // an integer in [0, n), n must be below 2^32
+    fn randbelow(&mut self, n: usize) -> usize {
+        let bits = usize::BITS - n.leading_zeros();
+        loop {
+            let r = (self.next_u32() >> (32 - bits)) as usize;
+            if r < n {
+                return r;
+            }
+        }
+    }
+
+    pub fn choice<'a, T>(&mut self, items: &'a [T]) -> &'a T {
+        &items[self.randbelow(items.len())]
+    }
+
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            let j = self.randbelow(i + 1);
+            items.swap(i, j);
+        }
+    }
+}
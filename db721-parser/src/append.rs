@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::dataset::{escape, Dataset};
+use crate::deletes::DeleteVector;
+use crate::parser::{BuildError, Metadata, Parser, ParserBuilder};
+use crate::staging::{self, tmp_path, FileLock, Staged};
+use crate::types::{ColumnType, ScalarValue};
+use crate::writer::{WriteError, Writer};
+
+/*
+Rows appended to a table, buffered until `Append::commit` writes them:
+
+    file        the file is rewritten with its blocks followed by blocks of the new rows,
+                while holding its lock. db721 keeps the blocks of a column together, new
+                blocks cannot go after the old file, so every commit copies the whole
+                file: a table taking many small INSERTs belongs in a directory.
+    directory   the rows are written as a new file `part-<id>.db721` of the directory, or
+                of the `key=value` directories of their partition values
+
+Every file is staged before any of them is published, and a publish that fails is
+undone, one that crashes by the next writer, see `staging`. A reader opening the files
+of a directory while they are published may still find some of the new files and not
+others. `Append::stage`
+leaves publishing to the caller, so that the changes to several tables go together.
+The stats of the new blocks are computed by the `Writer`, which is set up like the file,
+or the first file of the directory: the same checksum and, per column, compression and
+bloom filters. The encoding of every block is chosen anew.
+
+The columns of a directory are those of its dataset, of a directory without files those
+of the first row, typed by its values. db721 has no NULLs, every row needs a value for every column.
+*/
+
+#[derive(Debug)]
+pub enum AppendError {
+    IoError(std::io::Error),
+    BuildError(BuildError),
+    WriteError(WriteError),
+    // column, why the row cannot be appended
+    RowError(String, String),
+}
+
+impl From<std::io::Error> for AppendError {
+    fn from(error: std::io::Error) -> Self {
+        AppendError::IoError(error)
+    }
+}
+
+impl From<BuildError> for AppendError {
+    fn from(error: BuildError) -> Self {
+        AppendError::BuildError(error)
+    }
+}
+
+impl From<WriteError> for AppendError {
+    fn from(error: WriteError) -> Self {
+        AppendError::WriteError(error)
+    }
+}
+
+impl std::fmt::Display for AppendError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AppendError::IoError(e) => write!(f, "DB721|IO: {}", e),
+            AppendError::BuildError(e) => write!(f, "{}", e),
+            AppendError::WriteError(e) => write!(f, "{}", e),
+            AppendError::RowError(c, e) => write!(f, "DB721|Column: column {} {}", c, e),
+        }
+    }
+}
+
+impl std::error::Error for AppendError {}
+
+enum Target {
+    File(String),
+    Directory(String),
+}
+
+pub struct Append {
+    target: Target,
+    tablename: String,
+    columns: Vec<(String, ColumnType)>,
+    partition_columns: Vec<String>,
+    max_values_per_block: u32,
+    // the metadata of the file the new files are written like
+    template: Option<Metadata>,
+    // the values of `columns` followed by those of `partition_columns`
+    rows: Vec<Vec<ScalarValue>>,
+}
+
+impl Append {
+    // rows appended to the blocks of the file
+    pub fn file(filename: &str, parser: &Parser) -> Append {
+        Append {
+            target: Target::File(filename.to_string()),
+            tablename: parser.metadata.table.clone(),
+            columns: columns(parser),
+            partition_columns: Vec::new(),
+            max_values_per_block: parser.metadata.max_values_per_block,
+            template: Some(parser.metadata.clone()),
+            rows: Vec::new(),
+        }
+    }
+
+    // rows written as new files of the dataset read from the directory
+    pub fn directory(directory: &str, dataset: &Dataset, max_values_per_block: u32) -> Append {
+        let first = dataset.files.first();
+        Append {
+            target: Target::Directory(directory.trim_end_matches('/').to_string()),
+            tablename: match first {
+                Some(file) => file.metadata.table.clone(),
+                None => dataset.tablename.clone(),
+            },
+            // merged over the files under `SchemaPolicy::Merge`
+            columns: dataset.columns.clone(),
+            partition_columns: dataset.partition_columns.clone(),
+            max_values_per_block: first
+                .map_or(max_values_per_block, |f| f.metadata.max_values_per_block),
+            template: first.map(|f| f.metadata.clone()),
+            rows: Vec::new(),
+        }
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.rows.len()
+    }
+
+    // buffer a row given as the values of the named columns, None for NULL
+    pub fn push(
+        &mut self,
+        names: &[String],
+        values: &[Option<ScalarValue>],
+    ) -> Result<(), AppendError> {
+        let named = |column: &str| -> Result<&ScalarValue, AppendError> {
+            match names.iter().position(|n| n == column) {
+                Some(i) => values[i].as_ref().ok_or_else(|| {
+                    AppendError::RowError(column.to_string(), "cannot be NULL".to_string())
+                }),
+                None => Err(AppendError::RowError(
+                    column.to_string(),
+                    "has no value".to_string(),
+                )),
+            }
+        };
+        if let Some(unknown) = names.iter().find(|n| {
+            !self.partition_columns.contains(n)
+                && !self.columns.is_empty()
+                && !self.columns.iter().any(|(c, _)| c == *n)
+        }) {
+            return Err(AppendError::RowError(
+                unknown.clone(),
+                format!("does not exist in table {}", self.tablename),
+            ));
+        }
+        if self.columns.is_empty() {
+            // the first row of a directory without files
+            for name in names.iter().filter(|n| !self.partition_columns.contains(n)) {
+                let ty = named(name)?.column_type();
+                self.columns.push((name.clone(), ty));
+            }
+        }
+        let mut row = Vec::with_capacity(self.columns.len() + self.partition_columns.len());
+        for (name, ty) in &self.columns {
+            let value = named(name)?;
+            row.push(value.cast(*ty).ok_or_else(|| {
+                AppendError::WriteError(WriteError::TypeMismatch(name.clone(), *ty, value.clone()))
+            })?);
+        }
+        for name in &self.partition_columns {
+            row.push(named(name)?.clone());
+        }
+        self.rows.push(row);
+        Ok(())
+    }
+
+    // the file the rows are appended to, which the caller of `stage` locks
+    pub fn filename(&self) -> Option<&str> {
+        match &self.target {
+            Target::File(filename) => Some(filename),
+            Target::Directory(_) => None,
+        }
+    }
+
+    // write the rows to temporary files, `id` names the new files of a directory
+    pub fn stage(&self, id: &str) -> Result<Staged, AppendError> {
+        let mut staged = Staged::default();
+        if !self.rows.is_empty() {
+            self.write_all(id, &mut staged)?;
+        }
+        Ok(staged)
+    }
+
+    // lock, stage and publish the rows. Returns the files written.
+    pub fn commit(self, id: &str) -> Result<Vec<String>, AppendError> {
+        let _lock = match self.filename() {
+            Some(filename) => Some(FileLock::acquire(filename)?),
+            None => None,
+        };
+        Ok(self.stage(id)?.publish()?)
+    }
+
+    fn write_all(&self, id: &str, staged: &mut Staged) -> Result<(), AppendError> {
+        match &self.target {
+            Target::File(filename) => {
+                let parser =
+                    ParserBuilder::new(filename.clone(), self.tablename.clone()).build()?;
+                let tmp = tmp_path(filename);
+                let mut data = Staged::default();
+                data.push(tmp.clone(), filename.clone());
+                let rows: Vec<_> = self.rows.iter().collect();
+                self.write_file(&tmp, Some(&parser), &rows)?;
+                // the deleted rows keep their ids, the sidecar goes first with the
+                // deletes of both versions
+                if !parser.deletes.is_empty() {
+                    let new = ParserBuilder::new(tmp, self.tablename.clone()).build()?;
+                    let deletes = &parser.deletes;
+                    let versions = [(parser.identity, deletes), (new.identity, deletes)];
+                    staged.extend(DeleteVector::stage(filename, &versions)?);
+                }
+                staged.extend(data);
+                Ok(())
+            }
+            Target::Directory(directory) => {
+                // the files of a publish that crashed, no lock covers new files
+                staging::recover_dir(Path::new(directory))?;
+                // the rows of every partition
+                let mut partitions: BTreeMap<Vec<String>, Vec<&Vec<ScalarValue>>> = BTreeMap::new();
+                for row in &self.rows {
+                    let values = row[self.columns.len()..].iter().map(|v| v.to_string());
+                    partitions.entry(values.collect()).or_default().push(row);
+                }
+                for (values, rows) in partitions {
+                    let mut dir = directory.clone();
+                    for (key, value) in self.partition_columns.iter().zip(&values) {
+                        dir = format!("{}/{}={}", dir, escape(key), escape(value));
+                    }
+                    std::fs::create_dir_all(&dir)?;
+                    let path = format!("{}/part-{}.db721", dir, id);
+                    if Path::new(&path).exists() {
+                        return Err(AppendError::IoError(std::io::Error::new(
+                            std::io::ErrorKind::AlreadyExists,
+                            format!("{} already exists", path),
+                        )));
+                    }
+                    let tmp = tmp_path(&path);
+                    staged.push(tmp.clone(), path);
+                    self.write_file(&tmp, None, &rows)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // the blocks of `parser` if any, then the rows
+    fn write_file(
+        &self,
+        path: &str,
+        parser: Option<&Parser>,
+        rows: &[&Vec<ScalarValue>],
+    ) -> Result<(), AppendError> {
+        let file = File::create(path)?;
+        let mut writer = Writer::new(
+            self.tablename.clone(),
+            BufWriter::new(file),
+            self.max_values_per_block,
+        );
+        if let Some(template) = &self.template {
+            writer.checksum(template.checksum);
+        }
+        for (i, (name, ty)) in self.columns.iter().enumerate() {
+            if let Some(column) = self.template.as_ref().and_then(|t| t.columns.get(name)) {
+                writer
+                    .compression(column.compression.unwrap_or_default())
+                    .bloom_filter(column.bloom_filter.is_some());
+            }
+            // write_col_blocks only passes on WriteErrors, others are kept here
+            let mut failed = None;
+            let existing = parser.map_or(0, |p| p.num_blocks());
+            let blocks = (0..existing)
+                .map_while(|block| match parser.unwrap().read_block(name, block) {
+                    Ok(values) => Some(Ok::<_, WriteError>(
+                        (0..values.len())
+                            .map(|row| values.get(row).unwrap())
+                            .collect(),
+                    )),
+                    Err(e) => {
+                        failed = Some(e);
+                        None
+                    }
+                })
+                .chain(std::iter::once(Ok(rows
+                    .iter()
+                    .map(|row| row[i].clone())
+                    .collect())));
+            writer.write_col_blocks::<Vec<_>, _>(name, *ty, blocks)?;
+            if let Some(e) = failed {
+                return Err(e.into());
+            }
+        }
+        let mut out = writer.finalize()?;
+        out.flush()?;
+        out.get_ref().sync_all()?;
+        Ok(())
+    }
+}
+
+fn columns(parser: &Parser) -> Vec<(String, ColumnType)> {
+    parser
+        .columns
+        .iter()
+        .map(|c| (c.column_name.clone(), c.column_type))
+        .collect()
+}
@@ -0,0 +1,197 @@
+use std::ops::Range;
+use std::ptr::NonNull;
+use std::sync::Arc;
+
+use arrow_array::builder::{ListBuilder, StringBuilder};
+use arrow_array::types::{
+    ArrowPrimitiveType, Date32Type, Float32Type, Float64Type, Int32Type, Int64Type,
+    TimestampMicrosecondType,
+};
+use arrow_array::{
+    ArrayRef, BooleanArray, PrimitiveArray, RecordBatch, RecordBatchOptions, StringArray,
+};
+use arrow_buffer::{Buffer, ScalarBuffer};
+use arrow_schema::{DataType, Field, Schema, SchemaRef, TimeUnit};
+use arrow_select::filter::filter_record_batch;
+
+use crate::encoding::Encoding;
+use crate::parser::{BuildError, Parser};
+use crate::types::{ColumnBlock, ColumnType};
+
+/*
+Arrow export, behind the `arrow` feature. Every block is one RecordBatch.
+
+    db721           Arrow
+    "int"           Int32
+    "float"         Float32
+    "str"           Utf8
+    "bigint"        Int64
+    "double"        Float64
+    "bool"          Boolean
+    "date"          Date32
+    "timestamp"     Timestamp(Microsecond, "UTC")
+    "list[str]"     List(Utf8)
+
+None of the columns are nullable. Deleted rows are left out of the batches. Plain,
+uncompressed blocks of the fixed-width numeric types are not copied: their arrays
+point into the mapped file, which they keep alive. Other blocks are decoded first.
+*/
+
+pub fn arrow_type(ty: ColumnType) -> DataType {
+    match ty {
+        ColumnType::Int => DataType::Int32,
+        ColumnType::Float => DataType::Float32,
+        ColumnType::Str => DataType::Utf8,
+        ColumnType::BigInt => DataType::Int64,
+        ColumnType::Double => DataType::Float64,
+        ColumnType::Bool => DataType::Boolean,
+        ColumnType::Date => DataType::Date32,
+        ColumnType::Timestamp => DataType::Timestamp(TimeUnit::Microsecond, Some("UTC".into())),
+        ColumnType::StrList => {
+            DataType::List(Arc::new(Field::new_list_field(DataType::Utf8, true)))
+        }
+    }
+}
+
+fn array<T: ArrowPrimitiveType>(array: PrimitiveArray<T>) -> ArrayRef {
+    Arc::new(array)
+}
+
+fn arrow_error(error: arrow_schema::ArrowError) -> BuildError {
+    BuildError::FormatError(error.to_string())
+}
+
+impl Parser {
+    // the schema of `columns`, of all columns in file order if None
+    pub fn arrow_schema(&self, columns: Option<&[String]>) -> Result<SchemaRef, BuildError> {
+        let fields = match columns {
+            Some(columns) => columns
+                .iter()
+                .map(|name| {
+                    self.column_meta(name)
+                        .ok_or_else(|| BuildError::ColumnNotFound(name.clone()))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            None => self.columns.iter().collect(),
+        }
+        .into_iter()
+        .map(|meta| Field::new(&meta.column_name, arrow_type(meta.column_type), false))
+        .collect::<Vec<_>>();
+        let metadata = [("db721.table".to_string(), self.metadata.table.clone())];
+        Ok(Arc::new(Schema::new_with_metadata(fields, metadata.into())))
+    }
+
+    // one block of a column as an array
+    pub fn arrow_array(&self, name: &str, block: usize) -> Result<ArrayRef, BuildError> {
+        let meta = self
+            .column_meta(name)
+            .ok_or_else(|| BuildError::ColumnNotFound(name.to_string()))?;
+        if block >= self.num_blocks() {
+            return Err(BuildError::BlockNotFound(name.to_string(), block));
+        }
+        let num = self.block_len(block);
+        if meta.block_encodings.get(block) == Some(&Encoding::Plain) {
+            let zero_copy = match meta.column_type {
+                ColumnType::Int => self.zero_copy::<Int32Type>(name, block, num)?.map(array),
+                ColumnType::Float => self.zero_copy::<Float32Type>(name, block, num)?.map(array),
+                ColumnType::BigInt => self.zero_copy::<Int64Type>(name, block, num)?.map(array),
+                ColumnType::Double => self.zero_copy::<Float64Type>(name, block, num)?.map(array),
+                ColumnType::Date => self.zero_copy::<Date32Type>(name, block, num)?.map(array),
+                ColumnType::Timestamp => self
+                    .zero_copy::<TimestampMicrosecondType>(name, block, num)?
+                    .map(|a| array(a.with_timezone("UTC"))),
+                _ => None,
+            };
+            if let Some(array) = zero_copy {
+                return Ok(array);
+            }
+        }
+        Ok(match self.read_block(name, block)? {
+            ColumnBlock::Int(v) => Arc::new(PrimitiveArray::<Int32Type>::from(v)),
+            ColumnBlock::Float(v) => Arc::new(PrimitiveArray::<Float32Type>::from(v)),
+            ColumnBlock::Str(v) => Arc::new(StringArray::from(v)),
+            ColumnBlock::BigInt(v) => Arc::new(PrimitiveArray::<Int64Type>::from(v)),
+            ColumnBlock::Double(v) => Arc::new(PrimitiveArray::<Float64Type>::from(v)),
+            ColumnBlock::Bool(v) => Arc::new(BooleanArray::from(v)),
+            ColumnBlock::Date(v) => Arc::new(PrimitiveArray::<Date32Type>::from(v)),
+            ColumnBlock::Timestamp(v) => {
+                Arc::new(PrimitiveArray::<TimestampMicrosecondType>::from(v).with_timezone("UTC"))
+            }
+            ColumnBlock::StrList(v) => {
+                let mut builder = ListBuilder::new(StringBuilder::new());
+                for list in v {
+                    list.iter().for_each(|s| builder.values().append_value(s));
+                    builder.append(true);
+                }
+                Arc::new(builder.finish())
+            }
+        })
+    }
+
+    // an array over the mapped block, None if the block is compressed or the values
+    // are not aligned for T
+    fn zero_copy<T: ArrowPrimitiveType>(
+        &self,
+        name: &str,
+        block: usize,
+        num: usize,
+    ) -> Result<Option<PrimitiveArray<T>>, BuildError> {
+        let raw = self.block_raw(name, block)?;
+        let codec = self.column_meta(name).unwrap().block_codecs[block];
+        let len = num * std::mem::size_of::<T::Native>();
+        if !cfg!(target_endian = "little")
+            || codec != crate::codec::Codec::None
+            || raw.len() < len
+            || !(raw.as_ptr() as usize).is_multiple_of(std::mem::align_of::<T::Native>())
+        {
+            return Ok(None);
+        }
+        let owner = self.data.borrow_owner().clone();
+        // the mapping outlives the buffer, which holds a reference to it
+        let buffer = unsafe {
+            Buffer::from_custom_allocation(
+                NonNull::new(raw.as_ptr() as *mut u8).unwrap(),
+                len,
+                owner,
+            )
+        };
+        Ok(Some(PrimitiveArray::new(
+            ScalarBuffer::new(buffer, 0, num),
+            None,
+        )))
+    }
+
+    // one block of `columns`, of all columns if None
+    pub fn record_batch(
+        &self,
+        block: usize,
+        columns: Option<&[String]>,
+    ) -> Result<RecordBatch, BuildError> {
+        let schema = self.arrow_schema(columns)?;
+        let arrays = schema
+            .fields()
+            .iter()
+            .map(|field| self.arrow_array(field.name(), block))
+            .collect::<Result<Vec<_>, _>>()?;
+        // the row count is needed when no column is projected, e.g. for count(*)
+        let options = RecordBatchOptions::new().with_row_count(Some(self.block_len(block)));
+        let batch =
+            RecordBatch::try_new_with_options(schema, arrays, &options).map_err(arrow_error)?;
+        if self.block_num_deleted(block) == 0 {
+            return Ok(batch);
+        }
+        let start = self.block_start(block);
+        let live: BooleanArray = (start..start + self.block_len(block))
+            .map(|row| Some(!self.deletes.is_deleted(row)))
+            .collect();
+        filter_record_batch(&batch, &live).map_err(arrow_error)
+    }
+
+    pub fn record_batches<'a>(
+        &'a self,
+        blocks: Range<usize>,
+        columns: Option<&'a [String]>,
+    ) -> impl Iterator<Item = Result<RecordBatch, BuildError>> + 'a {
+        blocks.map(move |block| self.record_batch(block, columns))
+    }
+}
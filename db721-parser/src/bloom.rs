@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+
+use crate::types::{ColumnType, ScalarValue};
+
+/*
+Per-block bloom filters, for pruning equality predicates that min/max cannot.
+
+metadata["Columns"]["Column Name"]["bloom_filter"]: optional (JSON dict)
+    Keys:
+    "offset": the offset in the file of the filter of block 0 (int)
+    "num_bytes": the length of the filter of each block in bytes (int)
+    "num_hashes": the number of bits set per value (int)
+
+The filter of block i starts at offset + i * num_bytes. The writer puts the
+filters of all columns after the last column, right before the metadata.
+
+A value is hashed in its "plain" layout (see types.rs, floats with -0.0 as 0.0),
+the elements of a "list[str]" are hashed one by one. Bit j of the filter is
+bit j % 8 of byte j / 8, and the bits of a value are
+    (h1 + i * h2) % (8 * num_bytes)    for i in 0..num_hashes
+with h1 = mix(the 64-bit FNV-1a hash of the value) and h2 = mix(h1) | 1, where
+mix is the splitmix64 finalizer, see `hashes`.
+*/
+
+// false positive rate the writer sizes the filters for
+pub const FALSE_POSITIVE_RATE: f64 = 0.01;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BloomFilterMeta {
+    pub offset: usize,
+    pub num_bytes: usize,
+    pub num_hashes: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter<B: AsRef<[u8]>> {
+    pub bits: B,
+    pub num_hashes: u32,
+}
+
+// splitmix64 finalizer
+fn mix(h: u64) -> u64 {
+    let mut h = h.wrapping_add(0x9e3779b97f4a7c15);
+    h = (h ^ (h >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    h = (h ^ (h >> 27)).wrapping_mul(0x94d049bb133111eb);
+    h ^ (h >> 31)
+}
+
+fn hashes(key: &[u8]) -> (u64, u64) {
+    let mut fnv: u64 = 0xcbf29ce484222325;
+    for b in key {
+        fnv ^= *b as u64;
+        fnv = fnv.wrapping_mul(0x100000001b3);
+    }
+    let h1 = mix(fnv);
+    (h1, mix(h1) | 1)
+}
+
+// the keys hashed for a value of a column of type `ty`, None if `value` cannot be
+// stored in such a column
+pub fn keys(ty: ColumnType, value: &ScalarValue) -> Option<Vec<Vec<u8>>> {
+    let values = match (ty, value) {
+        (ColumnType::StrList, ScalarValue::StrList(list)) => {
+            list.iter().map(|s| ScalarValue::Str(s.clone())).collect()
+        }
+        (ColumnType::StrList, ScalarValue::Str(_)) => vec![value.clone()],
+        _ => vec![value.cast(ty)?],
+    };
+    Some(
+        values
+            .into_iter()
+            .map(|v| {
+                // adding 0.0 turns -0.0 into 0.0
+                let v = match v {
+                    ScalarValue::Float(f) => ScalarValue::Float(f + 0.0),
+                    ScalarValue::Double(f) => ScalarValue::Double(f + 0.0),
+                    v => v,
+                };
+                let mut key = Vec::new();
+                v.encode(&mut key);
+                key
+            })
+            .collect(),
+    )
+}
+
+impl BloomFilter<Vec<u8>> {
+    // a filter holding `num_values` distinct values at `FALSE_POSITIVE_RATE`
+    pub fn with_capacity(num_values: usize) -> BloomFilter<Vec<u8>> {
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits =
+            (-(num_values.max(1) as f64) * FALSE_POSITIVE_RATE.ln() / (ln2 * ln2)).ceil();
+        let num_bytes = (num_bits as usize).div_ceil(64) * 8;
+        let num_hashes = ((num_bytes * 8) as f64 / num_values.max(1) as f64 * ln2).round();
+        BloomFilter::new(num_bytes, (num_hashes as u32).clamp(1, 16))
+    }
+
+    pub fn new(num_bytes: usize, num_hashes: u32) -> BloomFilter<Vec<u8>> {
+        BloomFilter {
+            bits: vec![0; num_bytes],
+            num_hashes,
+        }
+    }
+
+    pub fn insert(&mut self, key: &[u8]) {
+        let num_bits = self.bits.len() as u64 * 8;
+        let (h1, h2) = hashes(key);
+        for i in 0..self.num_hashes as u64 {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % num_bits;
+            self.bits[(bit / 8) as usize] |= 1 << (bit % 8);
+        }
+    }
+}
+
+impl<B: AsRef<[u8]>> BloomFilter<B> {
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        let bits = self.bits.as_ref();
+        if bits.is_empty() {
+            return true;
+        }
+        let num_bits = bits.len() as u64 * 8;
+        let (h1, h2) = hashes(key);
+        (0..self.num_hashes as u64).all(|i| {
+            let bit = h1.wrapping_add(i.wrapping_mul(h2)) % num_bits;
+            bits[(bit / 8) as usize] & (1 << (bit % 8)) != 0
+        })
+    }
+
+    // whether a column of type `ty` may hold `value`
+    pub fn may_contain_value(&self, ty: ColumnType, value: &ScalarValue) -> bool {
+        match keys(ty, value) {
+            Some(keys) => keys.iter().all(|k| self.may_contain(k)),
+            None => true,
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use serde::{Deserialize, Serialize};
+
+/*
+Checksums, to detect bit rot and truncated writes.
+
+metadata["Checksum"]: optional, the checksum algorithm of the file (str)
+    "crc32c"    CRC-32C (Castagnoli)
+
+When it is set, the 4 bytes right before the metadata hold the u32 LE checksum of
+the metadata, and each block may carry its own:
+
+metadata["Columns"]["Column Name"]["block_stats"][i]["crc32c"]: optional, the
+    checksum of the block as stored in the file, i.e. after encoding and
+    compression (int)
+
+The metadata is verified when the file is opened, a block whenever it is read.
+*/
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Checksum {
+    #[default]
+    #[serde(rename = "crc32c")]
+    Crc32c,
+}
+
+impl Checksum {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Checksum::Crc32c => "crc32c",
+        }
+    }
+
+    pub fn compute(&self, data: &[u8]) -> u32 {
+        match self {
+            Checksum::Crc32c => crc32c::crc32c(data),
+        }
+    }
+}
+
+// how the parser treats checksums
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VerifyMode {
+    // verify the checksums the file has, read the rest unverified
+    #[default]
+    Lenient,
+    // the metadata and every block must have a checksum
+    Strict,
+}
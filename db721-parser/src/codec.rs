@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+/*
+Compression codecs for the blocks of a column.
+
+metadata["Columns"]["Column Name"]["compression"] picks the codec of every block
+of the column, metadata["Columns"]["Column Name"]["block_stats"][i]["compression"]
+overrides it for one block (the writer stores a block as "none" when compressing
+does not make it smaller). Both keys are optional, files without them are not
+compressed.
+
+A compressed block occupies "size" bytes in the file:
+    "lz4"   LZ4 block format, prefixed with the u32 LE decompressed length
+    "zstd"  one Zstandard frame
+
+Neither length nor frame is trusted when decompressing: the output is limited to the
+length the metadata gives the block, num * width for fixed width columns, "raw_size"
+for list[str] blocks. Blocks written before "raw_size" existed are limited to
+MAX_RAW_BLOCK_SIZE.
+*/
+
+pub const ZSTD_LEVEL: i32 = 3;
+
+// the limit of compressed list[str] blocks without "raw_size"
+pub const MAX_RAW_BLOCK_SIZE: usize = 1 << 28;
+
+// the length of a block after decompression, as far as the metadata tells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawSize {
+    Exact(usize),
+    AtMost(usize),
+}
+
+impl RawSize {
+    pub fn limit(&self) -> usize {
+        match self {
+            RawSize::Exact(size) | RawSize::AtMost(size) => *size,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Codec {
+    #[default]
+    #[serde(rename = "none")]
+    None,
+    #[serde(rename = "lz4")]
+    Lz4,
+    #[serde(rename = "zstd")]
+    Zstd,
+}
+
+impl Codec {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::None => "none",
+            Codec::Lz4 => "lz4",
+            Codec::Zstd => "zstd",
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Codec::None => Ok(data.to_vec()),
+            Codec::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            Codec::Zstd => zstd::bulk::compress(data, ZSTD_LEVEL),
+        }
+    }
+
+    // decompress a block of `size` bytes once decompressed
+    pub fn decompress<'a>(&self, data: &'a [u8], size: RawSize) -> Result<Cow<'a, [u8]>, String> {
+        let limit = size.limit();
+        let out = match self {
+            Codec::None => return Ok(Cow::Borrowed(data)),
+            Codec::Lz4 => {
+                let (len, data) = match data.split_first_chunk::<4>() {
+                    Some((len, data)) => (u32::from_le_bytes(*len) as usize, data),
+                    None => return Err("lz4 block is too short".to_string()),
+                };
+                if len > limit {
+                    return Err(format!("lz4 block of {} bytes, more than {}", len, limit));
+                }
+                let mut out = vec![0; len];
+                let written =
+                    lz4_flex::decompress_into(data, &mut out).map_err(|e| e.to_string())?;
+                out.truncate(written);
+                out
+            }
+            Codec::Zstd => zstd::bulk::decompress(data, limit).map_err(|e| e.to_string())?,
+        };
+        match size {
+            RawSize::Exact(size) if out.len() != size => {
+                Err(format!("decompressed to {} bytes, not {}", out.len(), size))
+            }
+            _ => Ok(Cow::Owned(out)),
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::dataset::Dataset;
+use crate::deletes::DeleteVector;
+use crate::parser::{BuildError, Parser, ParserBuilder};
+use crate::staging::{tmp_path, FileLock, Staged};
+use crate::types::ScalarValue;
+use crate::writer::{WriteError, Writer};
+
+/*
+Compaction rewrites the files of a table into one, after appends left them with small
+blocks and deletes with rows that are only hidden:
+
+    db721 compact part-1.db721 part-2.db721 --output data.db721
+
+The live rows of the files, in order, are written in full blocks of Max Values Per
+Block, that of the first file unless given, with their stats computed anew. Deleted
+rows are dropped. The new file is set up like the first one: the same checksum and, per
+column, compression and bloom filters, with the encoding of every block chosen anew.
+The files must hold the same table with the same columns.
+
+The files and the output are locked throughout, appends and deletes wait for the
+compaction, see `staging`. The new file and the removals of the other files, unless
+they are kept, are published together under one journal, undone after an error and
+by the next writer after a crash:
+
+    sidecar     a delete vector of the output is staged first for the old version of
+                the output, with its deletes, and the new one, without any, so that
+                readers of either find theirs
+    output      the new file is renamed over the output
+    inputs      every other file is removed, then its delete vector
+
+A dataset opened while they are published may still find the other files next to the
+new output and read their rows twice. Row ids change as well, deletes of rows read
+before the compaction fail rather than hit other rows: they name the old version of
+the file, see `stage_deletes`.
+*/
+
+#[derive(Debug)]
+pub enum CompactError {
+    IoError(std::io::Error),
+    BuildError(BuildError),
+    WriteError(WriteError),
+}
+
+impl From<std::io::Error> for CompactError {
+    fn from(error: std::io::Error) -> Self {
+        CompactError::IoError(error)
+    }
+}
+
+impl From<BuildError> for CompactError {
+    fn from(error: BuildError) -> Self {
+        CompactError::BuildError(error)
+    }
+}
+
+impl From<WriteError> for CompactError {
+    fn from(error: WriteError) -> Self {
+        CompactError::WriteError(error)
+    }
+}
+
+impl std::fmt::Display for CompactError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompactError::IoError(e) => write!(f, "DB721|IO: {}", e),
+            CompactError::BuildError(e) => write!(f, "{}", e),
+            CompactError::WriteError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for CompactError {}
+
+// what a compaction wrote
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Compacted {
+    pub rows: usize,
+    pub blocks: usize,
+    // the deleted rows dropped
+    pub deleted: usize,
+}
+
+pub struct Compaction {
+    inputs: Vec<String>,
+    output: String,
+    max_values_per_block: Option<u32>,
+    keep_inputs: bool,
+}
+
+impl Compaction {
+    pub fn new(inputs: &[String], output: &str) -> Compaction {
+        Compaction {
+            inputs: inputs.to_vec(),
+            output: output.to_string(),
+            max_values_per_block: None,
+            keep_inputs: false,
+        }
+    }
+
+    pub fn max_values_per_block(&mut self, max_values_per_block: u32) -> &mut Self {
+        self.max_values_per_block = Some(max_values_per_block);
+        self
+    }
+
+    // leave the files other than the output in place
+    pub fn keep_inputs(&mut self, keep_inputs: bool) -> &mut Self {
+        self.keep_inputs = keep_inputs;
+        self
+    }
+
+    pub fn run(&self) -> Result<Compacted, CompactError> {
+        let paths = self.inputs.iter().chain([&self.output]);
+        let _locks = FileLock::acquire_all(paths.map(String::as_str))?;
+        let files = self
+            .inputs
+            .iter()
+            .map(|path| ParserBuilder::new(path.clone(), String::new()).build())
+            .collect::<Result<Vec<_>, _>>()?;
+        if files.is_empty() {
+            return Err(CompactError::IoError(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "no files to compact",
+            )));
+        }
+        // the inputs as they are now, the output replaces one of them if in place
+        let inputs = self
+            .inputs
+            .iter()
+            .map(std::fs::canonicalize)
+            .collect::<Result<Vec<_>, _>>()?;
+        let output = std::fs::canonicalize(&self.output).ok();
+        // the version of the output replaced, with its deletes
+        let replaced = match inputs.iter().position(|path| Some(path) == output.as_ref()) {
+            Some(i) => Some((files[i].identity, files[i].deletes.clone())),
+            None if output.is_some() => ParserBuilder::new(self.output.clone(), String::new())
+                .build()
+                .ok()
+                .map(|parser| (parser.identity, parser.deletes)),
+            None => None,
+        };
+        let dataset = Dataset::try_new(String::new(), self.inputs.clone(), files)?;
+
+        let tmp = tmp_path(&self.output);
+        let mut data = Staged::default();
+        data.push(tmp.clone(), self.output.clone());
+        let compacted = self.write(&tmp, &dataset)?;
+        drop(dataset);
+
+        let mut staged = Staged::default();
+        let sidecar = DeleteVector::path(&self.output);
+        match replaced {
+            Some((identity, deletes)) if Path::new(&sidecar).exists() => {
+                let new = ParserBuilder::new(tmp, String::new()).build()?.identity;
+                let none = DeleteVector::default();
+                let versions = [(identity, &deletes), (new, &none)];
+                staged.extend(DeleteVector::stage(&self.output, &versions)?);
+                staged.extend(data);
+            }
+            // of no db721 file that could be read, it goes with it
+            _ => {
+                staged.extend(data);
+                staged.remove(sidecar);
+            }
+        }
+        if !self.keep_inputs {
+            for (input, path) in self.inputs.iter().zip(&inputs) {
+                if Some(path) != output.as_ref() {
+                    staged.remove(input.clone());
+                    staged.remove(DeleteVector::path(input));
+                }
+            }
+        }
+        staged.publish()?;
+        Ok(compacted)
+    }
+
+    fn write(&self, path: &str, dataset: &Dataset) -> Result<Compacted, CompactError> {
+        let first = &dataset.files[0];
+        let max_values_per_block = self
+            .max_values_per_block
+            .unwrap_or(first.metadata.max_values_per_block)
+            .max(1);
+        let mut writer = Writer::new(
+            first.metadata.table.clone(),
+            BufWriter::new(File::create(path)?),
+            max_values_per_block,
+        );
+        writer.checksum(first.metadata.checksum);
+        let mut blocks = 0;
+        for (name, ty) in &dataset.columns {
+            if let Some(column) = first.metadata.columns.get(name) {
+                writer
+                    .compression(column.compression.unwrap_or_default())
+                    .bloom_filter(column.bloom_filter.is_some());
+            }
+            // write_col_blocks only passes on WriteErrors, others are kept here
+            let mut failed = None;
+            let mut values = dataset
+                .files
+                .iter()
+                .flat_map(|file| (0..file.num_blocks()).map(move |block| (file, block)))
+                .map_while(|(file, block)| match live_values(file, name, block) {
+                    Ok(values) => Some(values),
+                    Err(e) => {
+                        failed = Some(e);
+                        None
+                    }
+                })
+                .flatten();
+            // full blocks across the blocks and files they came from
+            let chunks = std::iter::from_fn(|| {
+                let chunk: Vec<_> = values
+                    .by_ref()
+                    .take(max_values_per_block as usize)
+                    .collect();
+                (!chunk.is_empty()).then_some(Ok::<_, WriteError>(chunk))
+            });
+            blocks = writer.write_col_blocks(name, *ty, chunks)? as usize;
+            if let Some(e) = failed {
+                return Err(e.into());
+            }
+        }
+        let mut out = writer.finalize()?;
+        out.flush()?;
+        out.get_ref().sync_all()?;
+        Ok(Compacted {
+            rows: dataset.num_rows(),
+            blocks,
+            deleted: dataset.files.iter().map(|f| f.deletes.num_deleted()).sum(),
+        })
+    }
+}
+
+// the values of the rows of the block that are not deleted
+fn live_values(file: &Parser, name: &str, block: usize) -> Result<Vec<ScalarValue>, BuildError> {
+    let values = file.read_block(name, block)?;
+    let selection = file.filter_block(block, &[])?;
+    Ok((0..values.len())
+        .filter(|row| selection[*row])
+        .filter_map(|row| values.get(row))
+        .collect())
+}
@@ -0,0 +1,665 @@
+use std::cmp::Ordering;
+use std::path::{Component, Path};
+
+use crate::checksum::VerifyMode;
+use crate::parser::{BlockStats, BuildError, Parser, ParserBuilder};
+use crate::predicate::Predicate;
+use crate::types::{ColumnBlock, ColumnType, ScalarValue};
+
+/*
+A table over many db721 files, e.g. one per day:
+
+    directory           all *.db721 files in the directory and its subdirectories
+    pattern             the files matching a glob pattern, like "/data/2023-*.db721"
+    directory, pattern  the files matching the pattern inside the directory
+
+The files are ordered by path and must hold the same table, the requested one if any.
+How their columns may differ depends on the schema policy:
+
+    Strict      the same columns of the same types, in the order of the first file
+    Merge       the columns of all files, in the order they first appear. A file without
+                a column reads it as its declared default or NULL, and a column may widen
+                from int to bigint or from float to double, the narrower values are cast.
+                Any other change of type is reported with the file that makes it
+
+Directories named `key=value` below the root, the directory or the part of the pattern
+before its first wildcard, are hive-style partitions:
+
+    chickens/farm_name=Incubator/sex=FEMALE/part-0.db721
+
+adds the str columns farm_name and sex after the columns of the files, with the same
+value on every row of the file. `%XX` escapes in values are decoded. Every file must be
+partitioned by the same keys in the same order, and no key may be a column of the files.
+
+Rows are addressed by (file, block). Pruning first skips whole files by their partition
+values and the min/max of each column over all of their blocks, then the blocks of the
+remaining files as `Parser::prune` does. Files ruled out by their partition values alone
+are not even opened when the predicates are given to the builder.
+*/
+
+pub struct DatasetBuilder {
+    tablename: String,
+    directory: Option<String>,
+    pattern: Option<String>,
+    verify: VerifyMode,
+    predicates: Vec<Predicate>,
+    schema: SchemaPolicy,
+    // column, default value as text
+    defaults: Vec<(String, String)>,
+}
+
+// how the columns of the files of a dataset may differ
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SchemaPolicy {
+    #[default]
+    Strict,
+    Merge,
+}
+
+impl DatasetBuilder {
+    pub fn new(tablename: String) -> DatasetBuilder {
+        DatasetBuilder {
+            tablename,
+            directory: None,
+            pattern: None,
+            verify: VerifyMode::default(),
+            predicates: Vec::new(),
+            schema: SchemaPolicy::default(),
+            defaults: Vec::new(),
+        }
+    }
+
+    pub fn directory(&mut self, directory: &str) -> &mut Self {
+        self.directory = Some(directory.to_string());
+        self
+    }
+
+    pub fn pattern(&mut self, pattern: &str) -> &mut Self {
+        self.pattern = Some(pattern.to_string());
+        self
+    }
+
+    pub fn verify(&mut self, mode: VerifyMode) -> &mut Self {
+        self.verify = mode;
+        self
+    }
+
+    pub fn schema(&mut self, policy: SchemaPolicy) -> &mut Self {
+        self.schema = policy;
+        self
+    }
+
+    // the value of a column for the files without it under `SchemaPolicy::Merge`, parsed as
+    // the type of the column
+    pub fn default_value(&mut self, column: &str, value: &str) -> &mut Self {
+        self.defaults.push((column.to_string(), value.to_string()));
+        self
+    }
+
+    // files whose partition values do not match these are skipped without opening them
+    pub fn predicates(&mut self, predicates: &[Predicate]) -> &mut Self {
+        self.predicates = predicates.to_vec();
+        self
+    }
+
+    // the directory below which `key=value` directories are partitions
+    fn root(&self) -> String {
+        if let Some(directory) = &self.directory {
+            return directory.trim_end_matches('/').to_string();
+        }
+        let pattern = self.pattern.as_deref().unwrap_or_default();
+        let literal = pattern
+            .split('/')
+            .take_while(|c| !c.contains(['*', '?', '[']))
+            .collect::<Vec<_>>();
+        // the last literal component of a pattern without wildcards is the file
+        let len = match literal.len() == pattern.split('/').count() {
+            true => literal.len().saturating_sub(1),
+            false => literal.len(),
+        };
+        literal[..len].join("/")
+    }
+
+    // the partition keys and the partition values of every path
+    fn partitions(&self, paths: &[String]) -> Result<(Vec<String>, Vec<Vec<String>>), BuildError> {
+        let root = self.root();
+        let mut keys: Option<Vec<String>> = None;
+        let mut values = Vec::new();
+        for path in paths {
+            let (path_keys, path_values): (Vec<_>, Vec<_>) =
+                partitions(&root, path).into_iter().unzip();
+            match &keys {
+                Some(keys) if *keys != path_keys => {
+                    return Err(BuildError::SchemaMismatch(
+                        path.clone(),
+                        format!(
+                            "is partitioned by [{}], not [{}]",
+                            path_keys.join(", "),
+                            keys.join(", ")
+                        ),
+                    ))
+                }
+                Some(_) => {}
+                None => keys = Some(path_keys),
+            }
+            values.push(path_values);
+        }
+        Ok((keys.unwrap_or_default(), values))
+    }
+
+    // the partition columns, from the paths alone
+    pub fn partition_columns(&self) -> Result<Vec<String>, BuildError> {
+        Ok(self.partitions(&self.paths()?)?.0)
+    }
+
+    // the files of the dataset, ordered by path
+    pub fn paths(&self) -> Result<Vec<String>, BuildError> {
+        let pattern = match (&self.directory, &self.pattern) {
+            (Some(directory), pattern) => {
+                let directory = glob::Pattern::escape(directory.trim_end_matches('/'));
+                format!(
+                    "{}/{}",
+                    directory,
+                    pattern.as_deref().unwrap_or("**/*.db721")
+                )
+            }
+            (None, Some(pattern)) => pattern.clone(),
+            (None, None) => {
+                return Err(BuildError::FormatError(
+                    "a dataset needs a directory or a pattern".to_string(),
+                ))
+            }
+        };
+        let entries = glob::glob(&pattern).map_err(|e| {
+            BuildError::FormatError(format!("invalid pattern {:?}: {}", pattern, e))
+        })?;
+        let mut paths = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| BuildError::IoError(e.into()))?;
+            if path.is_file() {
+                paths.push(path.to_string_lossy().into_owned());
+            }
+        }
+        paths.sort();
+        Ok(paths)
+    }
+
+    pub fn build(&self) -> Result<Dataset, BuildError> {
+        let paths = self.paths()?;
+        let (partition_columns, values) = self.partitions(&paths)?;
+        let (paths, partitions): (Vec<_>, Vec<_>) = paths
+            .into_iter()
+            .zip(values)
+            .filter(|(_, values)| partitions_match(&partition_columns, values, &self.predicates))
+            .unzip();
+        let files = paths
+            .iter()
+            .map(|path| {
+                ParserBuilder::new(path.clone(), self.tablename.clone())
+                    .verify(self.verify)
+                    .build()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let tablename = self.tablename.clone();
+        let mut dataset = match self.schema {
+            SchemaPolicy::Strict => Dataset::try_new(tablename, paths, files)?,
+            SchemaPolicy::Merge => Dataset::try_merge(tablename, paths, files, &self.defaults)?,
+        };
+        if let Some(key) = partition_columns
+            .iter()
+            .find(|key| dataset.columns.iter().any(|(name, _)| name == *key))
+        {
+            return Err(BuildError::SchemaMismatch(
+                dataset.paths[0].clone(),
+                format!("has the column {} of its partition", key),
+            ));
+        }
+        dataset.partition_columns = partition_columns;
+        dataset.partitions = partitions;
+        Ok(dataset)
+    }
+}
+
+// the `key=value` directories of a path below root
+pub fn partitions(root: &str, path: &str) -> Vec<(String, String)> {
+    let root = normal_components(root);
+    let mut components = normal_components(path);
+    if !components.starts_with(&root) {
+        return Vec::new();
+    }
+    // the file name
+    components.pop();
+    components
+        .iter()
+        .skip(root.len())
+        .filter_map(|c| c.as_os_str().to_str()?.split_once('='))
+        .map(|(key, value)| (unescape(key), unescape(value)))
+        .collect()
+}
+
+// glob leaves out the `.` components of a pattern, like `./data/**/*.db721`, and
+// repeated separators, so paths are compared without them
+fn normal_components(path: &str) -> Vec<Component<'_>> {
+    Path::new(path)
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .collect()
+}
+
+// the inverse of `unescape`, for the characters that cannot be in a directory name
+pub(crate) fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '%' | '/' | '=' | '\\' | '\x00'..='\x1f' | '\x7f' => {
+                out.push_str(&format!("%{:02X}", c as u32))
+            }
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+// decode the %XX escapes hive uses for special characters in partition values
+fn unescape(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|h| std::str::from_utf8(h).ok())
+            .and_then(|h| u8::from_str_radix(h, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+// whether the partition values match the predicates on partition columns, the values
+// are parsed as the type of the predicate so that `year = 2023` matches year=2023
+fn partitions_match(columns: &[String], values: &[String], predicates: &[Predicate]) -> bool {
+    predicates.iter().all(
+        |pred| match columns.iter().position(|c| *c == pred.column) {
+            Some(i) => {
+                let value = pred
+                    .values
+                    .first()
+                    .and_then(|v| ScalarValue::parse(v.column_type(), &values[i]))
+                    .unwrap_or_else(|| ScalarValue::Str(values[i].clone()));
+                pred.matches(&value)
+            }
+            None => true,
+        },
+    )
+}
+
+pub struct Dataset {
+    pub tablename: String,
+    pub paths: Vec<String>,
+    pub files: Vec<Parser>,
+    // the columns of every file, in the order of the first one
+    pub columns: Vec<(String, ColumnType)>,
+    // the str columns of the `key=value` directories, and their values for every file
+    pub partition_columns: Vec<String>,
+    pub partitions: Vec<Vec<String>>,
+    // the values of the columns missing from some files, the others are NULL there
+    pub defaults: Vec<(String, ScalarValue)>,
+}
+
+// the values of a column in one block of a file
+enum Values {
+    // and the type of the dataset, to which widened columns are cast
+    Block(ColumnBlock, Option<ColumnType>),
+    // a partition column or a column the file lacks
+    Constant(Option<ScalarValue>),
+}
+
+impl Values {
+    fn get(&self, row: usize) -> Option<ScalarValue> {
+        match self {
+            Values::Block(block, ty) => block.get(row).map(|v| match ty {
+                Some(ty) => v.cast(*ty).unwrap_or(v),
+                None => v,
+            }),
+            Values::Constant(value) => value.clone(),
+        }
+    }
+}
+
+// the type both types widen to, if any
+fn widen(a: ColumnType, b: ColumnType) -> Option<ColumnType> {
+    match (a, b) {
+        _ if a == b => Some(a),
+        (ColumnType::Int, ColumnType::BigInt) | (ColumnType::BigInt, ColumnType::Int) => {
+            Some(ColumnType::BigInt)
+        }
+        (ColumnType::Float, ColumnType::Double) | (ColumnType::Double, ColumnType::Float) => {
+            Some(ColumnType::Double)
+        }
+        _ => None,
+    }
+}
+
+// whether every file holds the table, the requested one or else the one of the first file
+fn check_tables(tablename: &str, paths: &[String], files: &[Parser]) -> Result<(), BuildError> {
+    for (path, file) in paths.iter().zip(files) {
+        let table = &file.metadata.table;
+        let expected = match tablename.is_empty() {
+            true => &files[0].metadata.table,
+            false => tablename,
+        };
+        if table != expected {
+            return Err(BuildError::SchemaMismatch(
+                path.clone(),
+                format!("holds table {}, not {}", table, expected),
+            ));
+        }
+    }
+    Ok(())
+}
+
+impl Dataset {
+    // the files as one table, checking that they hold the same one
+    pub fn try_new(
+        tablename: String,
+        paths: Vec<String>,
+        files: Vec<Parser>,
+    ) -> Result<Dataset, BuildError> {
+        check_tables(&tablename, &paths, &files)?;
+        let columns: Vec<(String, ColumnType)> = files.first().map_or(Vec::new(), |first| {
+            first
+                .columns
+                .iter()
+                .map(|c| (c.column_name.clone(), c.column_type))
+                .collect()
+        });
+        for (path, file) in paths.iter().zip(&files) {
+            let mismatch = |e: String| Err(BuildError::SchemaMismatch(path.clone(), e));
+            for (name, ty) in &columns {
+                match file.column_meta(name) {
+                    Some(meta) if meta.column_type == *ty => {}
+                    Some(meta) => {
+                        return mismatch(format!(
+                            "column {} is {}, not {}",
+                            name,
+                            meta.column_type.name(),
+                            ty.name()
+                        ))
+                    }
+                    None => return mismatch(format!("has no column {}", name)),
+                }
+            }
+            if let Some(extra) = file
+                .columns
+                .iter()
+                .find(|c| !columns.iter().any(|(name, _)| *name == c.column_name))
+            {
+                return mismatch(format!("has the extra column {}", extra.column_name));
+            }
+        }
+        let partitions = vec![Vec::new(); files.len()];
+        Ok(Dataset {
+            tablename,
+            paths,
+            files,
+            columns,
+            partition_columns: Vec::new(),
+            partitions,
+            defaults: Vec::new(),
+        })
+    }
+
+    // the files as one table whose columns are merged as `SchemaPolicy::Merge` describes
+    pub fn try_merge(
+        tablename: String,
+        paths: Vec<String>,
+        files: Vec<Parser>,
+        defaults: &[(String, String)],
+    ) -> Result<Dataset, BuildError> {
+        check_tables(&tablename, &paths, &files)?;
+        let mut columns: Vec<(String, ColumnType)> = Vec::new();
+        for (path, file) in paths.iter().zip(&files) {
+            for meta in &file.columns {
+                match columns
+                    .iter_mut()
+                    .find(|(name, _)| *name == meta.column_name)
+                {
+                    Some((name, ty)) => {
+                        *ty = widen(*ty, meta.column_type).ok_or_else(|| {
+                            BuildError::SchemaMismatch(
+                                path.clone(),
+                                format!(
+                                    "column {} is {}, which does not widen {}",
+                                    name,
+                                    meta.column_type.name(),
+                                    ty.name()
+                                ),
+                            )
+                        })?
+                    }
+                    None => columns.push((meta.column_name.clone(), meta.column_type)),
+                }
+            }
+        }
+        // without files there are no columns to check the defaults against
+        let defaults = match files.is_empty() {
+            true => Vec::new(),
+            false => defaults
+                .iter()
+                .map(|(column, text)| {
+                    let ty = match columns.iter().find(|(name, _)| name == column) {
+                        Some((_, ty)) => *ty,
+                        None => {
+                            return Err(BuildError::FormatError(format!(
+                                "default for unknown column {}",
+                                column
+                            )))
+                        }
+                    };
+                    match ScalarValue::parse(ty, text) {
+                        Some(value) => Ok((column.clone(), value)),
+                        None => Err(BuildError::FormatError(format!(
+                            "default {:?} of column {} is not a {}",
+                            text,
+                            column,
+                            ty.name()
+                        ))),
+                    }
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+        };
+        Ok(Dataset {
+            tablename,
+            partitions: vec![Vec::new(); files.len()],
+            paths,
+            files,
+            columns,
+            partition_columns: Vec::new(),
+            defaults,
+        })
+    }
+
+    pub fn column_type(&self, name: &str) -> Option<ColumnType> {
+        self.columns
+            .iter()
+            .find(|(column, _)| column == name)
+            .map(|(_, ty)| *ty)
+            .or_else(|| {
+                self.partition_columns
+                    .iter()
+                    .any(|c| c == name)
+                    .then_some(ColumnType::Str)
+            })
+    }
+
+    // the value of a column for the rows of a file without it
+    pub fn default_value(&self, name: &str) -> Option<&ScalarValue> {
+        self.defaults
+            .iter()
+            .find(|(column, _)| column == name)
+            .map(|(_, value)| value)
+    }
+
+    // the value of a partition column for the rows of a file
+    pub fn partition_value(&self, file: usize, name: &str) -> Option<&str> {
+        let i = self.partition_columns.iter().position(|c| c == name)?;
+        Some(self.partitions.get(file)?[i].as_str())
+    }
+
+    // the value of a column on every row of a file, None for the columns the file stores,
+    // Some(None) for NULL
+    pub fn file_constant(&self, file: usize, name: &str) -> Option<Option<ScalarValue>> {
+        if let Some(value) = self.partition_value(file, name) {
+            return Some(Some(ScalarValue::Str(value.to_string())));
+        }
+        match self.files.get(file)?.column_meta(name) {
+            Some(_) => None,
+            None => Some(self.default_value(name).cloned()),
+        }
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.files.iter().map(|f| f.num_live_rows()).sum()
+    }
+
+    pub fn num_blocks(&self) -> usize {
+        self.files.iter().map(|f| f.num_blocks()).sum()
+    }
+
+    // the stats of a column over all blocks of a file, with only num, min and max
+    pub fn file_stats(&self, file: usize, name: &str) -> Option<BlockStats> {
+        let parser = self.files.get(file)?;
+        let mut stats = BlockStats {
+            num: 0,
+            min: None,
+            max: None,
+            min_len: None,
+            max_len: None,
+            size: None,
+            raw_size: None,
+            compression: None,
+            encoding: None,
+            crc32c: None,
+        };
+        let mut bounded = true;
+        for block in 0..parser.num_blocks() {
+            let block = parser.block_stats(name, block)?;
+            stats.num = stats.num.saturating_add(block.num);
+            if block.num == 0 {
+                continue;
+            }
+            // a block without bounds leaves the file without them
+            let (min, max) = match (&block.min, &block.max) {
+                (Some(min), Some(max)) => (min, max),
+                _ => {
+                    bounded = false;
+                    continue;
+                }
+            };
+            let pick = |a: &ScalarValue, b: &Option<ScalarValue>, keep: Ordering| match b {
+                Some(b) if a.compare(b) != Some(keep) => Some(b.clone()),
+                _ => Some(a.clone()),
+            };
+            stats.min = pick(min, &stats.min, Ordering::Less);
+            stats.max = pick(max, &stats.max, Ordering::Greater);
+        }
+        if !bounded {
+            stats.min = None;
+            stats.max = None;
+        }
+        Some(stats)
+    }
+
+    // the files that may hold rows matching all predicates
+    pub fn prune_files(&self, predicates: &[Predicate]) -> Vec<usize> {
+        (0..self.files.len())
+            .filter(|file| {
+                partitions_match(&self.partition_columns, &self.partitions[*file], predicates)
+            })
+            .filter(|file| {
+                predicates.iter().all(|pred| {
+                    match (
+                        self.column_type(&pred.column),
+                        self.file_stats(*file, &pred.column),
+                    ) {
+                        (Some(ty), Some(stats)) => pred.may_match(ty, &stats),
+                        // NULL never matches
+                        (Some(_), None) => self
+                            .file_constant(*file, &pred.column)
+                            .is_none_or(|v| v.is_some_and(|v| pred.matches(&v))),
+                        _ => true,
+                    }
+                })
+            })
+            .collect()
+    }
+
+    // the (file, block) pairs that may hold rows matching all predicates
+    pub fn prune(&self, predicates: &[Predicate]) -> Vec<(usize, usize)> {
+        self.prune_files(predicates)
+            .into_iter()
+            .flat_map(|file| {
+                self.files[file]
+                    .prune(predicates)
+                    .into_iter()
+                    .map(move |block| (file, block))
+            })
+            .collect()
+    }
+
+    // the matching rows of one block, with the values of `columns`, None for NULL
+    pub fn block_rows(
+        &self,
+        file: usize,
+        block: usize,
+        columns: &[String],
+        predicates: &[Predicate],
+    ) -> Result<Vec<Vec<Option<ScalarValue>>>, BuildError> {
+        let parser = &self.files[file];
+        let selection = parser.filter_block(block, predicates)?;
+        if !selection.iter().any(|s| *s) {
+            return Ok(Vec::new());
+        }
+        let values = columns
+            .iter()
+            .map(|name| match self.file_constant(file, name) {
+                Some(value) => Ok(Values::Constant(value)),
+                None => Ok(Values::Block(
+                    parser.read_block(name, block)?,
+                    self.column_type(name),
+                )),
+            })
+            .collect::<Result<Vec<_>, BuildError>>()?;
+        Ok(selection
+            .iter()
+            .enumerate()
+            .filter(|(_, s)| **s)
+            .map(|(row, _)| values.iter().map(|v| v.get(row)).collect())
+            .collect())
+    }
+
+    // the rows of all files matching all predicates, in file order
+    pub fn rows<'a>(
+        &'a self,
+        columns: &'a [String],
+        predicates: &'a [Predicate],
+    ) -> impl Iterator<Item = Result<Vec<Option<ScalarValue>>, BuildError>> + 'a {
+        self.prune(predicates)
+            .into_iter()
+            .flat_map(move |(file, block)| {
+                match self.block_rows(file, block, columns, predicates) {
+                    Ok(rows) => rows.into_iter().map(Ok).collect::<Vec<_>>(),
+                    Err(e) => vec![Err(e)],
+                }
+            })
+    }
+}
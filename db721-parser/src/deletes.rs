@@ -0,0 +1,245 @@
+use std::fs::File;
+use std::io::Write;
+
+use crate::checksum::Checksum;
+use crate::parser::{BuildError, ParserBuilder};
+use crate::staging::{tmp_path, FileLock, Staged};
+
+/*
+The deleted rows of a db721 file. The file itself is never changed, they are kept next
+to it in `<file>.deletes`:
+
+    "D7DV" | u32 LE versions | versions * version | u32 LE crc32c of everything before it
+    version = u64 LE rows | u64 LE file length | u32 LE file crc32c | bitmap
+
+where the bitmap has one bit per row, bit `id % 8` of byte `id / 8` set when the row is
+deleted. Rows are identified by their position in the file, counting across blocks.
+Rows appended to the file after the last delete, at or past `rows`, are not deleted.
+
+A version of the file is given by the length of the file and the crc32c of its
+metadata, the bytes after the data region, which change with every block written. The
+sidecar holds the deletes of every version it was written for, readers use those of the
+version they opened. A rewrite of the file publishes the sidecar first, with the
+deletes of both the old version and the new one, so that readers find theirs before,
+during and after it, and a crash in between leaves a pair that reads. A sidecar without
+the version of the file, left behind by a writer that knows nothing of it, is an error
+rather than deleting rows it was not written for.
+
+The parser loads the sidecar with the file, and every read that goes through
+`Parser::filter_block` skips the deleted rows. Deletes are staged while holding the
+lock of the file, like appends, see `staging`.
+*/
+
+const MAGIC: &[u8; 4] = b"D7DV";
+// magic and the number of versions
+const HEADER_LEN: usize = 8;
+// rows, file length and crc32c
+const VERSION_LEN: usize = 20;
+
+// the version of a db721 file a sidecar belongs to
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileIdentity {
+    pub len: u64,
+    pub metadata_crc32c: u32,
+}
+
+// `<len>-<crc32c in hex>`, e.g. in the row ids of the FDW
+impl std::fmt::Display for FileIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}-{:08x}", self.len, self.metadata_crc32c)
+    }
+}
+
+impl std::str::FromStr for FileIdentity {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<FileIdentity, ()> {
+        let (len, crc) = s.split_once('-').ok_or(())?;
+        Ok(FileIdentity {
+            len: len.parse().map_err(|_| ())?,
+            metadata_crc32c: u32::from_str_radix(crc, 16).map_err(|_| ())?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DeleteVector {
+    bits: Vec<u8>,
+    rows: u64,
+}
+
+impl DeleteVector {
+    // the sidecar of a db721 file
+    pub fn path(filename: &str) -> String {
+        format!("{}.deletes", filename)
+    }
+
+    // the deletes of a file with `num_rows` rows, empty without a sidecar
+    pub fn read(
+        filename: &str,
+        num_rows: usize,
+        identity: FileIdentity,
+    ) -> Result<DeleteVector, BuildError> {
+        let path = DeleteVector::path(filename);
+        let data = match std::fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(DeleteVector::default())
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let invalid = |e: &str| Err(BuildError::FormatError(format!("{} {}", path, e)));
+        if data.len() < HEADER_LEN + 4 || &data[..4] != MAGIC {
+            return invalid("is not a delete vector");
+        }
+        let (body, crc) = data.split_at(data.len() - 4);
+        if Checksum::Crc32c.compute(body) != u32::from_le_bytes(crc.try_into().unwrap()) {
+            return invalid("does not match its crc32c checksum");
+        }
+        let versions = u32::from_le_bytes(body[4..8].try_into().unwrap());
+        let mut rest = &body[HEADER_LEN..];
+        let mut found = None;
+        for _ in 0..versions {
+            if rest.len() < VERSION_LEN {
+                return invalid("is truncated");
+            }
+            let rows = u64::from_le_bytes(rest[0..8].try_into().unwrap());
+            let file = FileIdentity {
+                len: u64::from_le_bytes(rest[8..16].try_into().unwrap()),
+                metadata_crc32c: u32::from_le_bytes(rest[16..20].try_into().unwrap()),
+            };
+            let len = match usize::try_from(rows.div_ceil(8)) {
+                Ok(len) if len <= rest.len() - VERSION_LEN => len,
+                _ => return invalid("is truncated"),
+            };
+            let bits = &rest[VERSION_LEN..VERSION_LEN + len];
+            rest = &rest[VERSION_LEN + len..];
+            if file == identity && found.is_none() {
+                found = Some((rows, bits));
+            }
+        }
+        if !rest.is_empty() {
+            return invalid("has trailing bytes");
+        }
+        let (rows, bits) = match found {
+            Some(found) => found,
+            None => return invalid(&format!("belongs to another version of {}", filename)),
+        };
+        if rows > num_rows as u64 {
+            return invalid(&format!("covers {} rows, the file has {}", rows, num_rows));
+        }
+        Ok(DeleteVector {
+            bits: bits.to_vec(),
+            rows,
+        })
+    }
+
+    pub fn is_deleted(&self, row: usize) -> bool {
+        self.bits
+            .get(row / 8)
+            .is_some_and(|byte| byte & (1 << (row % 8)) != 0)
+    }
+
+    pub fn delete(&mut self, row: usize) {
+        if row as u64 >= self.rows {
+            self.rows = row as u64 + 1;
+            self.bits.resize(self.rows.div_ceil(8) as usize, 0);
+        }
+        self.bits[row / 8] |= 1 << (row % 8);
+    }
+
+    pub fn num_deleted(&self) -> usize {
+        self.bits.iter().map(|b| b.count_ones() as usize).sum()
+    }
+
+    // the number of deleted rows in [start, end)
+    pub fn num_deleted_in(&self, start: usize, end: usize) -> usize {
+        match self.bits.is_empty() {
+            true => 0,
+            false => (start..end).filter(|row| self.is_deleted(*row)).count(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bits.iter().all(|b| *b == 0)
+    }
+
+    // write the sidecar with the deletes of the versions of the file to a temporary
+    // file, published in its place
+    pub fn stage(
+        filename: &str,
+        versions: &[(FileIdentity, &DeleteVector)],
+    ) -> Result<Staged, std::io::Error> {
+        let path = DeleteVector::path(filename);
+        let tmp = tmp_path(&path);
+        let mut staged = Staged::default();
+        staged.push(tmp.clone(), path);
+        let mut data = Vec::with_capacity(HEADER_LEN + 4);
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&(versions.len() as u32).to_le_bytes());
+        for (identity, deletes) in versions {
+            data.extend_from_slice(&deletes.rows.to_le_bytes());
+            data.extend_from_slice(&identity.len.to_le_bytes());
+            data.extend_from_slice(&identity.metadata_crc32c.to_le_bytes());
+            data.extend_from_slice(&deletes.bits);
+        }
+        let crc = Checksum::Crc32c.compute(&data);
+        data.extend_from_slice(&crc.to_le_bytes());
+        let mut file = File::create(&tmp)?;
+        file.write_all(&data)?;
+        file.sync_all()?;
+        Ok(staged)
+    }
+}
+
+// mark rows of a db721 file deleted, returns how many were not already
+pub fn delete_rows(filename: &str, rows: &[usize]) -> Result<usize, BuildError> {
+    let _lock = FileLock::acquire(filename)?;
+    let (staged, deleted) = stage_deletes(filename, rows, filename, None)?;
+    staged.publish()?;
+    Ok(deleted)
+}
+
+// stage the sidecar with the rows deleted, the caller locks the file. The rows are
+// those of `version` of the file, an error if it changed since, or of the file as it
+// is. The sidecar belongs to the file and to `data`, its replacement staged before if
+// not the file, and is published before it. Returns how many rows were not deleted
+// already.
+pub fn stage_deletes(
+    filename: &str,
+    rows: &[usize],
+    data: &str,
+    version: Option<FileIdentity>,
+) -> Result<(Staged, usize), BuildError> {
+    let parser = ParserBuilder::new(filename.to_string(), String::new()).build()?;
+    if version.is_some_and(|version| version != parser.identity) {
+        return Err(BuildError::FileChanged(filename.to_string()));
+    }
+    let identity = match data == filename {
+        true => parser.identity,
+        false => {
+            ParserBuilder::new(data.to_string(), String::new())
+                .build()?
+                .identity
+        }
+    };
+    let mut deletes = parser.deletes.clone();
+    let before = deletes.num_deleted();
+    for row in rows {
+        if *row >= parser.num_rows() {
+            return Err(BuildError::FormatError(format!(
+                "{} has no row {}, it has {} rows",
+                filename,
+                row,
+                parser.num_rows()
+            )));
+        }
+        deletes.delete(*row);
+    }
+    let mut versions = vec![(parser.identity, &deletes)];
+    if identity != parser.identity {
+        versions.push((identity, &deletes));
+    }
+    let staged = DeleteVector::stage(filename, &versions)?;
+    Ok((staged, deletes.num_deleted() - before))
+}
@@ -0,0 +1,418 @@
+use serde::{Deserialize, Serialize};
+
+use crate::parser::BuildError;
+use crate::types::{ColumnBlock, ColumnType, ScalarValue, STR_SIZE};
+
+fn truncated(encoding: Encoding) -> BuildError {
+    BuildError::FormatError(format!("truncated {} block", encoding.name()))
+}
+
+/*
+Encodings of the values inside a block, applied before compression.
+
+metadata["Columns"]["Column Name"]["block_stats"][i]["encoding"] is optional,
+blocks without it are "plain". Encoded blocks always have a "size".
+
+    "plain"         the layout described in types.rs
+
+    "dictionary"    only for "str" columns
+                    u32 LE number of distinct values n
+                    n values as "str"
+                    one code per value, the index of the value in the dictionary,
+                    u8 if n <= 256, u16 LE otherwise
+
+    "rle"           only for fixed-width columns other than "str"
+                    u32 LE number of runs r
+                    r values in the "plain" layout
+                    r u32 LE run lengths
+
+    "delta"         only for "int", "bigint", "date", "timestamp", "float" and
+                    "double" columns. Floats are taken by the bits of their IEEE 754
+                    representation, as a signed integer of their width, so that
+                    every value round trips, NaNs and -0.0 included.
+                    i64 LE first value
+                    i64 LE smallest difference between consecutive values m
+                    u8 width w, one of 0, 1, 2, 4, 8
+                    for every following value, its difference to the previous
+                    value minus m, as a w bytes unsigned LE integer
+*/
+
+// the most distinct values a dictionary may hold
+pub const MAX_DICTIONARY_LEN: usize = u16::MAX as usize + 1;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Encoding {
+    #[default]
+    #[serde(rename = "plain")]
+    Plain,
+    #[serde(rename = "dictionary")]
+    Dictionary,
+    #[serde(rename = "rle")]
+    Rle,
+    #[serde(rename = "delta")]
+    Delta,
+}
+
+impl Encoding {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Encoding::Plain => "plain",
+            Encoding::Dictionary => "dictionary",
+            Encoding::Rle => "rle",
+            Encoding::Delta => "delta",
+        }
+    }
+
+    pub fn applies_to(&self, ty: ColumnType) -> bool {
+        match self {
+            Encoding::Plain => true,
+            Encoding::Dictionary => ty == ColumnType::Str,
+            Encoding::Rle => RleBlock::applies_to(ty),
+            Encoding::Delta => DeltaBlock::applies_to(ty),
+        }
+    }
+
+    pub fn decode(
+        &self,
+        ty: ColumnType,
+        data: &[u8],
+        num: usize,
+    ) -> Result<ColumnBlock, BuildError> {
+        match self {
+            Encoding::Plain => ColumnBlock::decode(ty, data, num),
+            Encoding::Dictionary => Ok(ColumnBlock::Str(
+                DictionaryBlock::parse(data, num)?.decode(),
+            )),
+            Encoding::Rle => RleBlock::parse(ty, data)?.decode(num),
+            Encoding::Delta => DeltaBlock::parse(data, num)?.decode(ty, num),
+        }
+    }
+}
+
+// A dictionary encoded block of a "str" column
+#[derive(Debug, Clone, PartialEq)]
+pub struct DictionaryBlock {
+    pub dictionary: Vec<String>,
+    pub codes: Vec<u16>,
+}
+
+impl DictionaryBlock {
+    // build the dictionary of `values`, None if they have too many distinct values
+    pub fn new(values: &[&str]) -> Option<DictionaryBlock> {
+        let mut dictionary: Vec<String> = Vec::new();
+        let mut index = std::collections::HashMap::new();
+        let mut codes = Vec::with_capacity(values.len());
+        for value in values {
+            let code = *index.entry(*value).or_insert_with(|| {
+                dictionary.push(value.to_string());
+                dictionary.len() - 1
+            });
+            if code >= MAX_DICTIONARY_LEN {
+                return None;
+            }
+            codes.push(code as u16);
+        }
+        Some(DictionaryBlock { dictionary, codes })
+    }
+
+    fn code_size(dictionary_len: usize) -> usize {
+        if dictionary_len <= 256 {
+            1
+        } else {
+            2
+        }
+    }
+
+    // encoded length of a block of `num` values with `dictionary_len` distinct ones
+    pub fn encoded_len(dictionary_len: usize, num: usize) -> usize {
+        4 + dictionary_len * STR_SIZE + num * DictionaryBlock::code_size(dictionary_len)
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&(self.dictionary.len() as u32).to_le_bytes());
+        for value in &self.dictionary {
+            ScalarValue::Str(value.clone()).encode(out);
+        }
+        match DictionaryBlock::code_size(self.dictionary.len()) {
+            1 => out.extend(self.codes.iter().map(|c| *c as u8)),
+            _ => self
+                .codes
+                .iter()
+                .for_each(|c| out.extend_from_slice(&c.to_le_bytes())),
+        }
+    }
+
+    pub fn parse(data: &[u8], num: usize) -> Result<DictionaryBlock, BuildError> {
+        let truncated = || truncated(Encoding::Dictionary);
+        let len = data.get(..4).ok_or_else(truncated)?;
+        let len = u32::from_le_bytes(len.try_into().unwrap()) as usize;
+        if len > MAX_DICTIONARY_LEN {
+            return Err(BuildError::FormatError(format!(
+                "dictionary of {} values is too large",
+                len
+            )));
+        }
+        let values = data.get(4..4 + len * STR_SIZE).ok_or_else(truncated)?;
+        let dictionary = match ColumnBlock::decode(ColumnType::Str, values, len)? {
+            ColumnBlock::Str(dictionary) => dictionary,
+            _ => unreachable!(),
+        };
+        let code_size = DictionaryBlock::code_size(len);
+        let codes = data
+            .get(4 + len * STR_SIZE..4 + len * STR_SIZE + num * code_size)
+            .ok_or_else(truncated)?;
+        let codes: Vec<u16> = match code_size {
+            1 => codes.iter().map(|c| *c as u16).collect(),
+            _ => codes
+                .chunks_exact(2)
+                .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                .collect(),
+        };
+        if codes.iter().any(|c| *c as usize >= len) {
+            return Err(BuildError::FormatError(
+                "dictionary code out of range".to_string(),
+            ));
+        }
+        Ok(DictionaryBlock { dictionary, codes })
+    }
+
+    pub fn decode(&self) -> Vec<String> {
+        self.codes
+            .iter()
+            .map(|c| self.dictionary[*c as usize].clone())
+            .collect()
+    }
+}
+
+// A run-length encoded block
+#[derive(Debug, Clone, PartialEq)]
+pub struct RleBlock {
+    pub values: ColumnBlock,
+    pub lengths: Vec<u32>,
+}
+
+impl RleBlock {
+    pub fn applies_to(ty: ColumnType) -> bool {
+        ty.is_fixed_width() && ty != ColumnType::Str
+    }
+
+    // floats are compared by their bits, so that NaNs form runs too
+    fn same(a: &ScalarValue, b: &ScalarValue) -> bool {
+        match (a, b) {
+            (ScalarValue::Float(a), ScalarValue::Float(b)) => a.to_bits() == b.to_bits(),
+            (ScalarValue::Double(a), ScalarValue::Double(b)) => a.to_bits() == b.to_bits(),
+            (a, b) => a == b,
+        }
+    }
+
+    pub fn count_runs(values: &[ScalarValue]) -> usize {
+        values
+            .windows(2)
+            .filter(|w| !RleBlock::same(&w[0], &w[1]))
+            .count()
+            + !values.is_empty() as usize
+    }
+
+    pub fn encoded_len(ty: ColumnType, runs: usize) -> usize {
+        4 + runs * (ty.elem_size() + 4)
+    }
+
+    pub fn encode(values: &[ScalarValue], out: &mut Vec<u8>) {
+        let mut lengths: Vec<u32> = Vec::new();
+        let mut run_values = Vec::new();
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 && RleBlock::same(&values[i - 1], value) {
+                *lengths.last_mut().unwrap() += 1;
+            } else {
+                value.encode(&mut run_values);
+                lengths.push(1);
+            }
+        }
+        out.extend_from_slice(&(lengths.len() as u32).to_le_bytes());
+        out.extend_from_slice(&run_values);
+        lengths
+            .iter()
+            .for_each(|l| out.extend_from_slice(&l.to_le_bytes()));
+    }
+
+    pub fn parse(ty: ColumnType, data: &[u8]) -> Result<RleBlock, BuildError> {
+        let runs = data.get(..4).ok_or_else(|| truncated(Encoding::Rle))?;
+        let runs = u32::from_le_bytes(runs.try_into().unwrap()) as usize;
+        let values_len = runs * ty.elem_size();
+        let values = ColumnBlock::decode(ty, &data[4..], runs)?;
+        let lengths = data
+            .get(4 + values_len..4 + values_len + runs * 4)
+            .ok_or_else(|| truncated(Encoding::Rle))?
+            .chunks_exact(4)
+            .map(|l| u32::from_le_bytes(l.try_into().unwrap()))
+            .collect();
+        Ok(RleBlock { values, lengths })
+    }
+
+    pub fn decode(&self, num: usize) -> Result<ColumnBlock, BuildError> {
+        if self.lengths.iter().map(|l| *l as usize).sum::<usize>() != num {
+            return Err(BuildError::FormatError(format!(
+                "rle runs do not add up to {} values",
+                num
+            )));
+        }
+        fn expand<T: Clone>(values: &[T], lengths: &[u32]) -> Vec<T> {
+            values
+                .iter()
+                .zip(lengths)
+                .flat_map(|(v, l)| std::iter::repeat_n(v.clone(), *l as usize))
+                .collect()
+        }
+        let l = &self.lengths;
+        Ok(match &self.values {
+            ColumnBlock::Int(v) => ColumnBlock::Int(expand(v, l)),
+            ColumnBlock::Float(v) => ColumnBlock::Float(expand(v, l)),
+            ColumnBlock::Str(v) => ColumnBlock::Str(expand(v, l)),
+            ColumnBlock::BigInt(v) => ColumnBlock::BigInt(expand(v, l)),
+            ColumnBlock::Double(v) => ColumnBlock::Double(expand(v, l)),
+            ColumnBlock::Bool(v) => ColumnBlock::Bool(expand(v, l)),
+            ColumnBlock::Date(v) => ColumnBlock::Date(expand(v, l)),
+            ColumnBlock::Timestamp(v) => ColumnBlock::Timestamp(expand(v, l)),
+            ColumnBlock::StrList(v) => ColumnBlock::StrList(expand(v, l)),
+        })
+    }
+}
+
+// A delta encoded block of an integer column, or of the bits of a float column
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeltaBlock {
+    pub first: i64,
+    pub min_delta: i64,
+    pub width: u8,
+    pub offsets: Vec<u64>,
+}
+
+impl DeltaBlock {
+    pub fn applies_to(ty: ColumnType) -> bool {
+        matches!(
+            ty,
+            ColumnType::Int
+                | ColumnType::BigInt
+                | ColumnType::Date
+                | ColumnType::Timestamp
+                | ColumnType::Float
+                | ColumnType::Double
+        )
+    }
+
+    fn as_i64(value: &ScalarValue) -> i64 {
+        match value {
+            ScalarValue::Int(v) | ScalarValue::Date(v) => *v as i64,
+            ScalarValue::BigInt(v) | ScalarValue::Timestamp(v) => *v,
+            ScalarValue::Float(v) => v.to_bits() as i32 as i64,
+            ScalarValue::Double(v) => v.to_bits() as i64,
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn new(values: &[ScalarValue]) -> DeltaBlock {
+        let values: Vec<i64> = values.iter().map(DeltaBlock::as_i64).collect();
+        let deltas: Vec<i64> = values.windows(2).map(|w| w[1].wrapping_sub(w[0])).collect();
+        let min_delta = deltas.iter().copied().min().unwrap_or(0);
+        // the true difference to the smallest delta always fits in a u64
+        let offsets: Vec<u64> = deltas
+            .iter()
+            .map(|d| d.wrapping_sub(min_delta) as u64)
+            .collect();
+        let width = match offsets.iter().copied().max().unwrap_or(0) {
+            0 => 0,
+            1..=0xff => 1,
+            0x100..=0xffff => 2,
+            0x1_0000..=0xffff_ffff => 4,
+            _ => 8,
+        };
+        DeltaBlock {
+            first: values.first().copied().unwrap_or(0),
+            min_delta,
+            width,
+            offsets,
+        }
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        17 + self.offsets.len() * self.width as usize
+    }
+
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.first.to_le_bytes());
+        out.extend_from_slice(&self.min_delta.to_le_bytes());
+        out.push(self.width);
+        for offset in &self.offsets {
+            out.extend_from_slice(&offset.to_le_bytes()[..self.width as usize]);
+        }
+    }
+
+    pub fn parse(data: &[u8], num: usize) -> Result<DeltaBlock, BuildError> {
+        let header = data.get(..17).ok_or_else(|| truncated(Encoding::Delta))?;
+        let first = i64::from_le_bytes(header[..8].try_into().unwrap());
+        let min_delta = i64::from_le_bytes(header[8..16].try_into().unwrap());
+        let width = header[16];
+        if ![0, 1, 2, 4, 8].contains(&width) {
+            return Err(BuildError::FormatError(format!(
+                "invalid delta width {}",
+                width
+            )));
+        }
+        let count = num.saturating_sub(1);
+        let offsets = match width {
+            0 => vec![0; count],
+            w => data
+                .get(17..17 + count * w as usize)
+                .ok_or_else(|| truncated(Encoding::Delta))?
+                .chunks_exact(w as usize)
+                .map(|o| {
+                    let mut bytes = [0u8; 8];
+                    bytes[..o.len()].copy_from_slice(o);
+                    u64::from_le_bytes(bytes)
+                })
+                .collect(),
+        };
+        Ok(DeltaBlock {
+            first,
+            min_delta,
+            width,
+            offsets,
+        })
+    }
+
+    pub fn decode(&self, ty: ColumnType, num: usize) -> Result<ColumnBlock, BuildError> {
+        let mut values = Vec::with_capacity(num);
+        let mut value = self.first;
+        if num > 0 {
+            values.push(value);
+        }
+        for offset in &self.offsets {
+            value = value.wrapping_add(self.min_delta.wrapping_add(*offset as i64));
+            values.push(value);
+        }
+        Ok(match ty {
+            ColumnType::Int => ColumnBlock::Int(values.into_iter().map(|v| v as i32).collect()),
+            ColumnType::Date => ColumnBlock::Date(values.into_iter().map(|v| v as i32).collect()),
+            ColumnType::BigInt => ColumnBlock::BigInt(values),
+            ColumnType::Timestamp => ColumnBlock::Timestamp(values),
+            ColumnType::Float => ColumnBlock::Float(
+                values
+                    .into_iter()
+                    .map(|v| f32::from_bits(v as i32 as u32))
+                    .collect(),
+            ),
+            ColumnType::Double => ColumnBlock::Double(
+                values
+                    .into_iter()
+                    .map(|v| f64::from_bits(v as u64))
+                    .collect(),
+            ),
+            _ => {
+                return Err(BuildError::FormatError(format!(
+                    "delta encoding does not apply to {} columns",
+                    ty.name()
+                )))
+            }
+        })
+    }
+}
@@ -1,23 +1,1329 @@
-mod parser;
+pub mod append;
+#[cfg(feature = "arrow")]
+pub mod arrow;
+pub mod bloom;
+pub mod checksum;
+pub mod codec;
+pub mod compact;
+pub mod dataset;
+pub mod deletes;
+pub mod encoding;
+#[cfg(feature = "parquet")]
+pub mod parquet;
+pub mod parser;
+pub mod predicate;
+pub mod staging;
+#[cfg(test)]
+mod testing;
+pub mod types;
+pub mod validate;
+pub mod version;
+pub mod writer;
+
+pub use append::{Append, AppendError};
+pub use bloom::{BloomFilter, BloomFilterMeta};
+pub use checksum::{Checksum, VerifyMode};
+pub use codec::{Codec, RawSize};
+pub use compact::{CompactError, Compacted, Compaction};
+pub use dataset::{Dataset, DatasetBuilder, SchemaPolicy};
+pub use deletes::{delete_rows, stage_deletes, DeleteVector, FileIdentity};
+pub use encoding::{DeltaBlock, DictionaryBlock, Encoding, RleBlock};
+pub use parser::{BuildError, Parser, ParserBuilder};
+pub use predicate::{Operator, Predicate};
+pub use staging::{FileLock, Staged};
+pub use types::{ColumnBlock, ColumnType, ScalarValue};
+pub use validate::{validate, Location, Violation};
+pub use version::FormatVersion;
+pub use writer::{WriteError, Writer};
+
 #[cfg(test)]
 mod tests {
+    use super::testing::{farms_path, open, write_file, write_table, TmpPath};
     use super::*;
 
     #[test]
     fn it_parses() {
-        let parser = parser::ParserBuilder::new(
-            String::from("/home/polo/Polo/PostgreSQL-FDW/db721-gen/data-farms.db721"),
-            String::from("Farm")
-        )
-        //.parse()
-        //.build_index()
-        .build()
-        .unwrap();
-        //println!("{:?}", parser.column_raw.clone());
-
-        let owner = parser.data.borrow_owner();
-        let dep = parser.data.borrow_dependent();
-        println!("{:#?}",owner);
-        println!("{:#?}",dep);
+        let parser = open(&farms_path(), "Farm");
+        assert_eq!(parser.metadata.table, "Farm");
+        assert_eq!(parser.num_rows(), 6);
+        let names: Vec<_> = parser
+            .columns
+            .iter()
+            .map(|c| c.column_name.as_str())
+            .collect();
+        assert_eq!(
+            names,
+            ["farm_name", "sexes", "min_age_weeks", "max_age_weeks"]
+        );
+        assert_eq!(
+            parser.read_block("farm_name", 0).unwrap().get(0),
+            Some(ScalarValue::Str("Incubator".to_string()))
+        );
+        assert_eq!(
+            parser.read_block("max_age_weeks", 0).unwrap().get(2),
+            Some(ScalarValue::Float(156.0))
+        );
+    }
+
+    #[test]
+    fn it_round_trips_wide_types() {
+        let bigints = [i64::MIN, 0, i64::MAX].map(ScalarValue::BigInt);
+        let doubles = [-1.5, 0.25, 1e300].map(ScalarValue::Double);
+        let bools = [true, false, true].map(ScalarValue::Bool);
+        let dates = [-1, 0, 19000].map(ScalarValue::Date);
+        let timestamps = [0, 1_686_000_000_000_000, -5].map(ScalarValue::Timestamp);
+        let path = write_table("wide", "Wide", 2, |writer| {
+            writer.write_col("b", ColumnType::BigInt, &bigints).unwrap();
+            writer.write_col("d", ColumnType::Double, &doubles).unwrap();
+            writer.write_col("f", ColumnType::Bool, &bools).unwrap();
+            writer.write_col("dt", ColumnType::Date, &dates).unwrap();
+            writer
+                .write_col("ts", ColumnType::Timestamp, &timestamps)
+                .unwrap();
+        });
+
+        let parser = open(&path, "Wide");
+        assert_eq!(parser.num_blocks(), 2);
+        assert_eq!(parser.num_rows(), 3);
+        assert_eq!(dates[0].to_string(), "1969-12-31");
+        assert_eq!(dates[2].to_string(), "2022-01-08");
+        assert_eq!(timestamps[1].to_string(), "2023-06-05 21:20:00+00");
+        assert_eq!(timestamps[2].to_string(), "1969-12-31 23:59:59.999995+00");
+        for (name, values) in [
+            ("b", &bigints),
+            ("d", &doubles),
+            ("f", &bools),
+            ("dt", &dates),
+            ("ts", &timestamps),
+        ] {
+            let decoded: Vec<_> = (0..parser.num_blocks())
+                .flat_map(|b| {
+                    let block = parser.read_block(name, b).unwrap();
+                    (0..block.len()).map(move |i| block.get(i).unwrap())
+                })
+                .collect();
+            assert_eq!(&decoded, values);
+
+            let stats = parser.block_stats(name, 0).unwrap();
+            let (min, max) = if values[0] < values[1] {
+                (&values[0], &values[1])
+            } else {
+                (&values[1], &values[0])
+            };
+            assert_eq!(stats.min.as_ref(), Some(min));
+            assert_eq!(stats.max.as_ref(), Some(max));
+        }
+    }
+
+    #[test]
+    fn it_reads_and_prunes_lists() {
+        let parser = open(&farms_path(), "Farm");
+        assert_eq!(
+            parser.read_block("sexes", 0).unwrap().get(1),
+            Some(ScalarValue::StrList(vec!["FEMALE".to_string()]))
+        );
+        let male =
+            |v: &str| Predicate::new("sexes", Operator::Contains, ScalarValue::Str(v.into()));
+        assert_eq!(parser.prune(&[male("MALE")]), [0]);
+        assert!(parser.prune(&[male("ROOSTER")]).is_empty());
+        let selection = parser.filter_block(0, &[male("MALE")]).unwrap();
+        assert_eq!(selection, [true, false, false, true, true, true]);
+    }
+
+    #[test]
+    fn it_quotes_list_literals() {
+        let list =
+            |elems: &[&str]| ScalarValue::StrList(elems.iter().map(|e| e.to_string()).collect());
+        for (value, text) in [
+            (list(&[]), "{}"),
+            (list(&["a", "b"]), "{a,b}"),
+            (list(&["a,b"]), r#"{"a,b"}"#),
+            (list(&[""]), r#"{""}"#),
+            (list(&["", ""]), r#"{"",""}"#),
+            (
+                list(&[r#"say "hi""#, r"back\slash"]),
+                r#"{"say \"hi\"","back\\slash"}"#,
+            ),
+            (list(&["{x}", " y "]), r#"{"{x}"," y "}"#),
+            (list(&["NULL"]), r#"{"NULL"}"#),
+        ] {
+            assert_eq!(value.to_string(), text);
+            assert_eq!(ScalarValue::parse(ColumnType::StrList, text), Some(value));
+        }
+        // the spellings postgres accepts besides its own
+        assert_eq!(
+            ScalarValue::parse(ColumnType::StrList, r#"{ a , "b" ,c\,d}"#),
+            Some(list(&["a", "b", "c,d"]))
+        );
+        for text in [
+            "{a",
+            "{a,}",
+            "{,}",
+            r#"{"a}"#,
+            "{NULL}",
+            "{{a}}",
+            "{a}b",
+            r#"{a"b"}"#,
+        ] {
+            assert_eq!(
+                ScalarValue::parse(ColumnType::StrList, text),
+                None,
+                "{}",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn it_prunes_blocks_by_stats() {
+        let ids: Vec<_> = (0..100).map(ScalarValue::Int).collect();
+        let path = write_table("prune", "Prune", 10, |writer| {
+            writer.write_col("id", ColumnType::Int, &ids).unwrap();
+        });
+        let parser = open(&path, "Prune");
+
+        let pred = |op, v| Predicate::new("id", op, ScalarValue::BigInt(v));
+        assert_eq!(parser.prune(&[pred(Operator::Eq, 42)]), [4]);
+        assert_eq!(parser.prune(&[pred(Operator::Lt, 10)]), [0]);
+        assert_eq!(parser.prune(&[pred(Operator::Ge, 89)]), [8, 9]);
+        assert_eq!(
+            parser.prune(&[pred(Operator::Gt, 15), pred(Operator::Le, 30)]),
+            [1, 2, 3]
+        );
+        let in_list = Predicate::in_list("id", vec![ScalarValue::Int(3), ScalarValue::Int(77)]);
+        assert_eq!(parser.prune(std::slice::from_ref(&in_list)), [0, 7]);
+        let selection = parser.filter_block(7, &[in_list]).unwrap();
+        assert_eq!(selection.iter().filter(|s| **s).count(), 1);
+        assert!(selection[7]);
+    }
+
+    #[test]
+    fn it_keeps_blocks_with_nans() {
+        let values = [1.0, f64::NAN, 2.0, 3.0].map(ScalarValue::Double);
+        let path = write_table("nan", "Nan", 2, |writer| {
+            writer.write_col("x", ColumnType::Double, &values).unwrap();
+        });
+        let parser = open(&path, "Nan");
+        // postgres orders NaN above 5
+        let gt = Predicate::new("x", Operator::Gt, ScalarValue::Double(5.0));
+        assert_eq!(parser.prune(std::slice::from_ref(&gt)), [0]);
+        assert_eq!(parser.filter_block(0, &[gt]).unwrap(), [false, true]);
+        let lt = Predicate::new("x", Operator::Lt, ScalarValue::Double(1.5));
+        assert_eq!(parser.prune(&[lt]), [0]);
+        assert_eq!(validate(&path).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn it_scans_datasets() {
+        let dir = TmpPath::dir("dataset");
+        let write = |name: &str, ty, ids: Vec<ScalarValue>| {
+            write_file(&dir.join(name), "Day", 10, |writer| {
+                writer.write_col("id", ty, &ids).unwrap();
+            });
+        };
+        write(
+            "day-1.db721",
+            ColumnType::Int,
+            (0..20).map(ScalarValue::Int).collect(),
+        );
+        write(
+            "day-2.db721",
+            ColumnType::Int,
+            (20..50).map(ScalarValue::Int).collect(),
+        );
+        std::fs::write(dir.join("notes.txt"), "not a table").unwrap();
+        let dataset = DatasetBuilder::new(String::from("Day"))
+            .directory(&dir)
+            .build()
+            .unwrap();
+
+        assert_eq!(dataset.files.len(), 2);
+        assert_eq!(dataset.num_rows(), 50);
+        assert_eq!(dataset.num_blocks(), 5);
+        let pred = Predicate::new("id", Operator::Ge, ScalarValue::BigInt(25));
+        assert_eq!(dataset.prune_files(std::slice::from_ref(&pred)), [1]);
+        assert_eq!(
+            dataset.prune(std::slice::from_ref(&pred)),
+            [(1, 0), (1, 1), (1, 2)]
+        );
+        let columns = [String::from("id")];
+        let rows: Vec<_> = dataset
+            .rows(&columns, &[pred])
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows.len(), 25);
+        assert_eq!(rows[0], [Some(ScalarValue::Int(25))]);
+        let pattern = dir.join("day-2.*");
+        let dataset = DatasetBuilder::new(String::new())
+            .pattern(&pattern)
+            .build()
+            .unwrap();
+        assert_eq!((dataset.tablename.as_str(), dataset.num_rows()), ("", 30));
+
+        write(
+            "day-3.db721",
+            ColumnType::Float,
+            vec![ScalarValue::Float(1.0)],
+        );
+        let result = DatasetBuilder::new(String::from("Day"))
+            .directory(&dir)
+            .build();
+        assert!(
+            matches!(result, Err(BuildError::SchemaMismatch(path, _)) if path.ends_with("day-3.db721"))
+        );
+    }
+
+    #[test]
+    fn it_prunes_partitions() {
+        let dir = TmpPath::dir("partitions");
+        let write = |partition: &str, ids: Vec<ScalarValue>| {
+            std::fs::create_dir_all(dir.join(partition)).unwrap();
+            let path = dir.join(&format!("{}/part-0.db721", partition));
+            write_file(&path, "Chicken", 10, |writer| {
+                writer.write_col("id", ColumnType::Int, &ids).unwrap();
+            });
+        };
+        write(
+            "farm_name=Cheep%20Birds/year=2022",
+            (0..10).map(ScalarValue::Int).collect(),
+        );
+        write(
+            "farm_name=Incubator/year=2023",
+            (10..30).map(ScalarValue::Int).collect(),
+        );
+        // never opened once pruned by its partition values
+        std::fs::create_dir_all(dir.join("farm_name=Broken/year=2023")).unwrap();
+        std::fs::write(dir.join("farm_name=Broken/year=2023/part-0.db721"), "").unwrap();
+        let mut builder = DatasetBuilder::new(String::from("Chicken"));
+        builder.directory(&dir);
+        assert_eq!(builder.partition_columns().unwrap(), ["farm_name", "year"]);
+        assert!(builder.build().is_err());
+
+        let not_broken = Predicate::in_list(
+            "farm_name",
+            vec![
+                ScalarValue::Str("Cheep Birds".into()),
+                ScalarValue::Str("Incubator".into()),
+            ],
+        );
+        let dataset = builder
+            .predicates(std::slice::from_ref(&not_broken))
+            .build()
+            .unwrap();
+        assert_eq!(dataset.num_rows(), 30);
+        assert_eq!(dataset.column_type("year"), Some(ColumnType::Str));
+        assert_eq!(dataset.partition_value(0, "farm_name"), Some("Cheep Birds"));
+        let year = Predicate::new("year", Operator::Ge, ScalarValue::Int(2023));
+        assert_eq!(dataset.prune_files(std::slice::from_ref(&year)), [1]);
+        let columns = [String::from("farm_name"), String::from("id")];
+        let rows: Vec<_> = dataset
+            .rows(&columns, &[year])
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(rows.len(), 20);
+        assert_eq!(
+            rows[0],
+            [
+                Some(ScalarValue::Str("Incubator".into())),
+                Some(ScalarValue::Int(10))
+            ]
+        );
+    }
+
+    #[test]
+    fn it_reads_partitions_below_relative_directories() {
+        let dir = TmpPath::dir("relative");
+        std::fs::create_dir_all(dir.join("data/year=2023")).unwrap();
+        write_file(
+            &dir.join("data/year=2023/part-0.db721"),
+            "T",
+            10,
+            |writer| {
+                writer
+                    .write_col("id", ColumnType::Int, &[ScalarValue::Int(1)])
+                    .unwrap();
+            },
+        );
+        // the directory relative to the working directory of the test
+        let depth = std::env::current_dir().unwrap().components().count() - 1;
+        let root = format!(
+            "{}{}",
+            "../".repeat(depth),
+            dir.join("data").trim_start_matches('/')
+        );
+        for directory in [format!("./{}", root), format!("{}//", root)] {
+            let dataset = DatasetBuilder::new(String::from("T"))
+                .directory(&directory)
+                .build()
+                .unwrap();
+            assert_eq!(dataset.partition_columns, ["year"], "{}", directory);
+            assert_eq!(dataset.partition_value(0, "year"), Some("2023"));
+        }
+        assert_eq!(
+            dataset::partitions("./data", "data/k=v/a.db721"),
+            [(String::from("k"), String::from("v"))]
+        );
+        assert_eq!(
+            dataset::partitions("data/k=v", "data/k=v/a.db721"),
+            Vec::<(String, String)>::new()
+        );
+    }
+
+    #[test]
+    fn it_merges_schemas() {
+        let dir = TmpPath::dir("schemas");
+        let write = |name: &str, columns: &[(&str, ColumnType, Vec<ScalarValue>)]| {
+            write_file(&dir.join(name), "Day", 10, |writer| {
+                for (column, ty, values) in columns {
+                    writer.write_col(column, *ty, values).unwrap();
+                }
+            });
+        };
+        let ints = |range: std::ops::Range<i32>| range.map(ScalarValue::Int).collect::<Vec<_>>();
+        write("day-1.db721", &[("id", ColumnType::Int, ints(0..5))]);
+        let big_ids = (5..10).map(ScalarValue::BigInt).collect();
+        let notes = vec![ScalarValue::Str("WOODY".into()); 5];
+        write(
+            "day-2.db721",
+            &[
+                ("id", ColumnType::BigInt, big_ids),
+                ("notes", ColumnType::Str, notes),
+            ],
+        );
+        let mut builder = DatasetBuilder::new(String::from("Day"));
+        builder.directory(&dir);
+        assert!(matches!(
+            builder.build(),
+            Err(BuildError::SchemaMismatch(..))
+        ));
+
+        let dataset = builder.schema(SchemaPolicy::Merge).build().unwrap();
+        assert_eq!(
+            dataset.columns,
+            [
+                (String::from("id"), ColumnType::BigInt),
+                (String::from("notes"), ColumnType::Str)
+            ]
+        );
+        let columns = [String::from("id"), String::from("notes")];
+        let rows: Vec<_> = dataset.rows(&columns, &[]).map(Result::unwrap).collect();
+        assert_eq!(rows[0], [Some(ScalarValue::BigInt(0)), None]);
+        assert_eq!(rows[9][1], Some(ScalarValue::Str("WOODY".into())));
+        // NULL never matches, the declared default does
+        let woody = Predicate::new("notes", Operator::Eq, ScalarValue::Str("WOODY".into()));
+        assert_eq!(dataset.prune_files(std::slice::from_ref(&woody)), [1]);
+        let dataset = builder.default_value("notes", "WOODY").build().unwrap();
+        assert_eq!(dataset.prune_files(&[woody]), [0, 1]);
+        assert_eq!(
+            dataset.file_constant(0, "notes"),
+            Some(Some(ScalarValue::Str("WOODY".into())))
+        );
+
+        write(
+            "day-3.db721",
+            &[("id", ColumnType::Str, vec![ScalarValue::Str("x".into())])],
+        );
+        let result = builder.build();
+        assert!(
+            matches!(result, Err(BuildError::SchemaMismatch(path, _)) if path.ends_with("day-3.db721"))
+        );
+    }
+
+    #[test]
+    fn it_appends_rows() {
+        let dir = TmpPath::dir("append");
+        std::fs::create_dir_all(dir.join("farm_name=Incubator")).unwrap();
+        let filename = dir.join("farm_name=Incubator/part-0.db721");
+        let ids: Vec<_> = (0..15).map(ScalarValue::Int).collect();
+        write_file(&filename, "Chicken", 10, |writer| {
+            writer.checksum(Some(Checksum::Crc32c));
+            writer.write_col("id", ColumnType::Int, &ids).unwrap();
+        });
+        let names = [String::from("id"), String::from("farm_name")];
+        let row = |id: i64, farm: &str| {
+            [
+                Some(ScalarValue::BigInt(id)),
+                Some(ScalarValue::Str(farm.into())),
+            ]
+        };
+
+        let parser = open(&filename, "Chicken");
+        let mut append = Append::file(&filename, &parser);
+        drop(parser);
+        for id in 15..20 {
+            append.push(&names[..1], &row(id, "")[..1]).unwrap();
+        }
+        assert!(append.push(&names[..1], &[None]).is_err());
+        assert_eq!(append.commit("0").unwrap(), std::slice::from_ref(&filename));
+        let parser = ParserBuilder::new(filename.clone(), String::from("Chicken"))
+            .verify(VerifyMode::Strict)
+            .build()
+            .unwrap();
+        assert_eq!((parser.num_rows(), parser.num_blocks()), (20, 3));
+        let stats = parser.block_stats("id", 2).unwrap();
+        assert_eq!(
+            (stats.num, stats.min.clone(), stats.max.clone()),
+            (5, Some(ScalarValue::Int(15)), Some(ScalarValue::Int(19)))
+        );
+
+        let dataset = DatasetBuilder::new(String::from("Chicken"))
+            .directory(&dir)
+            .build()
+            .unwrap();
+        let mut append = Append::directory(&dir, &dataset, 10);
+        append.push(&names, &row(20, "Cheep/Birds")).unwrap();
+        append.push(&names, &row(21, "Incubator")).unwrap();
+        assert!(append
+            .push(&[String::from("weight")], &[Some(ScalarValue::Int(1))])
+            .is_err());
+        assert_eq!(append.commit("1").unwrap().len(), 2);
+        let dataset = DatasetBuilder::new(String::from("Chicken"))
+            .directory(&dir)
+            .build()
+            .unwrap();
+        assert_eq!((dataset.files.len(), dataset.num_rows()), (3, 22));
+        assert_eq!(dataset.partition_value(0, "farm_name"), Some("Cheep/Birds"));
+    }
+
+    #[test]
+    fn it_honours_delete_vectors() {
+        let path = TmpPath::file("deletes");
+        let write = |num: i32| {
+            let ids: Vec<_> = (0..num).map(ScalarValue::Int).collect();
+            write_file(&path, "Chicken", 10, |writer| {
+                writer.write_col("id", ColumnType::Int, &ids).unwrap();
+            });
+        };
+        write(25);
+        assert_eq!(delete_rows(&path, &[3, 12, 24]).unwrap(), 3);
+        assert_eq!(delete_rows(&path, &[3]).unwrap(), 0);
+        assert!(delete_rows(&path, &[25]).is_err());
+        let parser = open(&path, "Chicken");
+        assert_eq!((parser.num_rows(), parser.num_live_rows()), (25, 22));
+        assert_eq!(parser.block_num_deleted(1), 1);
+        let selection = parser.filter_block(1, &[]).unwrap();
+        assert!(!selection[2] && selection[3]);
+        let ge = Predicate::new("id", Operator::Ge, ScalarValue::Int(20));
+        let selection = parser.filter_block(2, &[ge]).unwrap();
+        assert_eq!(selection, [true, true, true, true, false]);
+
+        // an append rewrites the file, the sidecar keeps the deletes of both versions
+        let old = std::fs::read(&*path).unwrap();
+        let mut append = Append::file(&path, &parser);
+        append
+            .push(&[String::from("id")], &[Some(ScalarValue::Int(25))])
+            .unwrap();
+        append.commit("0").unwrap();
+        let parser = open(&path, "Chicken");
+        assert_eq!((parser.num_rows(), parser.num_live_rows()), (26, 23));
+        // as read before the data is published
+        let new = std::fs::read(&*path).unwrap();
+        std::fs::write(&*path, &old).unwrap();
+        let parser = open(&path, "Chicken");
+        assert_eq!((parser.num_rows(), parser.num_live_rows()), (25, 22));
+        std::fs::write(&*path, &new).unwrap();
+        let parser = open(&path, "Chicken");
+
+        // rows read from the version before the append
+        let version = parser.identity;
+        assert_eq!(version.to_string().parse(), Ok(version));
+        let stale = FileIdentity {
+            len: version.len - 1,
+            ..version
+        };
+        assert!(matches!(
+            stage_deletes(&path, &[0], &path, Some(stale)),
+            Err(BuildError::FileChanged(file)) if file == *path
+        ));
+        let (staged, deleted) = stage_deletes(&path, &[0], &path, Some(version)).unwrap();
+        assert_eq!(deleted, 1);
+        drop(staged);
+
+        // a sidecar without the version of the file, the same rows could write the
+        // old version again
+        write(24);
+        let result = ParserBuilder::new(path.to_string(), String::from("Chicken")).build();
+        assert!(matches!(result, Err(BuildError::FormatError(_))));
+    }
+
+    #[test]
+    fn it_undoes_failed_publishes() {
+        let dir = TmpPath::dir("publish");
+        let (old, new, missing) = (dir.join("old"), dir.join("new"), dir.join("missing"));
+        std::fs::write(&old, "old").unwrap();
+        let mut staged = Staged::default();
+        for (path, data) in [(&new, "new"), (&old, "replaced")] {
+            let tmp = staging::tmp_path(path);
+            std::fs::write(&tmp, data).unwrap();
+            staged.push(tmp, path.clone());
+        }
+        // the rename of a temporary file that is gone fails after the others
+        staged.push(staging::tmp_path(&missing), missing.clone());
+        assert!(staged.publish().is_err());
+        assert_eq!(std::fs::read_to_string(&old).unwrap(), "old");
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["old"]);
+
+        let mut staged = Staged::default();
+        let tmp = staging::tmp_path(&new);
+        std::fs::write(&tmp, "new").unwrap();
+        staged.push(tmp, new.clone());
+        staged.remove(old.clone());
+        assert_eq!(staged.publish().unwrap(), [new.clone(), old.clone()]);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn it_recovers_crashed_publishes() {
+        let dir = TmpPath::dir("recover");
+        let (table, new) = (dir.join("table.db721"), dir.join("part-1.db721"));
+        let write = |path: &str, num: i32| {
+            let ids: Vec<_> = (0..num).map(ScalarValue::Int).collect();
+            write_file(path, "T", 10, |writer| {
+                writer.write_col("id", ColumnType::Int, &ids).unwrap();
+            });
+        };
+        let backup = dir.join(".table.db721.backup");
+        // a publish replacing table.db721 and adding part-1.db721 that stopped before
+        // its commit, the removal of the primary journal
+        let crash = |primary: bool| {
+            write(&table, 2);
+            std::fs::hard_link(&table, &backup).unwrap();
+            write(&dir.join(".table.db721.tmp"), 3);
+            std::fs::rename(dir.join(".table.db721.tmp"), &table).unwrap();
+            write(&new, 1);
+            let journals = [
+                dir.join(".table.db721.journal"),
+                dir.join(".part-1.db721.journal"),
+            ];
+            let journal = serde_json::json!({
+                "id": "crash",
+                "primary": journals[0],
+                "journals": journals,
+                "entries": [
+                    {"path": table, "tmp": dir.join(".table.db721.tmp"), "backup": backup},
+                    {"path": new, "tmp": dir.join(".part-1.db721.tmp"), "backup": null},
+                ],
+            });
+            for path in &journals[usize::from(!primary)..] {
+                std::fs::write(path, journal.to_string()).unwrap();
+            }
+        };
+
+        crash(true);
+        let read = || {
+            let dataset = DatasetBuilder::new(String::from("T"))
+                .directory(&dir)
+                .build()
+                .unwrap();
+            (dataset.files.len(), dataset.num_rows())
+        };
+        // readers change nothing, they read the files as the crash left them
+        assert_eq!(read(), (2, 4));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 5);
+        assert!(staging::recover_dir(dir.as_ref()).unwrap());
+        assert_eq!(read(), (1, 2));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // a writer taking the lock of the file recovers it
+        std::fs::remove_file(&table).unwrap();
+        crash(true);
+        drop(FileLock::acquire(&table).unwrap());
+        assert_eq!(open(&table, "T").num_rows(), 2);
+        assert!(!std::path::Path::new(&new).exists());
+
+        // once the primary journal is gone the publish is committed
+        std::fs::remove_file(&table).unwrap();
+        crash(false);
+        assert_eq!(open(&table, "T").num_rows(), 3);
+        assert!(staging::recover(&new).unwrap());
+        assert!(!staging::recover(&new).unwrap());
+        let mut names: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, ["part-1.db721", "table.db721", "table.db721.lock"]);
+    }
+
+    #[test]
+    fn it_compacts_files() {
+        let files: Vec<_> = (0..2)
+            .map(|i| {
+                let ids: Vec<_> = (0..7).map(|id| ScalarValue::Int(i * 7 + id)).collect();
+                write_table(&format!("compact-{}", i + 1), "Chicken", 3, |writer| {
+                    writer.write_col("id", ColumnType::Int, &ids).unwrap();
+                })
+            })
+            .collect();
+        let paths: Vec<_> = files.iter().map(|path| path.to_string()).collect();
+        delete_rows(&paths[0], &[0, 5]).unwrap();
+        delete_rows(&paths[1], &[6]).unwrap();
+        let stale = std::fs::read(DeleteVector::path(&paths[0])).unwrap();
+        let old = std::fs::read(&paths[0]).unwrap();
+        let compacted = Compaction::new(&paths, &paths[0])
+            .max_values_per_block(5)
+            .run()
+            .unwrap();
+        assert_eq!(
+            compacted,
+            Compacted {
+                rows: 11,
+                blocks: 3,
+                deleted: 3
+            }
+        );
+        let parser = open(&paths[0], "Chicken");
+        let ids: Vec<_> = (0..parser.num_blocks())
+            .flat_map(|block| {
+                let values = parser.read_block("id", block).unwrap();
+                (0..values.len()).map(move |row| values.get(row).unwrap())
+            })
+            .collect();
+        let expected: Vec<_> = [1, 2, 3, 4, 6]
+            .into_iter()
+            .chain(7..13)
+            .map(ScalarValue::Int)
+            .collect();
+        assert_eq!(ids, expected);
+        assert_eq!(parser.block_stats("id", 2).unwrap().num, 1);
+        assert!(parser.deletes.is_empty());
+        assert!(!std::path::Path::new(&paths[1]).exists());
+        assert!(!std::path::Path::new(&DeleteVector::path(&paths[1])).exists());
+        // the sidecar still holds the deletes of the old version, for its readers
+        let new = std::fs::read(&paths[0]).unwrap();
+        std::fs::write(&paths[0], &old).unwrap();
+        let parser = open(&paths[0], "Chicken");
+        assert_eq!((parser.num_rows(), parser.num_live_rows()), (7, 5));
+        std::fs::write(&paths[0], &new).unwrap();
+        // the old sidecar alone does not apply to the compacted file
+        std::fs::write(DeleteVector::path(&paths[0]), &stale).unwrap();
+        let result = ParserBuilder::new(paths[0].clone(), String::from("Chicken")).build();
+        assert!(matches!(result, Err(BuildError::FormatError(_))));
+
+        // a compaction that fails to publish leaves the files as they were
+        let dir = TmpPath::dir("compact-undo");
+        let (first, second) = (dir.join("part-0.db721"), dir.join("part-1.db721"));
+        for (path, ids) in [(&first, 0..4), (&second, 4..8)] {
+            let ids: Vec<_> = ids.map(ScalarValue::Int).collect();
+            write_file(path, "Chicken", 3, |writer| {
+                writer.write_col("id", ColumnType::Int, &ids).unwrap();
+            });
+        }
+        let before: Vec<_> = [&first, &second]
+            .iter()
+            .map(|path| std::fs::read(path).unwrap())
+            .collect();
+        // the sidecar of the second file cannot be removed, after the output is in place
+        std::fs::create_dir(DeleteVector::path(&second)).unwrap();
+        let inputs = [first.clone(), second.clone()];
+        assert!(Compaction::new(&inputs, &first).run().is_err());
+        std::fs::remove_dir(DeleteVector::path(&second)).unwrap();
+        let after: Vec<_> = [&first, &second]
+            .iter()
+            .map(|path| std::fs::read(path).unwrap())
+            .collect();
+        assert_eq!(after, before);
+        let dataset = DatasetBuilder::new(String::from("Chicken"))
+            .directory(&dir)
+            .build()
+            .unwrap();
+        assert_eq!(dataset.num_rows(), 8);
+    }
+
+    #[test]
+    fn it_limits_decompressed_blocks() {
+        let data = vec![0u8; 1000];
+        for codec in [Codec::Lz4, Codec::Zstd] {
+            let compressed = codec.compress(&data).unwrap();
+            let exact = codec.decompress(&compressed, RawSize::Exact(1000)).unwrap();
+            assert_eq!(exact.as_ref(), data.as_slice());
+            assert!(codec.decompress(&compressed, RawSize::Exact(999)).is_err());
+            assert!(codec.decompress(&compressed, RawSize::AtMost(100)).is_err());
+        }
+        // a length prefix claiming 4 GiB is not allocated
+        let forged = [0xff, 0xff, 0xff, 0xff, 0x00];
+        assert!(Codec::Lz4
+            .decompress(&forged, RawSize::AtMost(1000))
+            .is_err());
+    }
+
+    #[test]
+    fn it_round_trips_compressed_blocks() {
+        let sexes: Vec<_> = (0..2500)
+            .map(|i| ScalarValue::Str(if i % 3 == 0 { "MALE" } else { "FEMALE" }.into()))
+            .collect();
+        let lists: Vec<_> = (0..2500)
+            .map(|i| ScalarValue::StrList(vec!["x".repeat(i % 5); i % 3]))
+            .collect();
+        // random-looking floats do not compress, those blocks are kept as is
+        let weights: Vec<_> = (0..2500u32)
+            .map(|i| ScalarValue::Float(f32::from_bits(i.wrapping_mul(2654435761) >> 2)))
+            .collect();
+        let path = write_table("codec", "Chicken", 1000, |writer| {
+            writer.compression(Codec::Lz4);
+            writer.write_col("sex", ColumnType::Str, &sexes).unwrap();
+            writer.compression(Codec::Zstd);
+            writer
+                .write_col("lists", ColumnType::StrList, &lists)
+                .unwrap();
+            writer
+                .write_col("weight", ColumnType::Float, &weights)
+                .unwrap();
+            writer.compression(Codec::None);
+            writer.write_col("plain", ColumnType::Str, &sexes).unwrap();
+        });
+
+        let parser = open(&path, "Chicken");
+        let sex = parser.column_meta("sex").unwrap();
+        assert!(sex.len() * 10 < parser.column_meta("plain").unwrap().len());
+        assert_eq!(sex.block_codecs, [Codec::Lz4; 3]);
+        assert_eq!(
+            parser.column_meta("weight").unwrap().block_codecs,
+            [Codec::None; 3]
+        );
+        for (name, values) in [("sex", &sexes), ("lists", &lists), ("weight", &weights)] {
+            let decoded: Vec<_> = (0..parser.num_blocks())
+                .flat_map(|b| {
+                    let block = parser.read_block(name, b).unwrap();
+                    (0..block.len()).map(move |i| block.get(i).unwrap())
+                })
+                .collect();
+            assert_eq!(&decoded, values);
+        }
+    }
+
+    #[test]
+    fn it_filters_dictionary_codes() {
+        let farms = ["Incubator", "Eggscellent", "Cheep Birds"];
+        let farm_names: Vec<_> = (0..1000)
+            .map(|i| ScalarValue::Str(farms[i / 250 % 3].to_string()))
+            .collect();
+        let ids: Vec<_> = (0..1000).map(ScalarValue::Int).collect();
+        let path = write_table("dictionary", "Chicken", 400, |writer| {
+            writer.choose_encoding(true).compression(Codec::Lz4);
+            writer
+                .write_col("farm_name", ColumnType::Str, &farm_names)
+                .unwrap();
+            writer
+                .write_col("identifier", ColumnType::Int, &ids)
+                .unwrap();
+        });
+
+        let parser = open(&path, "Chicken");
+        let meta = parser.column_meta("farm_name").unwrap();
+        assert_eq!(meta.block_encodings, [Encoding::Dictionary; 3]);
+        let dictionary = parser.read_dictionary("farm_name", 0).unwrap().unwrap();
+        assert_eq!(dictionary.dictionary, ["Incubator", "Eggscellent"]);
+        assert!(parser.read_dictionary("identifier", 0).unwrap().is_none());
+
+        let decoded: Vec<_> = (0..parser.num_blocks())
+            .flat_map(|b| {
+                let block = parser.read_block("farm_name", b).unwrap();
+                (0..block.len()).map(move |i| block.get(i).unwrap())
+            })
+            .collect();
+        assert_eq!(decoded, farm_names);
+
+        let eq = Predicate::new(
+            "farm_name",
+            Operator::Eq,
+            ScalarValue::Str("Eggscellent".into()),
+        );
+        let selection = parser.filter_block(1, &[eq]).unwrap();
+        // rows 400..500 of the file
+        assert_eq!(selection.iter().filter(|s| **s).count(), 100);
+        assert!(selection[99] && !selection[100]);
+        let in_list = Predicate::in_list(
+            "farm_name",
+            vec![
+                ScalarValue::Str("Incubator".into()),
+                ScalarValue::Str("Nowhere".into()),
+            ],
+        );
+        let selection = parser.filter_block(2, &[in_list]).unwrap();
+        assert_eq!(selection.iter().filter(|s| **s).count(), 200);
+        let missing = Predicate::new(
+            "farm_name",
+            Operator::Eq,
+            ScalarValue::Str("Nowhere".into()),
+        );
+        let selection = parser.filter_block(2, &[missing]).unwrap();
+        assert!(!selection.iter().any(|s| *s));
+    }
+
+    #[test]
+    fn it_delta_encodes_floats() {
+        let floats = [1.5, -0.0, 0.0, f32::NAN, -f32::NAN, f32::INFINITY, f32::MIN];
+        let doubles = [1e300, -0.0, f64::NAN, f64::NEG_INFINITY, f64::MIN_POSITIVE];
+        let values: Vec<_> = floats.iter().map(|v| ScalarValue::Float(*v)).collect();
+        let delta = DeltaBlock::new(&values);
+        let mut data = Vec::new();
+        delta.encode(&mut data);
+        let decoded = DeltaBlock::parse(&data, floats.len())
+            .unwrap()
+            .decode(ColumnType::Float, floats.len())
+            .unwrap();
+        let bits: Vec<_> = floats.iter().map(|v| v.to_bits()).collect();
+        assert!(matches!(decoded, ColumnBlock::Float(v)
+            if v.iter().map(|v| v.to_bits()).collect::<Vec<_>>() == bits));
+        let values: Vec<_> = doubles.iter().map(|v| ScalarValue::Double(*v)).collect();
+        let delta = DeltaBlock::new(&values);
+        let mut data = Vec::new();
+        delta.encode(&mut data);
+        let decoded = DeltaBlock::parse(&data, doubles.len())
+            .unwrap()
+            .decode(ColumnType::Double, doubles.len())
+            .unwrap();
+        let bits: Vec<_> = doubles.iter().map(|v| v.to_bits()).collect();
+        assert!(matches!(decoded, ColumnBlock::Double(v)
+            if v.iter().map(|v| v.to_bits()).collect::<Vec<_>>() == bits));
+
+        // evenly spaced within a power of two, the bits grow by the same step
+        let weights: Vec<_> = (0..2500)
+            .map(|i| ScalarValue::Double(1024.0 + i as f64 * 0.125))
+            .collect();
+        let path = write_table("delta-floats", "Chicken", 1000, |writer| {
+            writer.choose_encoding(true);
+            writer
+                .write_col("weight_g", ColumnType::Double, &weights)
+                .unwrap();
+        });
+        let parser = open(&path, "Chicken");
+        let meta = parser.column_meta("weight_g").unwrap();
+        assert_eq!(meta.block_encodings, [Encoding::Delta; 3]);
+        assert!(meta.len() < 100);
+        let block = parser.read_block("weight_g", 2).unwrap();
+        assert_eq!(block.get(499), weights.last().cloned());
+    }
+
+    #[test]
+    fn it_chooses_run_length_and_delta_encodings() {
+        let ids: Vec<_> = (1..=2500).map(ScalarValue::Int).collect();
+        let ages: Vec<_> = (0..2500)
+            .map(|i| ScalarValue::Float((i / 100) as f32 * 0.5))
+            .collect();
+        let jitter: Vec<_> = (0..2500)
+            .map(|i| ScalarValue::BigInt(i64::MAX - 3 * i + i % 7))
+            .collect();
+        let noise: Vec<_> = (0..2500u32)
+            .map(|i| ScalarValue::Int(i.wrapping_mul(2654435761) as i32))
+            .collect();
+        let path = write_table("rle", "Chicken", 1000, |writer| {
+            writer.choose_encoding(true);
+            writer
+                .write_col("identifier", ColumnType::Int, &ids)
+                .unwrap();
+            writer
+                .write_col("age_weeks", ColumnType::Float, &ages)
+                .unwrap();
+            writer
+                .write_col("jitter", ColumnType::BigInt, &jitter)
+                .unwrap();
+            writer.write_col("noise", ColumnType::Int, &noise).unwrap();
+        });
+
+        let parser = open(&path, "Chicken");
+        let encodings = |name| parser.column_meta(name).unwrap().block_encodings.clone();
+        assert_eq!(encodings("identifier"), [Encoding::Delta; 3]);
+        assert_eq!(encodings("age_weeks"), [Encoding::Rle; 3]);
+        assert_eq!(encodings("jitter"), [Encoding::Delta; 3]);
+        assert_eq!(encodings("noise"), [Encoding::Plain; 3]);
+        assert!(parser.column_meta("identifier").unwrap().len() < 100);
+        for (name, values) in [
+            ("identifier", &ids),
+            ("age_weeks", &ages),
+            ("jitter", &jitter),
+            ("noise", &noise),
+        ] {
+            let decoded: Vec<_> = (0..parser.num_blocks())
+                .flat_map(|b| {
+                    let block = parser.read_block(name, b).unwrap();
+                    (0..block.len()).map(move |i| block.get(i).unwrap())
+                })
+                .collect();
+            assert_eq!(&decoded, values);
+        }
+
+        let runs = parser.read_runs("age_weeks", 1).unwrap().unwrap();
+        assert_eq!(runs.lengths, [100; 10]);
+        let ge = Predicate::new("age_weeks", Operator::Ge, ScalarValue::Double(7.0));
+        let selection = parser.filter_block(1, &[ge]).unwrap();
+        // ages 5.0..10.0 in block 1, 7.0 starts at row 400
+        assert!(!selection[399] && selection[400] && selection[999]);
+    }
+
+    #[test]
+    fn it_prunes_blocks_by_bloom_filters() {
+        // every block spans "Farm 00".."Farm 99", min/max cannot prune anything
+        let farm_names: Vec<_> = (0..1000)
+            .map(|i| {
+                let farm = if i % 100 == 0 {
+                    0
+                } else if i % 100 == 1 {
+                    99
+                } else {
+                    i / 100 * 10 + i % 2
+                };
+                ScalarValue::Str(format!("Farm {:02}", farm))
+            })
+            .collect();
+        let ids: Vec<_> = (0..1000).map(ScalarValue::Int).collect();
+        let path = write_table("bloom", "Chicken", 100, |writer| {
+            writer.bloom_filter(true).compression(Codec::Zstd);
+            writer
+                .write_col("farm_name", ColumnType::Str, &farm_names)
+                .unwrap();
+            writer
+                .write_col("identifier", ColumnType::Int, &ids)
+                .unwrap();
+        });
+
+        let parser = open(&path, "Chicken");
+        let farm = |n: usize| ScalarValue::Str(format!("Farm {:02}", n));
+        let eq = Predicate::new("farm_name", Operator::Eq, farm(31));
+        assert_eq!(parser.prune(&[eq]), [3]);
+        let in_list = Predicate::in_list("farm_name", vec![farm(50), farm(71), farm(22)]);
+        assert_eq!(parser.prune(&[in_list]), [5, 7]);
+        let eq = Predicate::new("farm_name", Operator::Eq, farm(0));
+        assert_eq!(parser.prune(&[eq]).len(), 10);
+        // a wider type that holds the same value still finds it
+        let id = Predicate::new("identifier", Operator::Eq, ScalarValue::Double(123.0));
+        assert_eq!(parser.prune(&[id]), [1]);
+        let lt = Predicate::new("farm_name", Operator::Lt, farm(1));
+        assert_eq!(parser.prune(&[lt]).len(), 10);
+    }
+
+    #[test]
+    fn it_verifies_checksums() {
+        let ids: Vec<_> = (0..30).map(ScalarValue::Int).collect();
+        let path = write_table("checksum", "Chicken", 10, |writer| {
+            // plain blocks of 40 bytes after the magic bytes
+            writer
+                .checksum(Some(Checksum::Crc32c))
+                .choose_encoding(false);
+            writer
+                .write_col("identifier", ColumnType::Int, &ids)
+                .unwrap();
+        });
+        let mut data = std::fs::read(&path).unwrap();
+
+        // the corruption is only found when the block is read
+        data[4 * 15] ^= 0x10;
+        std::fs::write(&path, &data).unwrap();
+        let parser = ParserBuilder::new(path.to_string(), String::from("Chicken"))
+            .verify(VerifyMode::Strict)
+            .build()
+            .unwrap();
+        assert_eq!(parser.read_block("identifier", 0).unwrap().len(), 10);
+        assert!(matches!(
+            parser.read_block("identifier", 1),
+            Err(BuildError::ChecksumMismatch(c, 1)) if c == "identifier"
+        ));
+        assert!(parser.verify_blocks().is_err());
+
+        data[4 * 15] ^= 0x10;
+        // still valid JSON, but not the metadata that was written
+        let table = data.windows(7).position(|w| w == b"Chicken").unwrap();
+        data[table] = b'T';
+        std::fs::write(&path, &data).unwrap();
+        let result = ParserBuilder::new(path.to_string(), String::from("Chicken")).build();
+        assert!(matches!(result, Err(BuildError::FormatError(_))));
+
+        // files without checksums are only rejected in strict mode
+        assert!(open(&farms_path(), "Farm").verify_blocks().is_ok());
+        assert!(ParserBuilder::new(farms_path(), String::from("Farm"))
+            .verify(VerifyMode::Strict)
+            .build()
+            .is_err());
+    }
+
+    #[test]
+    fn it_checks_magic_bytes_and_versions() {
+        let legacy = open(&farms_path(), "Farm");
+        assert_eq!(legacy.format_version(), FormatVersion::LEGACY);
+
+        let path = TmpPath::file("version");
+        let build = |json: &str, magic: bool| {
+            let mut data = Vec::new();
+            if magic {
+                data.extend_from_slice(version::MAGIC);
+            }
+            data.extend_from_slice(json.as_bytes());
+            data.extend_from_slice(&(json.len() as u32).to_le_bytes());
+            if magic {
+                data.extend_from_slice(version::MAGIC);
+            }
+            std::fs::write(&path, data).unwrap();
+            ParserBuilder::new(path.to_string(), String::from("T")).build()
+        };
+        let json = |version: &str, compression: &str| {
+            format!(
+                r#"{{"Table": "T", {} "Columns": {{"c": {{"type": "int", {} "block_stats": {{}}, "num_blocks": 0, "start_offset": 4}}}}, "Max Values Per Block": 1}}"#,
+                version, compression
+            )
+        };
+        let v1 = build(&json(r#""Format Version": "1.0","#, ""), true).unwrap();
+        assert_eq!(v1.format_version(), FormatVersion { major: 1, minor: 0 });
+        let v1_3 = build(&json(r#""Format Version": "1.3","#, ""), true).unwrap();
+        assert_eq!(v1_3.format_version(), FormatVersion { major: 1, minor: 3 });
+        assert!(matches!(
+            build(&json(r#""Format Version": "2.0","#, ""), true),
+            Err(BuildError::UnsupportedVersion(FormatVersion {
+                major: 2,
+                minor: 0
+            }))
+        ));
+        // the magic bytes and the version go together
+        assert!(build(&json("", ""), true).is_err());
+        assert!(build(&json(r#""Format Version": "1.0","#, ""), false).is_err());
+        // compression is not part of legacy files
+        match build(&json("", r#""compression": "lz4","#), false) {
+            Err(BuildError::FormatError(e)) => {
+                assert_eq!(e, "compression need format version 1.0, the file has 0.0")
+            }
+            _ => panic!("legacy file with compression was read"),
+        }
+    }
+
+    #[test]
+    fn it_reads_files_larger_than_4gib() {
+        use std::io::{Seek, SeekFrom, Write};
+
+        // a sparse file whose only column starts past 5 GiB
+        let path = TmpPath::file("large");
+        let start = 5usize << 30;
+        let mut file = std::fs::File::create(&path).unwrap();
+        file.write_all(version::MAGIC).unwrap();
+        file.seek(SeekFrom::Start(start as u64)).unwrap();
+        (0..10i32).for_each(|i| file.write_all(&i.to_le_bytes()).unwrap());
+        let json = |start: usize| {
+            format!(
+                r#"{{"Format Version": "1.1", "Table": "T", "Columns": {{"c": {{"type": "int", "block_stats": {{"0": {{"num": 10, "min": 0, "max": 9}}}}, "num_blocks": 1, "start_offset": {}}}}}, "Max Values Per Block": 10}}"#,
+                start
+            )
+        };
+        let json = json(start);
+        file.write_all(json.as_bytes()).unwrap();
+        file.write_all(&(json.len() as u64).to_le_bytes()).unwrap();
+        file.write_all(version::MAGIC_WIDE).unwrap();
+        drop(file);
+
+        let parser = open(&path, "T");
+        assert_eq!(
+            parser.read_block("c", 0).unwrap().get(9),
+            Some(ScalarValue::Int(9))
+        );
+        drop(parser);
+
+        // the wide footer round trips, offsets past the data region are rejected
+        let ids: Vec<_> = (0..10).map(ScalarValue::Int).collect();
+        write_file(&path, "T", 10, |writer| {
+            writer.wide_footer(true);
+            writer.write_col("c", ColumnType::Int, &ids).unwrap();
+        });
+        assert_eq!(open(&path, "T").num_rows(), 10);
+        let mut data = std::fs::read(&path).unwrap();
+        let at = data
+            .windows(16)
+            .position(|w| w == b"\"start_offset\":4")
+            .unwrap();
+        data[at + 15] = b'8';
+        std::fs::write(&path, &data).unwrap();
+        let result = ParserBuilder::new(path.to_string(), String::from("T")).build();
+        assert!(matches!(result, Err(BuildError::FormatError(_))));
+    }
+
+    #[test]
+    fn it_validates_block_stats() {
+        let path = TmpPath::file("stats");
+        let build = |stats: &str, num_blocks: u32| {
+            let json = format!(
+                r#"{{"Table": "T", "Columns": {{"c": {{"type": "int", "block_stats": {{{}}}, "num_blocks": {}, "start_offset": 0}}}}, "Max Values Per Block": 2}}"#,
+                stats, num_blocks
+            );
+            let mut data = vec![0; 8];
+            data.extend_from_slice(json.as_bytes());
+            data.extend_from_slice(&(json.len() as u32).to_le_bytes());
+            std::fs::write(&path, data).unwrap();
+            ParserBuilder::new(path.to_string(), String::from("T")).build()
+        };
+        let parser = build(r#""0": {"num": 2, "min": -1, "max": 7}"#, 1).unwrap();
+        let stats = parser.block_stats("c", 0).unwrap();
+        assert_eq!(stats.min, Some(ScalarValue::Int(-1)));
+        assert_eq!(stats.max, Some(ScalarValue::Int(7)));
+
+        for (stats, num_blocks) in [
+            // missing and extra blocks
+            (r#""0": {"num": 2, "min": 0, "max": 0}"#, 2),
+            (
+                r#""0": {"num": 1, "min": 0, "max": 0}, "1": {"num": 1, "min": 0, "max": 0}"#,
+                1,
+            ),
+            // more values than Max Values Per Block
+            (r#""0": {"num": 3, "min": 0, "max": 0}"#, 1),
+            // min of the wrong type
+            (r#""0": {"num": 2, "min": "zero", "max": 0}"#, 1),
+        ] {
+            assert!(matches!(
+                build(stats, num_blocks),
+                Err(BuildError::JsonError(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn it_validates_files() {
+        assert_eq!(validate(&farms_path()).unwrap(), vec![]);
+
+        let ids: Vec<_> = (0..20).map(ScalarValue::Int).collect();
+        let names: Vec<_> = (0..20)
+            .map(|i| ScalarValue::Str(format!("chicken {}", i)))
+            .collect();
+        let path = write_table("validate", "Chicken", 10, |writer| {
+            writer
+                .write_col("identifier", ColumnType::Int, &ids)
+                .unwrap();
+            writer.write_col("name", ColumnType::Str, &names).unwrap();
+        });
+        let mut data = std::fs::read(&path).unwrap();
+        // the 3rd name of the 2nd block loses its NUL, the stored max of the 1st block is off
+        let name = data.windows(10).position(|w| w == b"chicken 12").unwrap();
+        data[name..name + 32].fill(b'x');
+        let max = data.windows(7).position(|w| w == b"\"max\":9").unwrap();
+        data[max + 6] = b'8';
+        std::fs::write(&path, &data).unwrap();
+        let violations = validate(&path).unwrap();
+
+        let locations: Vec<_> = violations.iter().map(|v| v.location.clone()).collect();
+        assert!(locations.contains(&Location::Block(String::from("identifier"), 0)));
+        assert!(locations.contains(&Location::Value(String::from("name"), 1, 2)));
+        assert_eq!(
+            violations[0].to_string(),
+            "column identifier block 0: stored max is 8, the data has 9"
+        );
+
+        // columns that disagree on their blocks are not read as misaligned rows
+        let path = write_table("validate-blocks", "Chicken", 10, |writer| {
+            writer
+                .write_col("identifier", ColumnType::Int, &ids)
+                .unwrap();
+            writer
+                .write_col("name", ColumnType::Str, &names[..15])
+                .unwrap();
+        });
+        let result = ParserBuilder::new(path.to_string(), String::from("Chicken")).build();
+        assert!(matches!(result, Err(BuildError::FormatError(_))));
+        let violations = validate(&path).unwrap();
+        assert_eq!(
+            violations[0].to_string(),
+            "column name: blocks hold other numbers of values than column identifier"
+        );
+    }
+
+    #[cfg(feature = "arrow")]
+    #[test]
+    fn it_exports_arrow_record_batches() {
+        use arrow_array::cast::AsArray;
+        use arrow_array::types::Float32Type;
+
+        let parser = open(&farms_path(), "Farm");
+        let schema = parser.arrow_schema(None).unwrap();
+        assert_eq!(
+            schema.field(1).data_type(),
+            &arrow::arrow_type(ColumnType::StrList)
+        );
+        let batch = parser.record_batch(0, None).unwrap();
+        assert_eq!(batch.num_rows(), 6);
+        assert_eq!(batch.column(0).as_string::<i32>().value(0), "Incubator");
+        let sexes = batch.column(1).as_list::<i32>().value(1);
+        assert_eq!(sexes.as_string::<i32>().value(0), "FEMALE");
+        // the float column is not copied out of the mapped file
+        let weeks = batch.column(3).as_primitive::<Float32Type>();
+        assert_eq!(weeks.value(2), 156.0);
+        assert_eq!(
+            weeks.values().as_ptr() as *const u8,
+            parser.block_raw("max_age_weeks", 0).unwrap().as_ptr()
+        );
+
+        // no columns, but still the rows, e.g. for count(*)
+        let batch = parser.record_batch(0, Some(&[])).unwrap();
+        assert_eq!((batch.num_columns(), batch.num_rows()), (0, 6));
+        assert!(parser.record_batches(0..2, None).nth(1).unwrap().is_err());
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn it_converts_parquet() {
+        use ::parquet::file::reader::{FileReader, SerializedFileReader};
+        use ::parquet::file::statistics::Statistics;
+
+        let farms = open(&farms_path(), "Farm");
+        let parquet_path = TmpPath::file("farms-parquet");
+        let file = std::fs::File::create(&parquet_path).unwrap();
+        assert_eq!(parquet::write_parquet(&farms, file).unwrap(), 1);
+
+        // 4 rows per block, the second row group keeps the stats of the second block
+        let source = parquet::ParquetSource::open(&parquet_path).unwrap();
+        assert_eq!(source.table().as_deref(), Some("Farm"));
+        let path = write_table("farms-from-parquet", "Farm", 4, |writer| {
+            assert_eq!(source.write_to(writer).unwrap(), 6);
+        });
+        let parser = open(&path, "Farm");
+        assert_eq!(parser.num_blocks(), 2);
+        for column in &farms.columns {
+            let name = &column.column_name;
+            let original = farms.read_block(name, 0).unwrap();
+            let values: Vec<_> = (0..2)
+                .flat_map(|b| {
+                    let block = parser.read_block(name, b).unwrap();
+                    (0..parser.block_len(b)).map(move |i| block.get(i).unwrap())
+                })
+                .collect();
+            assert_eq!(
+                values,
+                (0..6).map(|i| original.get(i).unwrap()).collect::<Vec<_>>()
+            );
+        }
+
+        let file = std::fs::File::create(&parquet_path).unwrap();
+        assert_eq!(parquet::write_parquet(&parser, file).unwrap(), 2);
+        let reader =
+            SerializedFileReader::new(std::fs::File::open(&parquet_path).unwrap()).unwrap();
+        let column = farms
+            .columns
+            .iter()
+            .position(|c| c.column_name == "max_age_weeks");
+        let stats = reader
+            .metadata()
+            .row_group(1)
+            .column(column.unwrap())
+            .statistics();
+        let block = parser.block_stats("max_age_weeks", 1).unwrap();
+        match stats {
+            Some(Statistics::Float(stats)) => assert_eq!(
+                (block.min.clone(), block.max.clone()),
+                (
+                    stats.min_opt().map(|v| ScalarValue::Float(*v)),
+                    stats.max_opt().map(|v| ScalarValue::Float(*v))
+                )
+            ),
+            stats => panic!("unexpected {:?}", stats),
+        }
+    }
+
+    #[test]
+    fn it_rejects_unknown_types() {
+        let path = TmpPath::file("unknown");
+        let json = br#"{"Table": "T", "Columns": {"c": {"type": "map[str]", "block_stats": {}, "num_blocks": 0, "start_offset": 0}}, "Max Values Per Block": 1}"#;
+        let mut data = json.to_vec();
+        data.extend_from_slice(&(json.len() as u32).to_le_bytes());
+        std::fs::write(&path, data).unwrap();
+        let result = ParserBuilder::new(path.to_string(), String::from("T")).build();
+        assert!(matches!(result, Err(BuildError::JsonError(_))));
     }
 }
@@ -0,0 +1,328 @@
+use std::fs::File;
+use std::io::Write;
+use std::sync::Arc;
+
+use ::parquet::arrow::arrow_reader::{ArrowReaderMetadata, ParquetRecordBatchReaderBuilder};
+use ::parquet::arrow::{ArrowWriter, ProjectionMask};
+use ::parquet::basic::Compression;
+use ::parquet::file::properties::WriterProperties;
+use ::parquet::format::KeyValue;
+use arrow_array::cast::AsArray;
+use arrow_array::types::{
+    Date32Type, Float32Type, Float64Type, Int32Type, Int64Type, TimestampMicrosecondType,
+};
+use arrow_array::{Array, ArrayRef};
+use arrow_schema::{ArrowError, DataType};
+
+use crate::arrow::arrow_type;
+use crate::parser::{BuildError, Parser};
+use crate::types::{ColumnType, ScalarValue};
+use crate::writer::{WriteError, Writer};
+
+/*
+Parquet conversion in both directions, behind the `parquet` feature.
+
+db721 -> Parquet: the columns are mapped like the Arrow export (see arrow.rs) and every
+block becomes one row group, so the row groups have the same boundaries and their
+statistics the same min/max as the blocks.
+
+Parquet -> db721: every row group becomes one block, unless it holds more values than
+Max Values Per Block and is split. The block stats are computed from the values, like
+for any block the writer writes, which yields the min/max of the row group.
+
+    Parquet (as Arrow)                      db721
+    Int8, Int16, Int32, UInt8, UInt16       "int"
+    Int64, UInt32                           "bigint"
+    Float16, Float32                        "float"
+    Float64                                 "double"
+    Utf8, LargeUtf8, Utf8View               "str", at most 31 bytes
+    Boolean                                 "bool"
+    Date32, Date64                          "date"
+    Timestamp, any unit and time zone       "timestamp", in UTC
+    List or LargeList of strings            "list[str]"
+
+Other types and null values cannot be stored and fail the conversion.
+*/
+
+pub const TABLE_KEY: &str = "db721.table";
+
+#[derive(Debug)]
+pub enum ConvertError {
+    IoError(std::io::Error),
+    BuildError(BuildError),
+    WriteError(WriteError),
+    ParquetError(::parquet::errors::ParquetError),
+    ArrowError(ArrowError),
+    // column name, why it cannot be converted
+    Unsupported(String, String),
+}
+
+impl From<std::io::Error> for ConvertError {
+    fn from(error: std::io::Error) -> Self {
+        ConvertError::IoError(error)
+    }
+}
+
+impl From<BuildError> for ConvertError {
+    fn from(error: BuildError) -> Self {
+        ConvertError::BuildError(error)
+    }
+}
+
+impl From<WriteError> for ConvertError {
+    fn from(error: WriteError) -> Self {
+        ConvertError::WriteError(error)
+    }
+}
+
+impl From<::parquet::errors::ParquetError> for ConvertError {
+    fn from(error: ::parquet::errors::ParquetError) -> Self {
+        ConvertError::ParquetError(error)
+    }
+}
+
+impl From<ArrowError> for ConvertError {
+    fn from(error: ArrowError) -> Self {
+        ConvertError::ArrowError(error)
+    }
+}
+
+impl std::fmt::Display for ConvertError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConvertError::IoError(e) => write!(f, "DB721|IO: {}", e),
+            ConvertError::BuildError(e) => write!(f, "{}", e),
+            ConvertError::WriteError(e) => write!(f, "{}", e),
+            ConvertError::ParquetError(e) => write!(f, "DB721|Parquet: {}", e),
+            ConvertError::ArrowError(e) => write!(f, "DB721|Parquet: {}", e),
+            ConvertError::Unsupported(c, e) => write!(f, "DB721|Parquet: column {} {}", c, e),
+        }
+    }
+}
+
+impl std::error::Error for ConvertError {}
+
+// write the file as Parquet, returns the number of row groups
+pub fn write_parquet<W: Write + Send>(parser: &Parser, out: W) -> Result<usize, ConvertError> {
+    let properties = WriterProperties::builder()
+        .set_max_row_group_size(parser.metadata.max_values_per_block.max(1) as usize)
+        .set_compression(Compression::SNAPPY)
+        .set_key_value_metadata(Some(vec![KeyValue::new(
+            TABLE_KEY.to_string(),
+            parser.metadata.table.clone(),
+        )]))
+        .build();
+    let mut writer = ArrowWriter::try_new(out, parser.arrow_schema(None)?, Some(properties))?;
+    for batch in parser.record_batches(0..parser.num_blocks(), None) {
+        writer.write(&batch?)?;
+        // ends the row group at the end of the block
+        writer.flush()?;
+    }
+    let metadata = writer.close()?;
+    Ok(metadata.row_groups.len())
+}
+
+// the db721 type a Parquet column is stored as
+pub fn db721_type(data_type: &DataType) -> Option<ColumnType> {
+    Some(match data_type {
+        DataType::Int8 | DataType::Int16 | DataType::Int32 | DataType::UInt8 | DataType::UInt16 => {
+            ColumnType::Int
+        }
+        DataType::Int64 | DataType::UInt32 => ColumnType::BigInt,
+        DataType::Float16 | DataType::Float32 => ColumnType::Float,
+        DataType::Float64 => ColumnType::Double,
+        DataType::Utf8 | DataType::LargeUtf8 | DataType::Utf8View => ColumnType::Str,
+        DataType::Boolean => ColumnType::Bool,
+        DataType::Date32 | DataType::Date64 => ColumnType::Date,
+        DataType::Timestamp(_, _) => ColumnType::Timestamp,
+        DataType::List(field) | DataType::LargeList(field) => {
+            match db721_type(field.data_type())? {
+                ColumnType::Str => ColumnType::StrList,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    })
+}
+
+// the values of an array, cast to `ty` first
+fn scalar_values(
+    name: &str,
+    array: &ArrayRef,
+    ty: ColumnType,
+) -> Result<Vec<ScalarValue>, ConvertError> {
+    let array = arrow_cast::cast(array, &arrow_type(ty))?;
+    let nulls = match array.as_list_opt::<i32>() {
+        Some(lists) => lists.values().null_count(),
+        None => 0,
+    } + array.null_count();
+    if nulls > 0 {
+        return Err(ConvertError::Unsupported(
+            name.to_string(),
+            "has null values".to_string(),
+        ));
+    }
+    Ok(match ty {
+        ColumnType::Int => array
+            .as_primitive::<Int32Type>()
+            .values()
+            .iter()
+            .map(|v| ScalarValue::Int(*v))
+            .collect(),
+        ColumnType::Float => array
+            .as_primitive::<Float32Type>()
+            .values()
+            .iter()
+            .map(|v| ScalarValue::Float(*v))
+            .collect(),
+        ColumnType::BigInt => array
+            .as_primitive::<Int64Type>()
+            .values()
+            .iter()
+            .map(|v| ScalarValue::BigInt(*v))
+            .collect(),
+        ColumnType::Double => array
+            .as_primitive::<Float64Type>()
+            .values()
+            .iter()
+            .map(|v| ScalarValue::Double(*v))
+            .collect(),
+        ColumnType::Date => array
+            .as_primitive::<Date32Type>()
+            .values()
+            .iter()
+            .map(|v| ScalarValue::Date(*v))
+            .collect(),
+        ColumnType::Timestamp => array
+            .as_primitive::<TimestampMicrosecondType>()
+            .values()
+            .iter()
+            .map(|v| ScalarValue::Timestamp(*v))
+            .collect(),
+        ColumnType::Bool => array
+            .as_boolean()
+            .values()
+            .iter()
+            .map(ScalarValue::Bool)
+            .collect(),
+        ColumnType::Str => array
+            .as_string::<i32>()
+            .iter()
+            .map(|v| ScalarValue::Str(v.unwrap_or_default().to_string()))
+            .collect(),
+        ColumnType::StrList => array
+            .as_list::<i32>()
+            .iter()
+            .map(|list| {
+                let list = list.unwrap_or_else(|| {
+                    Arc::new(arrow_array::StringArray::from(Vec::<&str>::new()))
+                });
+                ScalarValue::StrList(
+                    list.as_string::<i32>()
+                        .iter()
+                        .map(|v| v.unwrap_or_default().to_string())
+                        .collect(),
+                )
+            })
+            .collect(),
+    })
+}
+
+// A Parquet file to convert to db721
+pub struct ParquetSource {
+    file: File,
+    metadata: ArrowReaderMetadata,
+}
+
+impl ParquetSource {
+    pub fn open(filename: &str) -> Result<ParquetSource, ConvertError> {
+        let file = File::open(filename)?;
+        let metadata = ArrowReaderMetadata::load(&file, Default::default())?;
+        Ok(ParquetSource { file, metadata })
+    }
+
+    // the table name of a file written by `write_parquet`
+    pub fn table(&self) -> Option<String> {
+        let metadata = self
+            .metadata
+            .metadata()
+            .file_metadata()
+            .key_value_metadata()?;
+        metadata
+            .iter()
+            .find(|kv| kv.key == TABLE_KEY)
+            .and_then(|kv| kv.value.clone())
+    }
+
+    // the Max Values Per Block that keeps every row group in one block
+    pub fn max_row_group_len(&self) -> usize {
+        let row_groups = self.metadata.metadata().row_groups();
+        row_groups
+            .iter()
+            .map(|rg| rg.num_rows() as usize)
+            .max()
+            .unwrap_or(0)
+    }
+
+    // the values of one column in one row group
+    fn read_row_group(
+        &self,
+        column: usize,
+        row_group: usize,
+        ty: ColumnType,
+    ) -> Result<Vec<ScalarValue>, ConvertError> {
+        let parquet = self.metadata.metadata();
+        let mask = ProjectionMask::roots(parquet.file_metadata().schema_descr(), [column]);
+        let reader = ParquetRecordBatchReaderBuilder::new_with_metadata(
+            self.file.try_clone()?,
+            self.metadata.clone(),
+        )
+        .with_projection(mask)
+        .with_row_groups(vec![row_group])
+        .build()?;
+        let name = self.metadata.schema().field(column).name();
+        let mut values = Vec::with_capacity(parquet.row_group(row_group).num_rows() as usize);
+        for batch in reader {
+            values.extend(scalar_values(name, batch?.column(0), ty)?);
+        }
+        Ok(values)
+    }
+
+    // write every column to `writer`, one row group at a time, returns the number of
+    // rows. The caller finalizes the writer.
+    pub fn write_to<W: Write>(&self, writer: &mut Writer<W>) -> Result<u64, ConvertError> {
+        let schema = self.metadata.schema().clone();
+        // all types are checked before anything is written
+        let types = schema
+            .fields()
+            .iter()
+            .map(|field| {
+                db721_type(field.data_type()).ok_or_else(|| {
+                    ConvertError::Unsupported(
+                        field.name().clone(),
+                        format!("has the unsupported type {}", field.data_type()),
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let num_row_groups = self.metadata.metadata().num_row_groups();
+        for (column, (field, ty)) in schema.fields().iter().zip(types).enumerate() {
+            // write_col_blocks only passes on WriteErrors, others are kept here
+            let mut failed = None;
+            let blocks = (0..num_row_groups).map_while(|row_group| {
+                match self.read_row_group(column, row_group, ty) {
+                    Ok(values) => Some(Ok::<_, WriteError>(values)),
+                    Err(e) => {
+                        failed = Some(e);
+                        None
+                    }
+                }
+            });
+            writer.write_col_blocks(field.name(), ty, blocks)?;
+            if let Some(e) = failed {
+                return Err(e);
+            }
+        }
+        Ok(self.metadata.metadata().file_metadata().num_rows() as u64)
+    }
+}
@@ -1,17 +1,9 @@
-use lazy_static::lazy_static;
 use self_cell::self_cell;
-use std::char::from_u32_unchecked;
-use std::collections::BTreeMap;
-use std::hash::Hash;
-use std::os::unix::net::UnixDatagram;
-use std::rc::Rc;
-use std::str::from_utf8;
-use std::sync::Mutex;
 /*
 CREATE FOREIGN TABLE IF NOT EXISTS db721_farm
 (
     farm_name       varchar,
---     sexes           varchar[],
+    sexes           varchar[],
     min_age_weeks   real,
     max_age_weeks   real
 ) SERVER db721_server OPTIONS
@@ -21,55 +13,232 @@ CREATE FOREIGN TABLE IF NOT EXISTS db721_farm
 );
 );
 */
-use nom::{bytes::streaming::take, IResult};
+use memmap2::Mmap;
+use nom::{bytes::complete::take, IResult};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::io::Read;
+use std::borrow::Cow;
+use std::ops::Range;
+use std::sync::Arc;
 use std::{collections::HashMap, fs::File};
 
-lazy_static!(
-        // column name as key
-      pub static ref COLUMN_META: Mutex<BTreeMap<usize, ColumnMeta>> = {
-        let mut m = Mutex::new(BTreeMap::new());
-        m
-    };
-
-);
+use crate::bloom::{BloomFilter, BloomFilterMeta};
+use crate::checksum::{Checksum, VerifyMode};
+use crate::codec::{Codec, RawSize, MAX_RAW_BLOCK_SIZE};
+use crate::deletes::{DeleteVector, FileIdentity};
+use crate::encoding::{DictionaryBlock, Encoding, RleBlock};
+use crate::types::{ColumnBlock, ColumnType, ScalarValue};
+use crate::version::{FormatVersion, MAGIC, MAGIC_WIDE};
 
 #[derive(Debug, Eq, PartialEq)]
 pub struct ColRaw<'a>(pub HashMap<String, &'a [u8]>);
 
+// the file is mapped rather than read, so it may be larger than memory. Zero-copy
+// Arrow arrays share the mapping.
 self_cell!(
     pub struct AstCell {
-        owner: Vec<u8>,
+        owner: Arc<Mmap>,
 
         #[covariant]
         dependent: ColRaw,
     }
 
-    impl {Debug, Eq, PartialEq}
+    impl {Debug}
 );
 
 pub struct Parser {
     pub tablename: String,
     pub data: AstCell,
-    //data: Vec<u8>,
-    //// also start index as key
-    //column_raw: HashMap<usize, &'par[u8]>,
+    pub metadata: Metadata,
+    // ordered by start offset
+    pub columns: Vec<ColumnMeta>,
+    pub verify: VerifyMode,
+    // rows deleted through the sidecar of the file
+    pub deletes: DeleteVector,
+    // the version of the file, which the sidecar must belong to
+    pub identity: FileIdentity,
 }
 
 impl Parser {
-    pub fn new(tablename: String, data: AstCell) -> Parser {
+    pub fn new(
+        tablename: String,
+        data: AstCell,
+        metadata: Metadata,
+        columns: Vec<ColumnMeta>,
+        verify: VerifyMode,
+    ) -> Parser {
         Parser {
-            tablename: tablename,
-            data: data,
+            tablename,
+            data,
+            metadata,
+            columns,
+            verify,
+            deletes: DeleteVector::default(),
+            identity: FileIdentity::default(),
+        }
+    }
+
+    pub fn format_version(&self) -> FormatVersion {
+        self.metadata
+            .format_version
+            .unwrap_or(FormatVersion::LEGACY)
+    }
+
+    pub fn column_meta(&self, name: &str) -> Option<&ColumnMeta> {
+        self.columns.iter().find(|c| c.column_name == name)
+    }
+
+    // all columns share the same block layout, see `build`, so the first one is
+    // representative
+    pub fn num_blocks(&self) -> usize {
+        self.columns.first().map_or(0, |c| c.block_nums.len())
+    }
+
+    pub fn num_rows(&self) -> usize {
+        self.columns
+            .first()
+            .map_or(0, |c| c.block_nums.iter().sum::<u32>() as usize)
+    }
+
+    // rows not deleted
+    pub fn num_live_rows(&self) -> usize {
+        self.num_rows() - self.deletes.num_deleted()
+    }
+
+    // the row id of the first row of `block`
+    pub fn block_start(&self, block: usize) -> usize {
+        self.columns.first().map_or(0, |c| {
+            c.block_nums.iter().take(block).map(|n| *n as usize).sum()
+        })
+    }
+
+    // number of deleted rows in `block`
+    pub fn block_num_deleted(&self, block: usize) -> usize {
+        let start = self.block_start(block);
+        self.deletes
+            .num_deleted_in(start, start + self.block_len(block))
+    }
+
+    // number of values in `block`
+    pub fn block_len(&self, block: usize) -> usize {
+        self.columns
+            .first()
+            .and_then(|c| c.block_nums.get(block))
+            .map_or(0, |n| *n as usize)
+    }
+
+    pub fn block_stats(&self, name: &str, block: usize) -> Option<&BlockStats> {
+        self.metadata.columns.get(name)?.block_stats.get(block)
+    }
+
+    pub fn block_raw(&self, name: &str, block: usize) -> Result<&[u8], BuildError> {
+        let meta = self
+            .column_meta(name)
+            .ok_or_else(|| BuildError::ColumnNotFound(name.to_string()))?;
+        let raw = self
+            .data
+            .borrow_dependent()
+            .0
+            .get(name)
+            .ok_or_else(|| BuildError::ColumnNotFound(name.to_string()))?;
+        let (start, end) = match (
+            meta.block_offsets.get(block),
+            meta.block_offsets.get(block + 1),
+        ) {
+            (Some(s), Some(e)) => (*s, *e),
+            _ => return Err(BuildError::BlockNotFound(name.to_string(), block)),
+        };
+        let raw = raw.get(start..end).ok_or_else(|| {
+            BuildError::FormatError(format!(
+                "column {} block {} exceeds the column region",
+                name, block
+            ))
+        })?;
+        // blocks are verified lazily, whenever they are read
+        match (meta.block_checksums[block], self.metadata.checksum) {
+            (Some(expected), Some(checksum)) if checksum.compute(raw) != expected => {
+                Err(BuildError::ChecksumMismatch(name.to_string(), block))
+            }
+            (None, _) | (_, None) if self.verify == VerifyMode::Strict => {
+                Err(BuildError::ChecksumMissing(name.to_string(), block))
+            }
+            _ => Ok(raw),
+        }
+    }
+
+    // verify the checksums of every block of every column, without decoding them
+    pub fn verify_blocks(&self) -> Result<(), BuildError> {
+        for meta in &self.columns {
+            for block in 0..meta.block_nums.len() {
+                self.block_raw(&meta.column_name, block)?;
+            }
         }
+        Ok(())
+    }
+
+    pub fn bloom_filter(&self, name: &str, block: usize) -> Option<BloomFilter<&[u8]>> {
+        let meta = self.column_meta(name)?;
+        let bloom = meta.bloom_filter.as_ref()?;
+        if block >= meta.block_nums.len() {
+            return None;
+        }
+        let start = bloom.offset + block * bloom.num_bytes;
+        Some(BloomFilter {
+            bits: &self.data.borrow_owner()[start..start + bloom.num_bytes],
+            num_hashes: bloom.num_hashes,
+        })
+    }
+
+    // the block after decompression
+    pub fn block_data(&self, name: &str, block: usize) -> Result<Cow<'_, [u8]>, BuildError> {
+        let raw = self.block_raw(name, block)?;
+        let meta = self.column_meta(name).unwrap();
+        meta.block_codecs[block]
+            .decompress(raw, meta.block_raw_sizes[block])
+            .map_err(|e| BuildError::DecompressError(name.to_string(), block, e))
+    }
+
+    pub fn read_block(&self, name: &str, block: usize) -> Result<ColumnBlock, BuildError> {
+        let meta = self
+            .column_meta(name)
+            .ok_or_else(|| BuildError::ColumnNotFound(name.to_string()))?;
+        let data = self.block_data(name, block)?;
+        meta.block_encodings[block].decode(meta.column_type, &data, meta.block_nums[block] as usize)
+    }
+
+    // the dictionary and codes of a dictionary encoded block, None for other encodings
+    pub fn read_dictionary(
+        &self,
+        name: &str,
+        block: usize,
+    ) -> Result<Option<DictionaryBlock>, BuildError> {
+        let meta = self
+            .column_meta(name)
+            .ok_or_else(|| BuildError::ColumnNotFound(name.to_string()))?;
+        if meta.block_encodings.get(block) != Some(&Encoding::Dictionary) {
+            return Ok(None);
+        }
+        let data = self.block_data(name, block)?;
+        DictionaryBlock::parse(&data, meta.block_nums[block] as usize).map(Some)
+    }
+
+    // the runs of a run-length encoded block, None for other encodings
+    pub fn read_runs(&self, name: &str, block: usize) -> Result<Option<RleBlock>, BuildError> {
+        let meta = self
+            .column_meta(name)
+            .ok_or_else(|| BuildError::ColumnNotFound(name.to_string()))?;
+        if meta.block_encodings.get(block) != Some(&Encoding::Rle) {
+            return Ok(None);
+        }
+        let data = self.block_data(name, block)?;
+        RleBlock::parse(meta.column_type, &data).map(Some)
     }
 }
 
 pub struct ParserBuilder {
     filename: String,
     tablename: String,
+    verify: VerifyMode,
 }
 
 // customize build error
@@ -77,6 +246,21 @@ pub struct ParserBuilder {
 pub enum BuildError {
     IoError(std::io::Error),
     JsonError(serde_json::Error),
+    FormatError(String),
+    ColumnNotFound(String),
+    // column name, block index
+    BlockNotFound(String, usize),
+    // column name, block index, codec error
+    DecompressError(String, usize, String),
+    // column name, block index
+    ChecksumMismatch(String, usize),
+    // column name, block index, only in strict mode
+    ChecksumMissing(String, usize),
+    UnsupportedVersion(FormatVersion),
+    // file, how it differs from the other files of a dataset
+    SchemaMismatch(String, String),
+    // file, rewritten since the rows to change were read from it
+    FileChanged(String),
 }
 
 impl From<std::io::Error> for BuildError {
@@ -91,11 +275,55 @@ impl From<serde_json::Error> for BuildError {
     }
 }
 
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::IoError(e) => write!(f, "DB721|IO: {}", e),
+            BuildError::JsonError(e) => write!(f, "DB721|Metadata: {}", e),
+            BuildError::FormatError(e) => write!(f, "DB721|Format: {}", e),
+            BuildError::ColumnNotFound(c) => write!(f, "DB721|Column: no column named {}", c),
+            BuildError::BlockNotFound(c, b) => {
+                write!(f, "DB721|Column: column {} has no block {}", c, b)
+            }
+            BuildError::DecompressError(c, b, e) => {
+                write!(f, "DB721|Codec: column {} block {}: {}", c, b, e)
+            }
+            BuildError::ChecksumMismatch(c, b) => {
+                write!(f, "DB721|Checksum: column {} block {} is corrupted", c, b)
+            }
+            BuildError::ChecksumMissing(c, b) => {
+                write!(
+                    f,
+                    "DB721|Checksum: column {} block {} has no checksum",
+                    c, b
+                )
+            }
+            BuildError::UnsupportedVersion(v) => {
+                write!(f, "DB721|Format: unsupported format version {}", v)
+            }
+            BuildError::SchemaMismatch(file, e) => write!(f, "DB721|Schema: file {} {}", file, e),
+            BuildError::FileChanged(file) => {
+                write!(
+                    f,
+                    "DB721|Version: file {} changed since its rows were read",
+                    file
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
 /*
+metadata["Format Version"]: optional, "major.minor" (str), see version.rs
+
 metadata["Table"]: the table name (string)
 
 metadata["Max Values Per Block"]: the maximum number of values in each block (int)
 
+metadata["Checksum"]: optional, the checksum algorithm of the file (str), see checksum.rs
+
 metadata["Columns"]: the table's columns (JSON dict)
     Keys: column names (string)
     Values: column data, see below (JSON dict)
@@ -103,163 +331,567 @@ metadata["Columns"]: the table's columns (JSON dict)
 metadata["Columns"]["Column Name"]: column data (JSON dict)
     Keys:
     "type": the column type (str), possible values are:
-        "float" | "int" | "str"
+        "float" | "int" | "str" | "bigint" | "double" | "bool" | "date" | "timestamp"
+        see types.rs for their layout
 
     "start_offset": the offset in the file for the first block of this column (int)
 
     "num_blocks": the number of blocks for this column (int)
 
-    "block_stats": statistics for the 0-indexed fixed-size blocks (JSON dict)
+    "compression": optional, the codec of the blocks of this column (str), see codec.rs
+        "none" | "lz4" | "zstd"
+
+    "bloom_filter": optional, where the bloom filters of the blocks are (JSON dict), see bloom.rs
+
+    "block_stats": statistics for the 0-indexed fixed-size blocks (JSON dict), one per
+    block, with at most "Max Values Per Block" values each
         Keys: block index (string)
         Values: statistics for the corresponding block (JSON dict)
             Keys:
             "num": the number of values in this block (int)
-            "min": the minimum value in this block (same type as column)
-            "max": the maximum value in this block (same type as column)
+            "min": the minimum value in this block (same type as column), null if unknown
+            "max": the maximum value in this block (same type as column), null if unknown
             "min_len": only exists for str column; the min length of a string in this block (int)
             "max_len": only exists for str column; the max length of a string in this block (int)
+            "size": only exists for list[str] column, compressed or encoded block; the length of this block in bytes (int)
+            "raw_size": only exists for compressed list[str] block; the length of this block after decompression (int)
+            "compression": optional, overrides the codec of the column for this block (str)
+            "encoding": optional, the encoding of this block (str), see encoding.rs
+                "plain" | "dictionary" | "rle" | "delta"
+            "crc32c": optional, the checksum of this block (int), see checksum.rs
 */
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct BlockStats {
+    pub num: u32,
+    // of the column type, the type of the elements for list[str]. None when unknown,
+    // e.g. for empty blocks, and the max of float blocks holding a NaN
+    pub min: Option<ScalarValue>,
+    pub max: Option<ScalarValue>,
+    pub min_len: Option<u32>,
+    pub max_len: Option<u32>,
+    pub size: Option<usize>,
+    pub raw_size: Option<usize>,
+    pub compression: Option<Codec>,
+    pub encoding: Option<Encoding>,
+    pub crc32c: Option<u32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub column_type: ColumnType,
+    pub start_offset: usize,
+    // indexed by block
+    pub block_stats: Vec<BlockStats>,
+    pub compression: Option<Codec>,
+    pub bloom_filter: Option<BloomFilterMeta>,
+}
+
+impl Column {
+    pub fn num_blocks(&self) -> usize {
+        self.block_stats.len()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "RawMetadata", into = "RawMetadata")]
+pub struct Metadata {
+    pub table: String,
+    pub columns: HashMap<String, Column>,
+    pub max_values_per_block: u32,
+    pub checksum: Option<Checksum>,
+    pub format_version: Option<FormatVersion>,
+}
+
+// The JSON layout of the metadata, typed and validated into `Metadata`
+#[derive(Serialize, Deserialize)]
+struct RawBlockStats {
     num: u32,
     min: Value,
     max: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
     min_len: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     max_len: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw_size: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<Codec>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encoding: Option<Encoding>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    crc32c: Option<u32>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Column {
+#[derive(Serialize, Deserialize)]
+struct RawColumn {
     #[serde(rename = "type")]
-    column_type: String,
+    column_type: ColumnType,
     start_offset: usize,
     num_blocks: u32,
-    block_stats: HashMap<String, BlockStats>,
+    block_stats: HashMap<String, RawBlockStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    compression: Option<Codec>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bloom_filter: Option<BloomFilterMeta>,
 }
-#[derive(Debug, Serialize, Deserialize)]
+
+#[derive(Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
-pub struct Metadata {
+struct RawMetadata {
     table: String,
-    #[serde(deserialize_with = "column_maker")]
-    columns: HashMap<String, Column>,
+    columns: HashMap<String, RawColumn>,
     #[serde(rename = "Max Values Per Block")]
     max_values_per_block: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    checksum: Option<Checksum>,
+    #[serde(rename = "Format Version", skip_serializing_if = "Option::is_none")]
+    format_version: Option<FormatVersion>,
+}
+
+impl RawColumn {
+    fn into_column(mut self, name: &str, max_values_per_block: u32) -> Result<Column, String> {
+        let ty = self.column_type;
+        let typed = |value: &Value, block: u32, which: &str| match value {
+            Value::Null => Ok(None),
+            value => ScalarValue::from_json(ty, value).map(Some).ok_or_else(|| {
+                format!(
+                    "{} {} of column {} block {} is not a {}",
+                    which,
+                    value,
+                    name,
+                    block,
+                    ty.name()
+                )
+            }),
+        };
+        let mut block_stats = Vec::with_capacity(self.num_blocks as usize);
+        for block in 0..self.num_blocks {
+            let stats = self.block_stats.remove(&block.to_string()).ok_or_else(|| {
+                format!("column {} is missing the stats of block {}", name, block)
+            })?;
+            if stats.num > max_values_per_block {
+                return Err(format!(
+                    "column {} block {} holds {} values, more than Max Values Per Block {}",
+                    name, block, stats.num, max_values_per_block
+                ));
+            }
+            block_stats.push(BlockStats {
+                num: stats.num,
+                min: typed(&stats.min, block, "min")?,
+                max: typed(&stats.max, block, "max")?,
+                min_len: stats.min_len,
+                max_len: stats.max_len,
+                size: stats.size,
+                raw_size: stats.raw_size,
+                compression: stats.compression,
+                encoding: stats.encoding,
+                crc32c: stats.crc32c,
+            });
+        }
+        if let Some(block) = self.block_stats.keys().next() {
+            return Err(format!(
+                "column {} has stats for block {} but only {} blocks",
+                name, block, self.num_blocks
+            ));
+        }
+        Ok(Column {
+            column_type: ty,
+            start_offset: self.start_offset,
+            block_stats,
+            compression: self.compression,
+            bloom_filter: self.bloom_filter,
+        })
+    }
+}
+
+impl From<Column> for RawColumn {
+    fn from(column: Column) -> Self {
+        let to_json = |v: Option<ScalarValue>| v.map_or(Value::Null, |v| v.to_json());
+        RawColumn {
+            column_type: column.column_type,
+            start_offset: column.start_offset,
+            num_blocks: column.block_stats.len() as u32,
+            block_stats: column
+                .block_stats
+                .into_iter()
+                .enumerate()
+                .map(|(block, stats)| {
+                    (
+                        block.to_string(),
+                        RawBlockStats {
+                            num: stats.num,
+                            min: to_json(stats.min),
+                            max: to_json(stats.max),
+                            min_len: stats.min_len,
+                            max_len: stats.max_len,
+                            size: stats.size,
+                            raw_size: stats.raw_size,
+                            compression: stats.compression,
+                            encoding: stats.encoding,
+                            crc32c: stats.crc32c,
+                        },
+                    )
+                })
+                .collect(),
+            compression: column.compression,
+            bloom_filter: column.bloom_filter,
+        }
+    }
+}
+
+impl TryFrom<RawMetadata> for Metadata {
+    type Error = String;
+
+    fn try_from(raw: RawMetadata) -> Result<Self, Self::Error> {
+        let max_values_per_block = raw.max_values_per_block;
+        Ok(Metadata {
+            table: raw.table,
+            columns: raw
+                .columns
+                .into_iter()
+                .map(|(name, column)| {
+                    let column = column.into_column(&name, max_values_per_block)?;
+                    Ok((name, column))
+                })
+                .collect::<Result<_, String>>()?,
+            max_values_per_block,
+            checksum: raw.checksum,
+            format_version: raw.format_version,
+        })
+    }
+}
+
+impl From<Metadata> for RawMetadata {
+    fn from(metadata: Metadata) -> Self {
+        RawMetadata {
+            table: metadata.table,
+            columns: metadata
+                .columns
+                .into_iter()
+                .map(|(name, column)| (name, column.into()))
+                .collect(),
+            max_values_per_block: metadata.max_values_per_block,
+            checksum: metadata.checksum,
+            format_version: metadata.format_version,
+        }
+    }
 }
 
 // The most frequently used column data
 #[derive(Debug)]
 pub struct ColumnMeta {
-    column_name: String,
-    column_type: String,
-    elem_size: u32, // size of the element in bytes
+    pub column_name: String,
+    pub column_type: ColumnType,
+    pub elem_size: usize, // size of the element in bytes
+    pub start_offset: usize,
+    // number of values in each block
+    pub block_nums: Vec<u32>,
+    // byte offset of each block inside the column, with the column length appended
+    pub block_offsets: Vec<usize>,
+    pub block_codecs: Vec<Codec>,
+    // length of each block after decompression
+    pub block_raw_sizes: Vec<RawSize>,
+    pub block_encodings: Vec<Encoding>,
+    pub block_checksums: Vec<Option<u32>>,
+    pub bloom_filter: Option<BloomFilterMeta>,
 }
 
-fn column_maker<'de, D>(deserializer: D) -> Result<HashMap<String, Column>, D::Error>
-where
-    D: serde::Deserializer<'de>,
-{
-    let mut map: HashMap<String, Column> = HashMap::new();
-    let value: Value = serde::Deserialize::deserialize(deserializer)?;
-    if let Value::Object(obj) = value {
-        for (key, value) in obj {
-            let column: Column = serde_json::from_value(value).unwrap();
-            map.insert(key.clone(), column.clone());
-
-            COLUMN_META.lock().unwrap().insert(
-                column.start_offset,
-                ColumnMeta {
-                    column_name: key,
-                    column_type: column.column_type.clone(),
-                    // FIXME: change this into dynamically calculated value
-                    elem_size: match column.column_type.as_str() {
-                        "float" => 4,
-                        "int" => 4,
-                        "str" => 32,
-                        _ => panic!("Invalid column type"),
-                    },
-                },
-            );
+impl ColumnMeta {
+    pub(crate) fn new(column_name: &str, column: &Column) -> Result<ColumnMeta, BuildError> {
+        let elem_size = column.column_type.elem_size();
+        let num_blocks = column.num_blocks();
+        let mut block_nums = Vec::with_capacity(num_blocks);
+        let mut block_offsets: Vec<usize> = vec![0];
+        let mut block_codecs = Vec::with_capacity(num_blocks);
+        let mut block_raw_sizes = Vec::with_capacity(num_blocks);
+        let mut block_encodings = Vec::with_capacity(num_blocks);
+        let mut block_checksums = Vec::with_capacity(num_blocks);
+        for (block, stats) in column.block_stats.iter().enumerate() {
+            let codec = stats.compression.or(column.compression).unwrap_or_default();
+            let encoding = stats.encoding.unwrap_or_default();
+            if !encoding.applies_to(column.column_type) {
+                return Err(BuildError::FormatError(format!(
+                    "block {} of {} column {} cannot be {} encoded",
+                    block,
+                    column.column_type.name(),
+                    column_name,
+                    encoding.name()
+                )));
+            }
+            let fixed_width = column.column_type.is_fixed_width()
+                && codec == Codec::None
+                && encoding == Encoding::Plain;
+            let size = match (fixed_width, stats.size) {
+                (true, _) => (stats.num as usize).saturating_mul(elem_size),
+                (false, Some(size)) => size,
+                (false, None) => {
+                    return Err(BuildError::FormatError(format!(
+                        "block {} of column {} has no size",
+                        block, column_name
+                    )))
+                }
+            };
+            let num = stats.num as usize;
+            let raw_size = match (column.column_type.is_fixed_width(), stats.raw_size) {
+                (true, _) if encoding == Encoding::Plain => {
+                    RawSize::Exact(num.saturating_mul(elem_size))
+                }
+                // at most a value and a code or run length per row, and a header
+                (true, _) => RawSize::AtMost(num.saturating_mul(elem_size + 8).saturating_add(64)),
+                (false, Some(raw_size)) => RawSize::Exact(raw_size),
+                (false, None) => RawSize::AtMost(MAX_RAW_BLOCK_SIZE),
+            };
+            block_nums.push(stats.num);
+            block_codecs.push(codec);
+            block_raw_sizes.push(raw_size);
+            block_encodings.push(encoding);
+            block_checksums.push(stats.crc32c);
+            let end = block_offsets[block].checked_add(size).ok_or_else(|| {
+                BuildError::FormatError(format!("column {} is too large", column_name))
+            })?;
+            block_offsets.push(end);
         }
+        Ok(ColumnMeta {
+            column_name: column_name.to_string(),
+            column_type: column.column_type,
+            elem_size,
+            start_offset: column.start_offset,
+            block_nums,
+            block_offsets,
+            block_codecs,
+            block_raw_sizes,
+            block_encodings,
+            block_checksums,
+            bloom_filter: column.bloom_filter.clone(),
+        })
+    }
+
+    // byte length of the column
+    pub fn len(&self) -> usize {
+        *self.block_offsets.last().unwrap()
     }
-    Ok(map)
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// split the metadata length, `width` bytes, from the end of the file
+fn split_footer(body: &[u8], width: usize) -> IResult<&[u8], &[u8]> {
+    take(body.len().saturating_sub(width))(body)
+}
+
+// `offset + len`, None if it does not fit in `bound`
+fn region_end(offset: usize, len: usize, bound: usize) -> Option<usize> {
+    offset.checked_add(len).filter(|end| *end <= bound)
 }
 
 impl ParserBuilder {
     pub fn new(filename: String, tablename: String) -> ParserBuilder {
         ParserBuilder {
-            filename: filename,
-            tablename: tablename,
+            filename,
+            tablename,
+            verify: VerifyMode::default(),
         }
     }
 
-    fn read_file_contents(filename: &str, data: &mut Vec<u8>) -> Result<(), BuildError> {
-        let mut file = File::open(filename)?;
-        file.read_to_end(data);
-        Ok(())
+    pub fn verify(&mut self, mode: VerifyMode) -> &mut Self {
+        self.verify = mode;
+        self
     }
 
+    pub(crate) fn map_file(filename: &str) -> Result<Mmap, BuildError> {
+        let file = File::open(filename)?;
+        // the file must not be modified while it is mapped, like postgres expects
+        // of the files behind foreign tables
+        Ok(unsafe { Mmap::map(&file)? })
+    }
 
-    pub fn build_index(&mut self) -> &mut Self {
-        todo!()
+    // the metadata of a whole file and the region between the header and the footer,
+    // where the columns are
+    pub(crate) fn read_metadata(
+        data: &[u8],
+        verify: VerifyMode,
+    ) -> Result<(Metadata, Range<usize>), BuildError> {
+        // versioned files are wrapped in magic bytes, legacy files are not
+        let has_header = data.len() >= 2 * MAGIC.len() && data.starts_with(MAGIC);
+        let trailer = data.len().checked_sub(MAGIC.len()).map(|end| &data[end..]);
+        let (versioned, width) = match (has_header, trailer) {
+            (true, Some(t)) if t == MAGIC => (true, 4),
+            (true, Some(t)) if t == MAGIC_WIDE => (true, 8),
+            (false, Some(t)) if t == MAGIC || t == MAGIC_WIDE => {
+                return Err(BuildError::FormatError(
+                    "file ends with the magic bytes but does not start with them".to_string(),
+                ));
+            }
+            _ => (false, 4),
+        };
+        let (header_len, file) = match versioned {
+            true => (MAGIC.len(), &data[..data.len() - MAGIC.len()]),
+            false => (0, data),
+        };
+        if file.len() < header_len + width {
+            return Err(BuildError::FormatError(
+                "file is too short to hold the metadata length".to_string(),
+            ));
+        }
+        let (size, body) = split_footer(file, width)
+            .map_err(|e| BuildError::FormatError(format!("invalid footer: {}", e)))?;
+        let size = match width {
+            4 => u32::from_le_bytes(size.try_into().unwrap()) as u64,
+            _ => u64::from_le_bytes(size.try_into().unwrap()),
+        };
+        let size = match usize::try_from(size) {
+            Ok(size) if size <= body.len() - header_len => size,
+            _ => {
+                return Err(BuildError::FormatError(format!(
+                    "metadata length {} exceeds the file size",
+                    size
+                )))
+            }
+        };
+        let (meta, raw) = take::<usize, &[u8], nom::error::Error<&[u8]>>(body.len() - size)(body)
+            .map_err(|e| BuildError::FormatError(format!("invalid footer: {}", e)))?;
+        let json_str = std::str::from_utf8(meta)
+            .map_err(|_| BuildError::FormatError("Invalid MetaData format".to_string()))?;
+        let metadata: Metadata = serde_json::from_str(json_str)?;
+        let version = match (versioned, metadata.format_version) {
+            (true, Some(version)) => version,
+            (false, None) => FormatVersion::LEGACY,
+            (true, None) => {
+                return Err(BuildError::FormatError(
+                    "metadata has no Format Version".to_string(),
+                ))
+            }
+            (false, Some(version)) => {
+                return Err(BuildError::FormatError(format!(
+                    "file of format version {} has no magic bytes",
+                    version
+                )))
+            }
+        };
+        if !version.is_supported() {
+            return Err(BuildError::UnsupportedVersion(version));
+        }
+        if width == 8 && version < FormatVersion::WIDE_FOOTER {
+            return Err(BuildError::FormatError(format!(
+                "64-bit metadata length needs format version {}, the file has {}",
+                FormatVersion::WIDE_FOOTER,
+                version
+            )));
+        }
+        if let Some((feature, needed)) = version.unsupported_feature(&metadata) {
+            return Err(BuildError::FormatError(format!(
+                "{} need format version {}, the file has {}",
+                feature, needed, version
+            )));
+        }
+        let raw = match metadata.checksum {
+            Some(checksum) => {
+                if raw.len() < header_len + 4 {
+                    return Err(BuildError::FormatError(
+                        "file is too short to hold the metadata checksum".to_string(),
+                    ));
+                }
+                let (raw, expected) = raw.split_at(raw.len() - 4);
+                if checksum.compute(meta) != u32::from_le_bytes(expected.try_into().unwrap()) {
+                    return Err(BuildError::FormatError(format!(
+                        "metadata does not match its {} checksum",
+                        checksum.name()
+                    )));
+                }
+                raw
+            }
+            None if verify == VerifyMode::Strict => {
+                return Err(BuildError::FormatError("file has no checksums".to_string()));
+            }
+            None => raw,
+        };
+        Ok((metadata, header_len..raw.len()))
     }
 
-    pub fn build<'par>(&mut self) -> Result<Parser, BuildError> {
-        let mut data = Vec::new();
-        ParserBuilder::read_file_contents(&self.filename, &mut data)?;
-        let body_bytes = &data[..];
-        let (i, o) =
-            take::<usize, &[u8], nom::error::Error<&[u8]>>(body_bytes.len() - 4)(body_bytes)
-                .unwrap();
-        println!("i: {:?}", i);
-        println!("o: {:?}", o);
-        let size: u32 = u32::from_le_bytes(i.try_into().unwrap());
-        println!("{}", size);
-        let (meta, raw) =
-            take::<usize, &[u8], nom::error::Error<&[u8]>>(o.len() - size as usize)(o).unwrap();
-        let json_str = std::str::from_utf8(meta).expect("DB721|Metadata: Invalid MetaData format");
-        println!("json_str: {:?}", json_str);
-        let json_struct: Metadata =
-            serde_json::from_str(json_str).expect("DB721|Metadata: Invalid json format");
-        println!("meta_struct: {:#?}", json_struct);
-        println!("raw size: {}", raw.len());
+    pub fn build(&mut self) -> Result<Parser, BuildError> {
+        let data = ParserBuilder::map_file(&self.filename)?;
+        let (metadata, region) = ParserBuilder::read_metadata(&data, self.verify)?;
+        let identity = FileIdentity {
+            len: data.len() as u64,
+            metadata_crc32c: Checksum::Crc32c.compute(&data[region.end..]),
+        };
 
         // parse raw
         // pushdowns: qual and sort.
         // qual stores an index,
         // sort needs an array of index, so we save indexes to b+ trees.
-        let mut offsets = vec![];
-        {
-            let cm = COLUMN_META.lock().unwrap();
-            let rawb = &*cm;
-            rawb.keys().for_each(|k| {
-            offsets.push(*k);
-            });
-        } 
-        let len = raw.len();
-        offsets.push(len);
-        println!("{:#?}", offsets);
-
-        let astcell = AstCell::new(raw.to_vec(), |raw_data| {
-            let raw = raw_data.as_slice();
-            let mut column_raw = HashMap::new();
-            let mut curdata = raw;
-            offsets.windows(2).for_each(|w| {
-                let (i, o) =
-                    take::<usize, &[u8], nom::error::Error<&[u8]>>(w[1] - w[0])(curdata).unwrap();
-                curdata = i;
-                column_raw.insert({
-                    let cm = COLUMN_META.lock().unwrap();
-                    let meta = cm.get(&w[0]).unwrap();
-                    meta.column_name.to_string()
-                }, o);
-            });
+        let mut columns = metadata
+            .columns
+            .iter()
+            .map(|(name, column)| ColumnMeta::new(name, column))
+            .collect::<Result<Vec<_>, _>>()?;
+        columns.sort_by_key(|c| c.start_offset);
+        // every region must lie between the header and the metadata, without overlapping
+        let mut prev_end = region.start;
+        for meta in &columns {
+            match region_end(meta.start_offset, meta.len(), region.end) {
+                Some(end) if meta.start_offset >= prev_end => prev_end = end,
+                _ => {
+                    return Err(BuildError::FormatError(format!(
+                        "column {} at offset {} exceeds the data region or overlaps another column",
+                        meta.column_name, meta.start_offset
+                    )))
+                }
+            }
+        }
+        for meta in &columns {
+            if let Some(bloom) = &meta.bloom_filter {
+                let len = bloom.num_bytes.checked_mul(meta.block_nums.len());
+                if bloom.offset < region.start
+                    || len
+                        .and_then(|len| region_end(bloom.offset, len, region.end))
+                        .is_none()
+                {
+                    return Err(BuildError::FormatError(format!(
+                        "bloom filters of column {} exceed the data region",
+                        meta.column_name
+                    )));
+                }
+            }
+        }
+        // a row is a value of every column, the rows are counted in the first one
+        if let Some(first) = columns.first() {
+            if let Some(meta) = columns.iter().find(|c| c.block_nums != first.block_nums) {
+                return Err(BuildError::FormatError(format!(
+                    "blocks of column {} hold other numbers of values than column {}",
+                    meta.column_name, first.column_name
+                )));
+            }
+        }
+
+        let astcell = AstCell::new(Arc::new(data), |raw_data| {
+            let raw = &raw_data[..];
+            let column_raw = columns
+                .iter()
+                .map(|meta| {
+                    (
+                        meta.column_name.clone(),
+                        &raw[meta.start_offset..meta.start_offset + meta.len()],
+                    )
+                })
+                .collect();
             ColRaw(column_raw)
-            });
+        });
 
-        Ok(Parser::new(
+        let mut parser = Parser::new(
             self.tablename.clone(),
             astcell,
-        ))
+            metadata,
+            columns,
+            self.verify,
+        );
+        parser.deletes = DeleteVector::read(&self.filename, parser.num_rows(), identity)?;
+        parser.identity = identity;
+        Ok(parser)
     }
 }
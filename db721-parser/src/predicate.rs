@@ -0,0 +1,204 @@
+use std::cmp::Ordering;
+
+use crate::bloom::BloomFilter;
+use crate::encoding::{DictionaryBlock, RleBlock};
+use crate::parser::{BlockStats, BuildError, Parser};
+use crate::types::{ColumnBlock, ColumnType, ScalarValue};
+
+/*
+Predicates pushed down to the parser. A list of predicates is a conjunction.
+
+They are used in two places:
+    pruning:    `Parser::prune` skips the blocks whose stats prove that no row can match,
+                and the blocks whose bloom filter does not hold the values of `=` and `IN`
+    filtering:  `Parser::filter_block` evaluates them on the rows of one block,
+                on the codes for dictionary encoded blocks and once per run for
+                run-length encoded blocks, after leaving out the deleted rows
+
+Both are conservative: a predicate that cannot be evaluated (unknown column,
+incomparable types) keeps the block and the rows, postgres rechecks them anyway.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    // column equals any of the values
+    In,
+    // list[str] column holds the value, i.e. `value = ANY(column)`
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Predicate {
+    pub column: String,
+    pub operator: Operator,
+    pub values: Vec<ScalarValue>,
+}
+
+impl Predicate {
+    pub fn new(column: &str, operator: Operator, value: ScalarValue) -> Predicate {
+        Predicate {
+            column: column.to_string(),
+            operator,
+            values: vec![value],
+        }
+    }
+
+    pub fn in_list(column: &str, values: Vec<ScalarValue>) -> Predicate {
+        Predicate {
+            column: column.to_string(),
+            operator: Operator::In,
+            values,
+        }
+    }
+
+    // whether a block with these stats may hold a matching row
+    pub fn may_match(&self, ty: ColumnType, stats: &BlockStats) -> bool {
+        if stats.num == 0 {
+            return false;
+        }
+        let (min, max) = match (&stats.min, &stats.max) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return true,
+        };
+        // None means incomparable, which must not prune
+        let within = |v: &ScalarValue| {
+            v.compare(min) != Some(Ordering::Less) && v.compare(max) != Some(Ordering::Greater)
+        };
+        let may = |bound: &ScalarValue, rejected: &[Ordering]| {
+            bound
+                .compare(&self.values[0])
+                .is_none_or(|o| !rejected.contains(&o))
+        };
+        match (self.operator, ty) {
+            (Operator::Contains, ColumnType::StrList) => within(&self.values[0]),
+            (Operator::Contains, _) | (_, ColumnType::StrList) => true,
+            (Operator::Eq, _) => within(&self.values[0]),
+            (Operator::In, _) => self.values.iter().any(within),
+            (Operator::Lt, _) => may(min, &[Ordering::Greater, Ordering::Equal]),
+            (Operator::Le, _) => may(min, &[Ordering::Greater]),
+            (Operator::Gt, _) => may(max, &[Ordering::Less, Ordering::Equal]),
+            (Operator::Ge, _) => may(max, &[Ordering::Less]),
+        }
+    }
+
+    // whether a block with this bloom filter may hold a matching row
+    pub fn may_match_bloom(&self, ty: ColumnType, bloom: &BloomFilter<&[u8]>) -> bool {
+        match self.operator {
+            Operator::Eq | Operator::Contains => bloom.may_contain_value(ty, &self.values[0]),
+            Operator::In => self.values.iter().any(|v| bloom.may_contain_value(ty, v)),
+            _ => true,
+        }
+    }
+
+    // whether a single value matches
+    pub fn matches(&self, value: &ScalarValue) -> bool {
+        let cmp = |v: &ScalarValue| value.compare(v);
+        match self.operator {
+            Operator::Contains => match (value, &self.values[0]) {
+                (ScalarValue::StrList(list), ScalarValue::Str(v)) => list.contains(v),
+                _ => true,
+            },
+            Operator::Eq => cmp(&self.values[0]).is_none_or(|o| o == Ordering::Equal),
+            Operator::In => self
+                .values
+                .iter()
+                .any(|v| cmp(v).is_none_or(|o| o == Ordering::Equal)),
+            Operator::Lt => cmp(&self.values[0]).is_none_or(|o| o == Ordering::Less),
+            Operator::Le => cmp(&self.values[0]).is_none_or(|o| o != Ordering::Greater),
+            Operator::Gt => cmp(&self.values[0]).is_none_or(|o| o == Ordering::Greater),
+            Operator::Ge => cmp(&self.values[0]).is_none_or(|o| o != Ordering::Less),
+        }
+    }
+
+    // evaluate once per dictionary value and select the rows by their codes,
+    // the strings of the rows are never materialized
+    pub fn filter_dictionary(&self, block: &DictionaryBlock, selection: &mut [bool]) {
+        let matching: Vec<bool> = block
+            .dictionary
+            .iter()
+            .map(|v| self.matches(&ScalarValue::Str(v.clone())))
+            .collect();
+        for (selected, code) in selection.iter_mut().zip(&block.codes) {
+            *selected &= matching[*code as usize];
+        }
+    }
+
+    // evaluate once per run of a run-length encoded block
+    pub fn filter_runs(&self, block: &RleBlock, selection: &mut [bool]) {
+        let mut row = 0;
+        for (run, len) in block.lengths.iter().enumerate() {
+            let end = (row + *len as usize).min(selection.len());
+            if !block.values.get(run).is_none_or(|v| self.matches(&v)) {
+                selection[row..end].iter_mut().for_each(|s| *s = false);
+            }
+            row = end;
+        }
+    }
+
+    // evaluate on every row of a decoded block
+    pub fn filter(&self, block: &ColumnBlock, selection: &mut [bool]) {
+        for (row, selected) in selection.iter_mut().enumerate() {
+            if *selected {
+                *selected = block.get(row).is_none_or(|v| self.matches(&v));
+            }
+        }
+    }
+}
+
+impl Parser {
+    // the blocks that may hold rows matching all predicates
+    pub fn prune(&self, predicates: &[Predicate]) -> Vec<usize> {
+        (0..self.num_blocks())
+            .filter(|block| {
+                predicates.iter().all(|pred| {
+                    match (
+                        self.column_meta(&pred.column),
+                        self.block_stats(&pred.column, *block),
+                    ) {
+                        (Some(meta), Some(stats)) => {
+                            pred.may_match(meta.column_type, stats)
+                                && self
+                                    .bloom_filter(&pred.column, *block)
+                                    .is_none_or(|b| pred.may_match_bloom(meta.column_type, &b))
+                        }
+                        _ => true,
+                    }
+                })
+            })
+            .collect()
+    }
+
+    // which rows of `block` match all predicates
+    pub fn filter_block(
+        &self,
+        block: usize,
+        predicates: &[Predicate],
+    ) -> Result<Vec<bool>, BuildError> {
+        // deleted rows never match
+        let start = self.block_start(block);
+        let mut selection: Vec<bool> = (start..start + self.block_len(block))
+            .map(|row| !self.deletes.is_deleted(row))
+            .collect();
+        for pred in predicates {
+            if self.column_meta(&pred.column).is_none() {
+                continue;
+            }
+            if !selection.iter().any(|s| *s) {
+                break;
+            }
+            if let Some(dictionary) = self.read_dictionary(&pred.column, block)? {
+                pred.filter_dictionary(&dictionary, &mut selection);
+            } else if let Some(runs) = self.read_runs(&pred.column, block)? {
+                pred.filter_runs(&runs, &mut selection);
+            } else {
+                pred.filter(&self.read_block(&pred.column, block)?, &mut selection);
+            }
+        }
+        Ok(selection)
+    }
+}
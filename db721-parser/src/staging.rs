@@ -0,0 +1,405 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::deletes::DeleteVector;
+
+/*
+Changes to db721 files are staged, then published:
+
+    lock        `FileLock::acquire` takes an exclusive advisory lock on `<file>.lock`, held
+                by appends, deletes and compactions from reading a file until its
+                replacement is in place. The lock file is never removed, so that the
+                lock outlives the renames of the file. Several files are locked in
+                sorted order.
+    stage       new files are written and synced under temporary names next to their
+                final paths, `.<name>.<pid>-<n>.tmp`, which no dataset reads. Files to
+                remove are staged by their path.
+    publish     the staged files are renamed over their final paths and the removed
+                ones unlinked, in the order they were staged, under a journal
+
+The journal makes the outcome of a publish all or nothing. Before anything is renamed,
+every path gets a journal `.<name>.journal` next to it, listing all changes of the
+publish. The journal of the first path is the primary one, locked by the writer while
+it publishes. Every path replaced or removed is hard linked to a backup first. Removing
+the primary journal commits the changes, the backups and the other journals are
+removed after it:
+
+    failure     an error before the commit undoes the changes made so far, from the
+                backups, and removes the journals
+    crash       the journals stay behind. `recover` finds the one of a path: while the
+                primary journal exists, the publish did not commit and is undone,
+                without it only the backups and the journals are left to remove
+
+Writers recover: `FileLock::acquire` the file and its delete vector once it holds the
+lock, an append to a directory the directory, and `publish` every path it changes.
+`db721 recover` recovers files and directories on demand, e.g. of a table that is only
+read.
+
+Readers neither lock nor change anything, they need no more than read access: a
+rename replaces a single file at once, but a reader opening the files of a publish one
+after another while it runs may open some of them before and others after it, and
+until a crashed publish is recovered it reads the files as the crash left them. Staged
+files that are not published, after an error anywhere, are removed when dropped.
+*/
+
+pub struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    // blocks until no other writer holds the lock of the file, then recovers the file
+    // and its delete vector
+    pub fn acquire(filename: &str) -> Result<FileLock, std::io::Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(format!("{}.lock", canonical(filename)))?;
+        file.lock()?;
+        recover(filename)?;
+        recover(&DeleteVector::path(filename))?;
+        Ok(FileLock { _file: file })
+    }
+
+    // the locks of the files, taken in sorted order so that writers do not deadlock
+    pub fn acquire_all<'a>(
+        filenames: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Vec<FileLock>, std::io::Error> {
+        let mut filenames: Vec<_> = filenames.into_iter().map(canonical).collect();
+        filenames.sort_unstable();
+        filenames.dedup();
+        filenames.iter().map(|f| FileLock::acquire(f)).collect()
+    }
+}
+
+// the same lock for every path of a file, as far as it exists
+fn canonical(filename: &str) -> String {
+    std::fs::canonicalize(filename).map_or(filename.to_string(), |path| {
+        path.to_string_lossy().into_owned()
+    })
+}
+
+// a temporary path next to `path`, unique to this process and call
+pub fn tmp_path(path: &str) -> String {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let path = Path::new(path);
+    let name = path
+        .file_name()
+        .map_or(String::new(), |n| n.to_string_lossy().into_owned());
+    let tmp = format!(
+        ".{}.{}-{}.tmp",
+        name,
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    );
+    path.with_file_name(tmp).to_string_lossy().into_owned()
+}
+
+// unique to this process and call, and to the processes before with the same pid
+fn unique_id() -> String {
+    let nanos = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos());
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    format!("{}-{}-{}", nanos, std::process::id(), n)
+}
+
+// the journal of a publish changing `path`
+fn journal_path(path: &str) -> String {
+    let path = Path::new(path);
+    let name = path
+        .file_name()
+        .map_or(String::new(), |n| n.to_string_lossy().into_owned());
+    path.with_file_name(format!(".{}.journal", name))
+        .to_string_lossy()
+        .into_owned()
+}
+
+enum Change {
+    // a temporary file renamed over the path
+    Rename(String),
+    Remove,
+}
+
+#[derive(Default)]
+pub struct Staged {
+    // final paths and their changes
+    changes: Vec<(String, Change)>,
+}
+
+impl Staged {
+    // stage a written temporary file
+    pub fn push(&mut self, tmp: String, path: String) {
+        self.changes.push((path, Change::Rename(tmp)));
+    }
+
+    // stage the removal of a file, if it exists when published
+    pub fn remove(&mut self, path: String) {
+        self.changes.push((path, Change::Remove));
+    }
+
+    // stage the changes of `other` after these, but for the paths changed here already
+    pub fn extend(&mut self, mut other: Staged) {
+        for (path, change) in std::mem::take(&mut other.changes) {
+            match (self.is_staged(&path), change) {
+                (true, Change::Rename(tmp)) => {
+                    let _ = std::fs::remove_file(tmp);
+                }
+                (true, Change::Remove) => {}
+                (false, change) => self.changes.push((path, change)),
+            }
+        }
+    }
+
+    fn is_staged(&self, path: &str) -> bool {
+        self.changes.iter().any(|(p, _)| p == path)
+    }
+
+    // the temporary file staged for `path`
+    pub fn staged(&self, path: &str) -> Option<&str> {
+        self.changes.iter().find_map(|(p, change)| match change {
+            Change::Rename(tmp) if p == path => Some(tmp.as_str()),
+            _ => None,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    // publish the changes under a journal, all of them or, after an error, none.
+    // Returns the paths changed.
+    pub fn publish(mut self) -> Result<Vec<String>, std::io::Error> {
+        // left behind by a publish that crashed, resolved before they are replaced
+        for (path, _) in &self.changes {
+            recover(path)?;
+        }
+        let changes = std::mem::take(&mut self.changes);
+        let mut journal = Journal {
+            id: unique_id(),
+            primary: changes
+                .first()
+                .map_or(String::new(), |(p, _)| journal_path(p)),
+            journals: Vec::new(),
+            entries: Vec::with_capacity(changes.len()),
+        };
+        for (path, change) in changes {
+            let exists = Path::new(&path).exists();
+            let journal_path = journal_path(&path);
+            if !journal.journals.contains(&journal_path) {
+                journal.journals.push(journal_path);
+            }
+            journal.entries.push(Entry {
+                backup: exists.then(|| tmp_path(&path)),
+                tmp: match change {
+                    Change::Rename(tmp) => Some(tmp),
+                    Change::Remove => None,
+                },
+                path,
+            });
+        }
+        if journal.entries.is_empty() {
+            return Ok(Vec::new());
+        }
+        // the primary journal is locked before it appears under its name, so that no
+        // one takes it for the journal of a crashed publish
+        let mut primary = None;
+        let result = serde_json::to_vec(&journal)
+            .map_err(std::io::Error::from)
+            .and_then(|data| {
+                primary = Some(write_journal(&journal.primary, &data, true)?);
+                journal.journals[1..]
+                    .iter()
+                    .try_for_each(|path| write_journal(path, &data, false).map(|_| ()))
+            })
+            .and_then(|_| journal.entries.iter().try_for_each(Entry::apply))
+            // the commit
+            .and_then(|_| std::fs::remove_file(&journal.primary));
+        if let Err(e) = result {
+            // the journals stay for `recover` if the changes cannot be undone
+            if journal.undo().is_ok() {
+                journal.remove_journals();
+            }
+            return Err(e);
+        }
+        drop(primary);
+        journal.clean_up();
+        Ok(journal.entries.into_iter().map(|e| e.path).collect())
+    }
+}
+
+impl Drop for Staged {
+    fn drop(&mut self) {
+        for (_, change) in &self.changes {
+            if let Change::Rename(tmp) = change {
+                let _ = std::fs::remove_file(tmp);
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Journal {
+    // unique to the publish
+    id: String,
+    primary: String,
+    // the journals of all paths, the primary one first
+    journals: Vec<String>,
+    entries: Vec<Entry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    path: String,
+    // the staged file renamed over `path`, None when it is removed
+    tmp: Option<String>,
+    // a hard link to the file `path` held before, None when there was none
+    backup: Option<String>,
+}
+
+impl Entry {
+    fn apply(&self) -> Result<(), std::io::Error> {
+        if let Some(backup) = &self.backup {
+            std::fs::hard_link(&self.path, backup)?;
+        }
+        match &self.tmp {
+            Some(tmp) => std::fs::rename(tmp, &self.path),
+            None => remove_if_exists(&self.path),
+        }
+    }
+
+    // put back the file `path` held before, whether the entry was applied or not
+    fn undo(&self) -> Result<(), std::io::Error> {
+        if let Some(tmp) = &self.tmp {
+            remove_if_exists(tmp)?;
+        }
+        match &self.backup {
+            // renaming a link over another link of the same file does nothing
+            Some(backup) if Path::new(backup).exists() => {
+                std::fs::rename(backup, &self.path)?;
+                remove_if_exists(backup)
+            }
+            // not applied yet, the link is made first
+            Some(_) => Ok(()),
+            // the staged file is new, no one else writes under its name
+            None if self.tmp.is_some() => remove_if_exists(&self.path),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Journal {
+    fn read(path: &str) -> Result<Option<Journal>, std::io::Error> {
+        match std::fs::read(path) {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn undo(&self) -> Result<(), std::io::Error> {
+        self.entries.iter().rev().try_for_each(Entry::undo)
+    }
+
+    // the primary journal goes last, its removal ends the publish. A journal of
+    // another publish that took the path since is left alone.
+    fn remove_journals(&self) {
+        for path in self.journals.iter().rev() {
+            if Journal::read(path).is_ok_and(|j| j.is_some_and(|j| j.id == self.id)) {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    // once committed
+    fn clean_up(&self) {
+        for entry in &self.entries {
+            if let Some(backup) = &entry.backup {
+                let _ = std::fs::remove_file(backup);
+            }
+        }
+        self.remove_journals();
+    }
+}
+
+// write a journal under a temporary name, then rename it into place. The primary
+// journal is returned locked.
+fn write_journal(path: &str, data: &[u8], lock: bool) -> Result<File, std::io::Error> {
+    let tmp = tmp_path(path);
+    let mut file = File::create(&tmp)?;
+    let result = (|| {
+        if lock {
+            file.lock()?;
+        }
+        file.write_all(data)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, path)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    result.map(|_| file)
+}
+
+// finish the publish that left a journal for `path`, if any: wait for it while it is
+// under way, undo it if it crashed before the commit and clean up after it otherwise.
+// Returns whether there was a journal.
+pub fn recover(path: &str) -> Result<bool, std::io::Error> {
+    let journal = match Journal::read(&journal_path(path))? {
+        Some(journal) => journal,
+        None => return Ok(false),
+    };
+    let primary = match File::open(&journal.primary) {
+        Ok(primary) => primary,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            journal.clean_up();
+            return Ok(true);
+        }
+        Err(e) => return Err(e),
+    };
+    // held by the writer until the publish is over
+    primary.lock()?;
+    match Journal::read(&journal.primary)? {
+        // the writer is gone, and so is its lock
+        Some(current) if current.id == journal.id => {
+            journal.undo()?;
+            journal.remove_journals();
+        }
+        _ => journal.clean_up(),
+    }
+    Ok(true)
+}
+
+// recover every path below the directory that has a journal. Returns whether there
+// was any.
+pub fn recover_dir(directory: &Path) -> Result<bool, std::io::Error> {
+    let mut recovered = false;
+    for entry in std::fs::read_dir(directory)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() {
+            recovered |= recover_dir(&entry.path())?;
+            continue;
+        }
+        let name = entry.file_name();
+        let name = name.to_string_lossy();
+        if let Some(target) = name
+            .strip_prefix('.')
+            .and_then(|n| n.strip_suffix(".journal"))
+        {
+            let path = directory.join(target);
+            recovered |= recover(&path.to_string_lossy())?;
+        }
+    }
+    Ok(recovered)
+}
+
+fn remove_if_exists(path: &str) -> Result<(), std::io::Error> {
+    match std::fs::remove_file(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
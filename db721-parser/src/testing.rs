@@ -0,0 +1,51 @@
+use std::fs::File;
+
+pub use db721_testing::TmpPath;
+
+use crate::parser::{Parser, ParserBuilder};
+use crate::writer::Writer;
+
+/*
+Helpers for the tests of this crate, besides those of db721-testing:
+
+    write_table     writes a table to a new TmpPath
+    write_file      writes a table to a given path, e.g. inside a TmpPath directory
+    open            the parser of a file, panics if it does not build
+    farms_path      the sample file of db721-gen
+*/
+
+// write the table `table` to `path`, `columns` writes the columns
+pub fn write_file(
+    path: &str,
+    table: &str,
+    max_values_per_block: u32,
+    columns: impl FnOnce(&mut Writer<File>),
+) {
+    let file = File::create(path).unwrap();
+    let mut writer = Writer::new(table.to_string(), file, max_values_per_block);
+    columns(&mut writer);
+    writer.finalize().unwrap();
+}
+
+// write the table `table` to a new temporary file
+pub fn write_table(
+    name: &str,
+    table: &str,
+    max_values_per_block: u32,
+    columns: impl FnOnce(&mut Writer<File>),
+) -> TmpPath {
+    let path = TmpPath::file(name);
+    write_file(&path, table, max_values_per_block, columns);
+    path
+}
+
+pub fn open(path: &str, table: &str) -> Parser {
+    ParserBuilder::new(path.to_string(), table.to_string())
+        .build()
+        .unwrap()
+}
+
+// the sample file of db721-gen
+pub fn farms_path() -> String {
+    db721_testing::gen_path("data-farms.db721")
+}
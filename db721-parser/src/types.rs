@@ -0,0 +1,583 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::cmp::Ordering;
+
+use crate::parser::BuildError;
+
+/*
+Column types and their on-disk layout (all little-endian):
+
+    "int"       i32                                   4 bytes
+    "float"     f32                                   4 bytes
+    "str"       NUL-terminated ASCII, padded          32 bytes
+    "bigint"    i64                                   8 bytes
+    "double"    f64                                   8 bytes
+    "bool"      0 or 1                                1 byte
+    "date"      i32, days since 1970-01-01            4 bytes
+    "timestamp" i64, microseconds since 1970-01-01 UTC 8 bytes
+    "list[str]" u32 element count, then the elements   4 + 32 * count bytes
+                as "str"
+
+In the block stats, "date" and "timestamp" min/max are stored as the same
+integers that are written to disk, "bool" min/max as JSON booleans.
+For "list[str]", min/max/min_len/max_len are taken over the elements of all
+lists in the block, and "size" holds the byte length of the block.
+*/
+
+pub const STR_SIZE: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ColumnType {
+    #[serde(rename = "int")]
+    Int,
+    #[serde(rename = "float")]
+    Float,
+    #[serde(rename = "str")]
+    Str,
+    #[serde(rename = "bigint")]
+    BigInt,
+    #[serde(rename = "double")]
+    Double,
+    #[serde(rename = "bool")]
+    Bool,
+    #[serde(rename = "date")]
+    Date,
+    #[serde(rename = "timestamp")]
+    Timestamp,
+    #[serde(rename = "list[str]")]
+    StrList,
+}
+
+impl ColumnType {
+    // size of the element in bytes, for list[str] the size of one list element
+    pub fn elem_size(&self) -> usize {
+        match self {
+            ColumnType::Int | ColumnType::Float | ColumnType::Date => 4,
+            ColumnType::BigInt | ColumnType::Double | ColumnType::Timestamp => 8,
+            ColumnType::Bool => 1,
+            ColumnType::Str | ColumnType::StrList => STR_SIZE,
+        }
+    }
+
+    // whether every value takes exactly `elem_size` bytes
+    pub fn is_fixed_width(&self) -> bool {
+        *self != ColumnType::StrList
+    }
+
+    // the type name used in the metadata
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColumnType::Int => "int",
+            ColumnType::Float => "float",
+            ColumnType::Str => "str",
+            ColumnType::BigInt => "bigint",
+            ColumnType::Double => "double",
+            ColumnType::Bool => "bool",
+            ColumnType::Date => "date",
+            ColumnType::Timestamp => "timestamp",
+            ColumnType::StrList => "list[str]",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<ColumnType> {
+        [
+            ColumnType::Int,
+            ColumnType::Float,
+            ColumnType::Str,
+            ColumnType::BigInt,
+            ColumnType::Double,
+            ColumnType::Bool,
+            ColumnType::Date,
+            ColumnType::Timestamp,
+            ColumnType::StrList,
+        ]
+        .into_iter()
+        .find(|ty| ty.name() == name)
+    }
+}
+
+// A single decoded value
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub enum ScalarValue {
+    Int(i32),
+    Float(f32),
+    Str(String),
+    BigInt(i64),
+    Double(f64),
+    Bool(bool),
+    Date(i32),
+    Timestamp(i64),
+    StrList(Vec<String>),
+}
+
+impl ScalarValue {
+    pub fn column_type(&self) -> ColumnType {
+        match self {
+            ScalarValue::Int(_) => ColumnType::Int,
+            ScalarValue::Float(_) => ColumnType::Float,
+            ScalarValue::Str(_) => ColumnType::Str,
+            ScalarValue::BigInt(_) => ColumnType::BigInt,
+            ScalarValue::Double(_) => ColumnType::Double,
+            ScalarValue::Bool(_) => ColumnType::Bool,
+            ScalarValue::Date(_) => ColumnType::Date,
+            ScalarValue::Timestamp(_) => ColumnType::Timestamp,
+            ScalarValue::StrList(_) => ColumnType::StrList,
+        }
+    }
+
+    // interpret a min/max value of the block stats as a value of `ty`
+    pub fn from_json(ty: ColumnType, value: &Value) -> Option<ScalarValue> {
+        match ty {
+            ColumnType::Int => value
+                .as_i64()
+                .and_then(|v| i32::try_from(v).ok())
+                .map(ScalarValue::Int),
+            ColumnType::Float => value.as_f64().map(|v| ScalarValue::Float(v as f32)),
+            ColumnType::Str | ColumnType::StrList => {
+                value.as_str().map(|v| ScalarValue::Str(v.to_string()))
+            }
+            ColumnType::BigInt => value.as_i64().map(ScalarValue::BigInt),
+            ColumnType::Double => value.as_f64().map(ScalarValue::Double),
+            ColumnType::Bool => value.as_bool().map(ScalarValue::Bool),
+            ColumnType::Date => value
+                .as_i64()
+                .and_then(|v| i32::try_from(v).ok())
+                .map(ScalarValue::Date),
+            ColumnType::Timestamp => value.as_i64().map(ScalarValue::Timestamp),
+        }
+    }
+
+    pub fn to_json(&self) -> Value {
+        match self {
+            ScalarValue::Int(v) | ScalarValue::Date(v) => Value::from(*v),
+            ScalarValue::Float(v) => Value::from(*v),
+            ScalarValue::Str(v) => Value::from(v.as_str()),
+            ScalarValue::BigInt(v) | ScalarValue::Timestamp(v) => Value::from(*v),
+            ScalarValue::Double(v) => Value::from(*v),
+            ScalarValue::Bool(v) => Value::from(*v),
+            ScalarValue::StrList(v) => Value::from(v.clone()),
+        }
+    }
+
+    // append the on-disk representation of the value to `out`
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            ScalarValue::Int(v) | ScalarValue::Date(v) => out.extend_from_slice(&v.to_le_bytes()),
+            ScalarValue::Float(v) => out.extend_from_slice(&v.to_le_bytes()),
+            ScalarValue::Str(v) => encode_str(v, out),
+            ScalarValue::BigInt(v) | ScalarValue::Timestamp(v) => {
+                out.extend_from_slice(&v.to_le_bytes())
+            }
+            ScalarValue::Double(v) => out.extend_from_slice(&v.to_le_bytes()),
+            ScalarValue::Bool(v) => out.push(*v as u8),
+            ScalarValue::StrList(v) => {
+                out.extend_from_slice(&(v.len() as u32).to_le_bytes());
+                v.iter().for_each(|s| encode_str(s, out));
+            }
+        }
+    }
+
+    // compare values of compatible types, numbers of different widths compare by value
+    pub fn compare(&self, other: &ScalarValue) -> Option<Ordering> {
+        match (self, other) {
+            (ScalarValue::Str(a), ScalarValue::Str(b)) => a.partial_cmp(b),
+            (ScalarValue::Bool(a), ScalarValue::Bool(b)) => a.partial_cmp(b),
+            (ScalarValue::Date(a), ScalarValue::Date(b)) => a.partial_cmp(b),
+            (ScalarValue::Timestamp(a), ScalarValue::Timestamp(b)) => a.partial_cmp(b),
+            _ => match (self.as_i64(), other.as_i64()) {
+                (Some(a), Some(b)) => a.partial_cmp(&b),
+                _ => self.as_f64()?.partial_cmp(&other.as_f64()?),
+            },
+        }
+    }
+
+    // the same value as a value of type `ty`, None if it does not convert exactly
+    pub fn cast(&self, ty: ColumnType) -> Option<ScalarValue> {
+        if self.column_type() == ty {
+            return Some(self.clone());
+        }
+        if let Some(v) = self.as_i64() {
+            return match ty {
+                ColumnType::Int => i32::try_from(v).ok().map(ScalarValue::Int),
+                ColumnType::BigInt => Some(ScalarValue::BigInt(v)),
+                ColumnType::Float => {
+                    ((v as f32) as i128 == v as i128).then_some(ScalarValue::Float(v as f32))
+                }
+                ColumnType::Double => {
+                    ((v as f64) as i128 == v as i128).then_some(ScalarValue::Double(v as f64))
+                }
+                _ => None,
+            };
+        }
+        let v = self.as_f64()?;
+        match ty {
+            ColumnType::Int | ColumnType::BigInt if v.fract() == 0.0 => {
+                ScalarValue::BigInt(v as i64)
+                    .cast(ty)
+                    .filter(|c| c.as_f64() == Some(v))
+            }
+            ColumnType::Float => ((v as f32) as f64 == v).then_some(ScalarValue::Float(v as f32)),
+            ColumnType::Double => Some(ScalarValue::Double(v)),
+            _ => None,
+        }
+    }
+
+    fn as_i64(&self) -> Option<i64> {
+        match self {
+            ScalarValue::Int(v) => Some(*v as i64),
+            ScalarValue::BigInt(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match self {
+            ScalarValue::Int(v) => Some(*v as f64),
+            ScalarValue::BigInt(v) => Some(*v as f64),
+            ScalarValue::Float(v) => Some(*v as f64),
+            ScalarValue::Double(v) => Some(*v),
+            _ => None,
+        }
+    }
+
+    // the strings covered by the block stats of the value
+    pub fn stats_values(&self) -> Vec<ScalarValue> {
+        match self {
+            ScalarValue::StrList(v) => v.iter().cloned().map(ScalarValue::Str).collect(),
+            v => vec![v.clone()],
+        }
+    }
+
+    // the inverse of `Display`, timestamps may also use 'T' as separator and carry
+    // another UTC offset or none at all
+    pub fn parse(ty: ColumnType, text: &str) -> Option<ScalarValue> {
+        Some(match ty {
+            ColumnType::Int => ScalarValue::Int(text.parse().ok()?),
+            ColumnType::Float => ScalarValue::Float(text.parse().ok()?),
+            ColumnType::Str => ScalarValue::Str(text.to_string()),
+            ColumnType::BigInt => ScalarValue::BigInt(text.parse().ok()?),
+            ColumnType::Double => ScalarValue::Double(text.parse().ok()?),
+            ColumnType::Bool => match text {
+                "true" | "t" => ScalarValue::Bool(true),
+                "false" | "f" => ScalarValue::Bool(false),
+                _ => return None,
+            },
+            ColumnType::Date => ScalarValue::Date(i32::try_from(parse_date(text)?).ok()?),
+            ColumnType::Timestamp => ScalarValue::Timestamp(parse_timestamp(text)?),
+            ColumnType::StrList => ScalarValue::StrList(parse_list(text)?),
+        })
+    }
+}
+
+// the elements of a one-dimensional array literal: `{a,"b,c",""}`. Elements may be
+// quoted and use backslash escapes, unquoted ones lose their surrounding whitespace.
+// An unquoted NULL is rejected, lists have no NULL elements.
+fn parse_list(text: &str) -> Option<Vec<String>> {
+    let mut chars = text.trim().strip_prefix('{')?.chars().peekable();
+    let mut elems = Vec::new();
+    let skip_whitespace = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    };
+    skip_whitespace(&mut chars);
+    if chars.next_if_eq(&'}').is_some() {
+        return chars.next().is_none().then_some(elems);
+    }
+    loop {
+        skip_whitespace(&mut chars);
+        let mut elem = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => elem.push(chars.next()?),
+                    c => elem.push(c),
+                }
+            }
+            skip_whitespace(&mut chars);
+        } else {
+            // the length without trailing whitespace that was not escaped
+            let (mut len, mut escaped) = (0, false);
+            while let Some(c) = chars.next_if(|c| !matches!(c, ',' | '}')) {
+                match c {
+                    '{' | '"' => return None,
+                    '\\' => {
+                        elem.push(chars.next()?);
+                        escaped = true;
+                    }
+                    c => elem.push(c),
+                }
+                if !c.is_whitespace() {
+                    len = elem.len();
+                }
+            }
+            elem.truncate(len);
+            if elem.is_empty() || !escaped && elem.eq_ignore_ascii_case("null") {
+                return None;
+            }
+        }
+        elems.push(elem);
+        match chars.next()? {
+            ',' => continue,
+            '}' => return chars.next().is_none().then_some(elems),
+            _ => return None,
+        }
+    }
+}
+
+// an element of an array literal, quoted when postgres would not read it back as is
+fn fmt_elem(f: &mut std::fmt::Formatter<'_>, elem: &str) -> std::fmt::Result {
+    let quote = elem.is_empty()
+        || elem.eq_ignore_ascii_case("null")
+        || elem
+            .chars()
+            .any(|c| matches!(c, ',' | '"' | '{' | '}' | '\\') || c.is_whitespace());
+    if !quote {
+        return write!(f, "{}", elem);
+    }
+    write!(f, "\"")?;
+    for c in elem.chars() {
+        if matches!(c, '"' | '\\') {
+            write!(f, "\\")?;
+        }
+        write!(f, "{}", c)?;
+    }
+    write!(f, "\"")
+}
+
+// the values as postgres prints them: dates as YYYY-MM-DD, timestamps in UTC,
+// lists as array literals
+impl std::fmt::Display for ScalarValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ScalarValue::Int(v) => write!(f, "{}", v),
+            ScalarValue::Float(v) => write!(f, "{}", v),
+            ScalarValue::Str(v) => write!(f, "{}", v),
+            ScalarValue::BigInt(v) => write!(f, "{}", v),
+            ScalarValue::Double(v) => write!(f, "{}", v),
+            ScalarValue::Bool(v) => write!(f, "{}", v),
+            ScalarValue::Date(v) => {
+                let (y, m, d) = civil_from_days(*v as i64);
+                write!(f, "{:04}-{:02}-{:02}", y, m, d)
+            }
+            ScalarValue::Timestamp(v) => {
+                let (days, micros) = (v.div_euclid(86_400_000_000), v.rem_euclid(86_400_000_000));
+                let (y, m, d) = civil_from_days(days);
+                let secs = micros / 1_000_000;
+                write!(
+                    f,
+                    "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+                    y,
+                    m,
+                    d,
+                    secs / 3600,
+                    secs / 60 % 60,
+                    secs % 60
+                )?;
+                if micros % 1_000_000 != 0 {
+                    write!(f, ".{:06}", micros % 1_000_000)?;
+                }
+                write!(f, "+00")
+            }
+            ScalarValue::StrList(v) => {
+                write!(f, "{{")?;
+                for (i, elem) in v.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    fmt_elem(f, elem)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+// days since 1970-01-01 of "YYYY-MM-DD"
+fn parse_date(text: &str) -> Option<i64> {
+    let mut parts = text.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: u32 = parts.next()?.parse().ok()?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    let days = days_from_civil(year, month, day);
+    // rejects e.g. 2023-02-30
+    (civil_from_days(days) == (year, month, day)).then_some(days)
+}
+
+// microseconds since 1970-01-01 UTC of "YYYY-MM-DD HH:MM:SS[.ffffff][+HH[:MM]|Z]"
+fn parse_timestamp(text: &str) -> Option<i64> {
+    let (date, time) = text.split_once([' ', 'T'])?;
+    let (time, offset) = match time.find(['+', '-', 'Z']) {
+        Some(i) => time.split_at(i),
+        None => (time, ""),
+    };
+    let offset_secs = match offset {
+        "" | "Z" => 0,
+        _ => {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let (hours, minutes) = offset[1..].split_once(':').unwrap_or((&offset[1..], "0"));
+            sign * (hours.parse::<i64>().ok()? * 3600 + minutes.parse::<i64>().ok()? * 60)
+        }
+    };
+    let (secs, fraction) = time.split_once('.').unwrap_or((time, ""));
+    let mut parts = secs.splitn(3, ':');
+    let hours: i64 = parts.next()?.parse().ok()?;
+    let minutes: i64 = parts.next()?.parse().ok()?;
+    let seconds: i64 = parts.next()?.parse().ok()?;
+    if hours > 23 || minutes > 59 || seconds > 59 || fraction.len() > 6 {
+        return None;
+    }
+    let micros = match fraction {
+        "" => 0,
+        _ => format!("{:0<6}", fraction).parse::<i64>().ok()?,
+    };
+    let secs = parse_date(date)? * 86_400 + hours * 3600 + minutes * 60 + seconds - offset_secs;
+    secs.checked_mul(1_000_000)?.checked_add(micros)
+}
+
+// day count since 1970-01-01 of a proleptic Gregorian date, the inverse of civil_from_days
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = (month as i64 + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// (year, month, day) of a day count since 1970-01-01, in the proleptic Gregorian calendar
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+fn encode_str(v: &str, out: &mut Vec<u8>) {
+    let mut buf = [0u8; STR_SIZE];
+    let len = v.len().min(STR_SIZE - 1);
+    buf[..len].copy_from_slice(&v.as_bytes()[..len]);
+    out.extend_from_slice(&buf);
+}
+
+// The decoded values of one block of a column
+#[derive(Debug, Clone, PartialEq)]
+pub enum ColumnBlock {
+    Int(Vec<i32>),
+    Float(Vec<f32>),
+    Str(Vec<String>),
+    BigInt(Vec<i64>),
+    Double(Vec<f64>),
+    Bool(Vec<bool>),
+    Date(Vec<i32>),
+    Timestamp(Vec<i64>),
+    StrList(Vec<Vec<String>>),
+}
+
+fn decode_str(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+impl ColumnBlock {
+    pub fn decode(ty: ColumnType, bytes: &[u8], num: usize) -> Result<ColumnBlock, BuildError> {
+        if ty == ColumnType::StrList {
+            return ColumnBlock::decode_str_list(bytes, num);
+        }
+        let size = ty.elem_size();
+        if bytes.len() < num * size {
+            return Err(BuildError::FormatError(format!(
+                "block of {} {} values needs {} bytes, found {}",
+                num,
+                ty.name(),
+                num * size,
+                bytes.len()
+            )));
+        }
+        let elems = bytes[..num * size].chunks_exact(size);
+        Ok(match ty {
+            ColumnType::Int => ColumnBlock::Int(
+                elems
+                    .map(|e| i32::from_le_bytes(e.try_into().unwrap()))
+                    .collect(),
+            ),
+            ColumnType::Float => ColumnBlock::Float(
+                elems
+                    .map(|e| f32::from_le_bytes(e.try_into().unwrap()))
+                    .collect(),
+            ),
+            ColumnType::Str => ColumnBlock::Str(elems.map(decode_str).collect()),
+            ColumnType::BigInt => ColumnBlock::BigInt(
+                elems
+                    .map(|e| i64::from_le_bytes(e.try_into().unwrap()))
+                    .collect(),
+            ),
+            ColumnType::Double => ColumnBlock::Double(
+                elems
+                    .map(|e| f64::from_le_bytes(e.try_into().unwrap()))
+                    .collect(),
+            ),
+            ColumnType::Bool => ColumnBlock::Bool(elems.map(|e| e[0] != 0).collect()),
+            ColumnType::Date => ColumnBlock::Date(
+                elems
+                    .map(|e| i32::from_le_bytes(e.try_into().unwrap()))
+                    .collect(),
+            ),
+            ColumnType::Timestamp => ColumnBlock::Timestamp(
+                elems
+                    .map(|e| i64::from_le_bytes(e.try_into().unwrap()))
+                    .collect(),
+            ),
+            ColumnType::StrList => unreachable!(),
+        })
+    }
+
+    fn decode_str_list(mut bytes: &[u8], num: usize) -> Result<ColumnBlock, BuildError> {
+        let truncated = || BuildError::FormatError("truncated list[str] block".to_string());
+        let mut lists = Vec::with_capacity(num);
+        for _ in 0..num {
+            let count = bytes.get(..4).ok_or_else(truncated)?;
+            let count = u32::from_le_bytes(count.try_into().unwrap()) as usize;
+            let elems = bytes.get(4..4 + count * STR_SIZE).ok_or_else(truncated)?;
+            lists.push(elems.chunks_exact(STR_SIZE).map(decode_str).collect());
+            bytes = &bytes[4 + count * STR_SIZE..];
+        }
+        Ok(ColumnBlock::StrList(lists))
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            ColumnBlock::Int(v) | ColumnBlock::Date(v) => v.len(),
+            ColumnBlock::Float(v) => v.len(),
+            ColumnBlock::Str(v) => v.len(),
+            ColumnBlock::BigInt(v) | ColumnBlock::Timestamp(v) => v.len(),
+            ColumnBlock::Double(v) => v.len(),
+            ColumnBlock::Bool(v) => v.len(),
+            ColumnBlock::StrList(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, idx: usize) -> Option<ScalarValue> {
+        match self {
+            ColumnBlock::Int(v) => v.get(idx).map(|v| ScalarValue::Int(*v)),
+            ColumnBlock::Float(v) => v.get(idx).map(|v| ScalarValue::Float(*v)),
+            ColumnBlock::Str(v) => v.get(idx).map(|v| ScalarValue::Str(v.clone())),
+            ColumnBlock::BigInt(v) => v.get(idx).map(|v| ScalarValue::BigInt(*v)),
+            ColumnBlock::Double(v) => v.get(idx).map(|v| ScalarValue::Double(*v)),
+            ColumnBlock::Bool(v) => v.get(idx).map(|v| ScalarValue::Bool(*v)),
+            ColumnBlock::Date(v) => v.get(idx).map(|v| ScalarValue::Date(*v)),
+            ColumnBlock::Timestamp(v) => v.get(idx).map(|v| ScalarValue::Timestamp(*v)),
+            ColumnBlock::StrList(v) => v.get(idx).map(|v| ScalarValue::StrList(v.clone())),
+        }
+    }
+}
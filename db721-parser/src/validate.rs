@@ -0,0 +1,308 @@
+use crate::checksum::VerifyMode;
+use crate::encoding::{DeltaBlock, DictionaryBlock, Encoding, RleBlock};
+use crate::parser::{BuildError, ColumnMeta, Parser, ParserBuilder};
+use crate::types::{ColumnType, STR_SIZE};
+use crate::writer::StatsCollector;
+
+/*
+Checks a file before it is handed to the FDW, reporting every violation instead of
+stopping at the first one like `ParserBuilder::build` does:
+
+    footer      magic bytes, metadata length and checksum
+    metadata    JSON schema, block stats of every block, format version
+    regions     columns and bloom filters inside the data region, without overlaps
+                or gaps between them
+    blocks      checksums, the same number of values in every column, "num"
+                against the byte length of the block, stored stats against the data
+    strings     NUL-terminated and valid UTF-8
+
+A broken footer or metadata stops the checks, nothing else can be located then.
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Location {
+    File,
+    Column(String),
+    // column name, block index
+    Block(String, usize),
+    // column name, block index, index of the value in the block
+    Value(String, usize, usize),
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::File => write!(f, "file"),
+            Location::Column(c) => write!(f, "column {}", c),
+            Location::Block(c, b) => write!(f, "column {} block {}", c, b),
+            Location::Value(c, b, v) => write!(f, "column {} block {} value {}", c, b, v),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub location: Location,
+    pub message: String,
+}
+
+impl std::fmt::Display for Violation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+#[derive(Default)]
+struct Violations(Vec<Violation>);
+
+impl Violations {
+    fn push(&mut self, location: Location, message: impl ToString) {
+        self.0.push(Violation {
+            location,
+            message: message.to_string(),
+        });
+    }
+}
+
+// the violations of the file, only failing to read it is an error
+pub fn validate(filename: &str) -> Result<Vec<Violation>, BuildError> {
+    let data = ParserBuilder::map_file(filename)?;
+    let mut violations = Violations::default();
+    let (metadata, region) = match ParserBuilder::read_metadata(&data, VerifyMode::Lenient) {
+        Ok(metadata) => metadata,
+        Err(BuildError::IoError(e)) => return Err(BuildError::IoError(e)),
+        Err(e) => {
+            violations.push(Location::File, e);
+            return Ok(violations.0);
+        }
+    };
+
+    let mut columns = Vec::new();
+    for (name, column) in &metadata.columns {
+        match ColumnMeta::new(name, column) {
+            Ok(meta) => columns.push(meta),
+            Err(e) => violations.push(Location::Column(name.clone()), e),
+        }
+    }
+
+    // (start, length, what) of everything stored between the header and the footer
+    let mut regions: Vec<(usize, usize, String)> = Vec::new();
+    for meta in &columns {
+        regions.push((meta.start_offset, meta.len(), meta.column_name.clone()));
+        if let Some(bloom) = &meta.bloom_filter {
+            let len = bloom.num_bytes.saturating_mul(meta.block_nums.len());
+            regions.push((
+                bloom.offset,
+                len,
+                meta.column_name.clone() + " bloom filters",
+            ));
+        }
+    }
+    regions.sort();
+    let mut prev_end = region.start;
+    for (start, len, what) in &regions {
+        let location = Location::Column(what.trim_end_matches(" bloom filters").to_string());
+        let end = start.saturating_add(*len);
+        if *start < region.start || end > region.end {
+            violations.push(
+                location,
+                format!(
+                    "{} at bytes {}..{} exceed the data region {}..{}",
+                    what, start, end, region.start, region.end
+                ),
+            );
+        } else if *start < prev_end {
+            violations.push(
+                location,
+                format!("{} at offset {} overlaps the previous region", what, start),
+            );
+        } else if *start > prev_end {
+            violations.push(
+                location,
+                format!("{} unused bytes before {}", start - prev_end, what),
+            );
+        }
+        prev_end = prev_end.max(end);
+    }
+    if prev_end < region.end {
+        violations.push(
+            Location::File,
+            format!(
+                "{} unused bytes after the last column",
+                region.end - prev_end
+            ),
+        );
+    }
+
+    // the first column of the data region, as the parser counts rows in it
+    if let Some(first) = columns.iter().min_by_key(|c| c.start_offset) {
+        for meta in columns.iter().filter(|c| c.block_nums != first.block_nums) {
+            violations.push(
+                Location::Column(meta.column_name.clone()),
+                format!(
+                    "blocks hold other numbers of values than column {}",
+                    first.column_name
+                ),
+            );
+        }
+    }
+
+    let parser = match ParserBuilder::new(filename.to_string(), String::new()).build() {
+        Ok(parser) => parser,
+        Err(e) => {
+            // already reported above
+            if violations.0.is_empty() {
+                violations.push(Location::File, e);
+            }
+            return Ok(violations.0);
+        }
+    };
+    for meta in &parser.columns {
+        for block in 0..meta.block_nums.len() {
+            validate_block(&parser, meta, block, &mut violations);
+        }
+    }
+    Ok(violations.0)
+}
+
+fn validate_block(parser: &Parser, meta: &ColumnMeta, block: usize, violations: &mut Violations) {
+    let name = &meta.column_name;
+    let location = || Location::Block(name.clone(), block);
+    let num = meta.block_nums[block] as usize;
+    let data = match parser.block_data(name, block) {
+        Ok(data) => data,
+        Err(e) => return violations.push(location(), e),
+    };
+
+    // the byte length of the block must be exactly what its values need
+    let encoding = meta.block_encodings[block];
+    let expected = match encoding {
+        Encoding::Plain if meta.column_type == ColumnType::StrList => str_list_len(&data, num),
+        Encoding::Plain => Some(num * meta.elem_size),
+        Encoding::Dictionary => DictionaryBlock::parse(&data, num)
+            .ok()
+            .map(|d| DictionaryBlock::encoded_len(d.dictionary.len(), num)),
+        Encoding::Rle => RleBlock::parse(meta.column_type, &data).ok().map(|r| {
+            if r.lengths.iter().map(|l| *l as usize).sum::<usize>() != num {
+                violations.push(location(), format!("runs do not add up to {} values", num));
+            }
+            RleBlock::encoded_len(meta.column_type, r.lengths.len())
+        }),
+        Encoding::Delta => DeltaBlock::parse(&data, num).ok().map(|d| d.encoded_len()),
+    };
+    if let Some(expected) = expected.filter(|e| *e != data.len()) {
+        violations.push(
+            location(),
+            format!(
+                "{} {} encoded values need {} bytes, the block has {}",
+                num,
+                encoding.name(),
+                expected,
+                data.len()
+            ),
+        );
+    }
+
+    for (value, bytes) in raw_strings(meta.column_type, encoding, &data, num) {
+        let location = Location::Value(name.clone(), block, value);
+        match bytes.iter().position(|b| *b == 0) {
+            None => violations.push(location, "string is not NUL-terminated"),
+            Some(end) if std::str::from_utf8(&bytes[..end]).is_err() => {
+                violations.push(location, "string is not valid UTF-8")
+            }
+            Some(_) => {}
+        }
+    }
+
+    let values = match parser.read_block(name, block) {
+        Ok(values) => values,
+        Err(e) => return violations.push(location(), e),
+    };
+    if values.len() != num {
+        violations.push(
+            location(),
+            format!("holds {} values, its stats say {}", values.len(), num),
+        );
+    }
+    let mut collector = StatsCollector::new();
+    (0..values.len()).for_each(|i| collector.process(&values.get(i).unwrap()));
+    let actual = collector.finish(meta.column_type);
+    let stored = parser.block_stats(name, block).unwrap();
+    let fmt = |v: &Option<crate::types::ScalarValue>| v.as_ref().map(|v| v.to_string());
+    for (key, stored, actual) in [
+        ("min", fmt(&stored.min), fmt(&actual.min)),
+        ("max", fmt(&stored.max), fmt(&actual.max)),
+        (
+            "min_len",
+            stored.min_len.map(|v| v.to_string()),
+            actual.min_len.map(|v| v.to_string()),
+        ),
+        (
+            "max_len",
+            stored.max_len.map(|v| v.to_string()),
+            actual.max_len.map(|v| v.to_string()),
+        ),
+    ] {
+        if stored != actual {
+            violations.push(
+                location(),
+                format!(
+                    "stored {} is {}, the data has {}",
+                    key,
+                    stored.unwrap_or_else(|| "missing".to_string()),
+                    actual.unwrap_or_else(|| "none".to_string())
+                ),
+            );
+        }
+    }
+}
+
+// byte length of `num` plain encoded lists, None if the block is truncated
+fn str_list_len(data: &[u8], num: usize) -> Option<usize> {
+    let mut len = 0;
+    for _ in 0..num {
+        let count = data.get(len..len + 4)?;
+        len += 4 + u32::from_le_bytes(count.try_into().unwrap()) as usize * STR_SIZE;
+    }
+    Some(len)
+}
+
+// the string slots of a block, with the index of the value they belong to
+fn raw_strings(ty: ColumnType, encoding: Encoding, data: &[u8], num: usize) -> Vec<(usize, &[u8])> {
+    fn slots(bytes: &[u8], n: usize) -> Vec<&[u8]> {
+        bytes.chunks_exact(STR_SIZE).take(n).collect()
+    }
+    match (ty, encoding) {
+        (ColumnType::Str, Encoding::Plain) => slots(data, num).into_iter().enumerate().collect(),
+        // the dictionary values are reported at the index of their code
+        (ColumnType::Str, Encoding::Dictionary) => {
+            let len = data
+                .get(..4)
+                .map_or(0, |l| u32::from_le_bytes(l.try_into().unwrap()) as usize);
+            let end = (4 + len * STR_SIZE).min(data.len());
+            slots(&data[4.min(end)..end], len)
+                .into_iter()
+                .enumerate()
+                .collect()
+        }
+        (ColumnType::StrList, Encoding::Plain) => {
+            let mut strings = Vec::new();
+            let mut offset = 0;
+            for value in 0..num {
+                let count = match data.get(offset..offset + 4) {
+                    Some(count) => u32::from_le_bytes(count.try_into().unwrap()) as usize,
+                    None => break,
+                };
+                let end = (offset + 4 + count * STR_SIZE).min(data.len());
+                strings.extend(
+                    slots(&data[offset + 4..end], count)
+                        .into_iter()
+                        .map(|s| (value, s)),
+                );
+                offset = end;
+            }
+            strings
+        }
+        _ => Vec::new(),
+    }
+}
@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+
+use crate::codec::Codec;
+use crate::encoding::Encoding;
+use crate::parser::Metadata;
+
+/*
+Identification and versioning of the format.
+
+Versioned files start and end with the 4 magic bytes:
+    | MAGIC | columns ... | metadata | u32 LE metadata length | MAGIC |
+or, when the metadata does not fit a u32 length, end with the wide variant:
+    | MAGIC | columns ... | metadata | u64 LE metadata length | MAGIC_WIDE |
+
+metadata["Format Version"]: "major.minor" (str), required in versioned files
+    A reader rejects files with a major version it does not know, a newer minor
+    version only adds keys that older readers can ignore.
+
+Legacy files, e.g. the ones written by chicken_farm_gen.py, have neither the magic
+bytes nor the key, and are read as version 0.0:
+    | columns ... | metadata | u32 LE metadata length |
+
+Features that need a version, a file using them with an older version is rejected:
+    1.0     compression, encodings, checksums, bloom filters
+    1.1     64-bit metadata length
+*/
+
+pub const MAGIC: &[u8; 4] = b"D721";
+pub const MAGIC_WIDE: &[u8; 4] = b"D72L";
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct FormatVersion {
+    pub major: u16,
+    pub minor: u16,
+}
+
+impl FormatVersion {
+    pub const LEGACY: FormatVersion = FormatVersion { major: 0, minor: 0 };
+    // the version the writer produces and the newest the parser knows
+    pub const CURRENT: FormatVersion = FormatVersion { major: 1, minor: 1 };
+    // compression, encodings, checksums and bloom filters
+    pub const BLOCK_FEATURES: FormatVersion = FormatVersion { major: 1, minor: 0 };
+    pub const WIDE_FOOTER: FormatVersion = FormatVersion { major: 1, minor: 1 };
+
+    pub fn is_supported(&self) -> bool {
+        self.major <= FormatVersion::CURRENT.major
+    }
+
+    // the first feature of `metadata` that this version does not allow, with the
+    // version it needs
+    pub fn unsupported_feature(
+        &self,
+        metadata: &Metadata,
+    ) -> Option<(&'static str, FormatVersion)> {
+        let needs = |feature| {
+            (*self < FormatVersion::BLOCK_FEATURES)
+                .then_some((feature, FormatVersion::BLOCK_FEATURES))
+        };
+        if metadata.checksum.is_some() {
+            return needs("checksums");
+        }
+        for column in metadata.columns.values() {
+            if column.bloom_filter.is_some() {
+                return needs("bloom filters");
+            }
+            if column.compression.is_some_and(|c| c != Codec::None) {
+                return needs("compression");
+            }
+            for stats in &column.block_stats {
+                if stats.compression.is_some_and(|c| c != Codec::None) {
+                    return needs("compression");
+                }
+                if stats.encoding.is_some_and(|e| e != Encoding::Plain) {
+                    return needs("encodings");
+                }
+                if stats.crc32c.is_some() {
+                    return needs("checksums");
+                }
+            }
+        }
+        None
+    }
+}
+
+impl std::fmt::Display for FormatVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl From<FormatVersion> for String {
+    fn from(version: FormatVersion) -> Self {
+        version.to_string()
+    }
+}
+
+impl TryFrom<String> for FormatVersion {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let invalid = || format!("invalid format version {:?}", value);
+        let (major, minor) = value.split_once('.').ok_or_else(invalid)?;
+        Ok(FormatVersion {
+            major: major.parse().map_err(|_| invalid())?,
+            minor: minor.parse().map_err(|_| invalid())?,
+        })
+    }
+}
@@ -0,0 +1,443 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use crate::bloom::{self, BloomFilter, BloomFilterMeta};
+use crate::checksum::Checksum;
+use crate::codec::Codec;
+use crate::encoding::{DeltaBlock, DictionaryBlock, Encoding, RleBlock};
+use crate::parser::{BlockStats, Column, Metadata};
+use crate::types::{ColumnType, ScalarValue, STR_SIZE};
+use crate::version::{FormatVersion, MAGIC, MAGIC_WIDE};
+
+// Rust counterpart of `Db721Serializer` in db721-gen/chicken_farm_gen.py
+pub struct Writer<W: Write> {
+    out: W,
+    offset: usize,
+    metadata: Metadata,
+    // codec of the columns written from now on
+    compression: Codec,
+    // whether to pick the smallest encoding for the blocks of the columns written from now
+    // on, rather than the plain one
+    choose_encoding: bool,
+    // whether to build bloom filters for the columns written from now on
+    bloom_filter: bool,
+    // filters of every block of a column, written by `finalize`
+    bloom_filters: Vec<(String, Vec<BloomFilter<Vec<u8>>>)>,
+    // checksum of the blocks written from now on and of the metadata
+    checksum: Option<Checksum>,
+    // whether to store a 64-bit metadata length even when it fits 32 bits
+    wide_footer: bool,
+}
+
+#[derive(Debug)]
+pub enum WriteError {
+    IoError(std::io::Error),
+    JsonError(serde_json::Error),
+    DuplicateColumn(String),
+    // column name, expected type, value
+    TypeMismatch(String, ColumnType, ScalarValue),
+    StringTooLong(String, String),
+}
+
+impl From<std::io::Error> for WriteError {
+    fn from(error: std::io::Error) -> Self {
+        WriteError::IoError(error)
+    }
+}
+
+impl From<serde_json::Error> for WriteError {
+    fn from(error: serde_json::Error) -> Self {
+        WriteError::JsonError(error)
+    }
+}
+
+impl std::fmt::Display for WriteError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WriteError::IoError(e) => write!(f, "DB721|IO: {}", e),
+            WriteError::JsonError(e) => write!(f, "DB721|Metadata: {}", e),
+            WriteError::DuplicateColumn(c) => write!(f, "DB721|Column: duplicate column {}", c),
+            WriteError::TypeMismatch(c, ty, v) => write!(
+                f,
+                "DB721|Column: column {} of type {} cannot hold {:?}",
+                c,
+                ty.name(),
+                v
+            ),
+            WriteError::StringTooLong(c, v) => write!(
+                f,
+                "DB721|Column: {:?} in column {} does not fit in {} bytes",
+                v, c, STR_SIZE
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WriteError {}
+
+// Running statistics of the block being written
+pub(crate) struct StatsCollector {
+    num: u32,
+    min: Option<ScalarValue>,
+    max: Option<ScalarValue>,
+    min_len: Option<u32>,
+    max_len: Option<u32>,
+    // postgres orders NaN above every number, a block holding one has no max
+    nan: bool,
+}
+
+impl StatsCollector {
+    pub(crate) fn new() -> StatsCollector {
+        StatsCollector {
+            num: 0,
+            min: None,
+            max: None,
+            min_len: None,
+            max_len: None,
+            nan: false,
+        }
+    }
+
+    pub(crate) fn process(&mut self, val: &ScalarValue) {
+        self.num += 1;
+        for val in val.stats_values() {
+            if let ScalarValue::Str(s) = &val {
+                let len = s.len() as u32;
+                self.min_len = Some(self.min_len.map_or(len, |l| l.min(len)));
+                self.max_len = Some(self.max_len.map_or(len, |l| l.max(len)));
+            }
+            if val.as_f64().is_some_and(f64::is_nan) {
+                self.nan = true;
+                continue;
+            }
+            if self.min.as_ref().is_none_or(|min| &val < min) {
+                self.min = Some(val.clone());
+            }
+            if self.max.as_ref().is_none_or(|max| &val > max) {
+                self.max = Some(val);
+            }
+        }
+    }
+
+    pub(crate) fn finish(self, ty: ColumnType) -> BlockStats {
+        let is_str = matches!(ty, ColumnType::Str | ColumnType::StrList);
+        BlockStats {
+            num: self.num,
+            min: self.min,
+            max: if self.nan { None } else { self.max },
+            min_len: if is_str { self.min_len } else { None },
+            max_len: if is_str { self.max_len } else { None },
+            size: None,
+            raw_size: None,
+            compression: None,
+            encoding: None,
+            crc32c: None,
+        }
+    }
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(tablename: String, out: W, max_values_per_block: u32) -> Writer<W> {
+        Writer {
+            out,
+            offset: 0,
+            metadata: Metadata {
+                table: tablename,
+                columns: HashMap::new(),
+                // blocks hold at least one value
+                max_values_per_block: max_values_per_block.max(1),
+                checksum: None,
+                format_version: Some(FormatVersion::CURRENT),
+            },
+            compression: Codec::None,
+            choose_encoding: true,
+            bloom_filter: false,
+            bloom_filters: Vec::new(),
+            checksum: None,
+            wide_footer: false,
+        }
+    }
+
+    pub fn compression(&mut self, codec: Codec) -> &mut Self {
+        self.compression = codec;
+        self
+    }
+
+    pub fn choose_encoding(&mut self, enabled: bool) -> &mut Self {
+        self.choose_encoding = enabled;
+        self
+    }
+
+    pub fn bloom_filter(&mut self, enabled: bool) -> &mut Self {
+        self.bloom_filter = enabled;
+        self
+    }
+
+    pub fn checksum(&mut self, checksum: Option<Checksum>) -> &mut Self {
+        self.checksum = checksum;
+        self
+    }
+
+    pub fn wide_footer(&mut self, enabled: bool) -> &mut Self {
+        self.wide_footer = enabled;
+        self
+    }
+
+    // one filter per block, all sized for the block with the most distinct values
+    fn build_bloom_filters(keys: &[HashSet<Vec<u8>>]) -> Vec<BloomFilter<Vec<u8>>> {
+        let capacity = keys.iter().map(|k| k.len()).max().unwrap_or(0);
+        keys.iter()
+            .map(|keys| {
+                let mut filter = BloomFilter::with_capacity(capacity);
+                keys.iter().for_each(|k| filter.insert(k));
+                filter
+            })
+            .collect()
+    }
+
+    // the smallest encoding of a block, given its plain encoding. Every candidate is
+    // built to know its exact length, only the winner is serialized.
+    fn encode_block(
+        col_type: ColumnType,
+        chunk: &[ScalarValue],
+        plain: Vec<u8>,
+    ) -> (Encoding, Vec<u8>) {
+        let mut best = (Encoding::Plain, plain.len());
+        let mut consider = |encoding, len| {
+            if len < best.1 {
+                best = (encoding, len);
+            }
+        };
+        let dictionary = match col_type {
+            ColumnType::Str => {
+                let values: Vec<&str> = chunk
+                    .iter()
+                    .map(|v| match v {
+                        ScalarValue::Str(s) => s.as_str(),
+                        _ => unreachable!(),
+                    })
+                    .collect();
+                DictionaryBlock::new(&values)
+            }
+            _ => None,
+        };
+        if let Some(dictionary) = &dictionary {
+            consider(
+                Encoding::Dictionary,
+                DictionaryBlock::encoded_len(dictionary.dictionary.len(), chunk.len()),
+            );
+        }
+        let delta = DeltaBlock::applies_to(col_type).then(|| DeltaBlock::new(chunk));
+        if let Some(delta) = &delta {
+            consider(Encoding::Delta, delta.encoded_len());
+        }
+        if RleBlock::applies_to(col_type) {
+            consider(
+                Encoding::Rle,
+                RleBlock::encoded_len(col_type, RleBlock::count_runs(chunk)),
+            );
+        }
+
+        let mut bytes = Vec::with_capacity(best.1);
+        match best.0 {
+            Encoding::Plain => return (Encoding::Plain, plain),
+            Encoding::Dictionary => dictionary.unwrap().encode(&mut bytes),
+            Encoding::Delta => delta.unwrap().encode(&mut bytes),
+            Encoding::Rle => RleBlock::encode(chunk, &mut bytes),
+        }
+        (best.0, bytes)
+    }
+
+    // the magic bytes go first, before any column
+    fn write_header(&mut self) -> Result<(), WriteError> {
+        if self.offset == 0 {
+            self.out.write_all(MAGIC)?;
+            self.offset += MAGIC.len();
+        }
+        Ok(())
+    }
+
+    pub fn max_values_per_block(&self) -> u32 {
+        self.metadata.max_values_per_block
+    }
+
+    // write a whole column, returns the number of blocks written
+    pub fn write_col(
+        &mut self,
+        col_name: &str,
+        col_type: ColumnType,
+        col_contents: &[ScalarValue],
+    ) -> Result<u32, WriteError> {
+        let chunk_size = self.metadata.max_values_per_block as usize;
+        let chunks = col_contents.chunks(chunk_size).map(Ok::<_, WriteError>);
+        self.write_col_blocks(col_name, col_type, chunks)
+    }
+
+    // write a column that is produced block by block, e.g. spilled to disk, so that it
+    // never is in memory at once. Blocks larger than Max Values Per Block are split,
+    // smaller ones are kept. Returns the number of blocks written.
+    pub fn write_col_blocks<B, E>(
+        &mut self,
+        col_name: &str,
+        col_type: ColumnType,
+        blocks: impl IntoIterator<Item = Result<B, E>>,
+    ) -> Result<u32, WriteError>
+    where
+        B: AsRef<[ScalarValue]>,
+        WriteError: From<E>,
+    {
+        if self.metadata.columns.contains_key(col_name) {
+            return Err(WriteError::DuplicateColumn(col_name.to_string()));
+        }
+        self.write_header()?;
+        let start_offset = self.offset;
+        let chunk_size = self.metadata.max_values_per_block as usize;
+        let mut block_stats = Vec::new();
+        let mut bloom_keys = Vec::new();
+        for block in blocks {
+            let block = block?;
+            for chunk in block.as_ref().chunks(chunk_size) {
+                block_stats.push(self.write_block(col_name, col_type, chunk)?);
+                if self.bloom_filter {
+                    bloom_keys.push(Writer::<W>::bloom_keys(col_type, chunk));
+                }
+            }
+        }
+        if block_stats.is_empty() {
+            // like the python serializer, an empty column still has one empty block
+            block_stats.push(self.write_block(col_name, col_type, &[])?);
+            if self.bloom_filter {
+                bloom_keys.push(HashSet::new());
+            }
+        }
+        if self.bloom_filter {
+            self.bloom_filters.push((
+                col_name.to_string(),
+                Writer::<W>::build_bloom_filters(&bloom_keys),
+            ));
+        }
+        let num_blocks = block_stats.len() as u32;
+        self.metadata.columns.insert(
+            col_name.to_string(),
+            Column {
+                column_type: col_type,
+                start_offset,
+                block_stats,
+                compression: match self.compression {
+                    Codec::None => None,
+                    codec => Some(codec),
+                },
+                bloom_filter: None,
+            },
+        );
+        Ok(num_blocks)
+    }
+
+    // the distinct bloom filter keys of a block
+    fn bloom_keys(col_type: ColumnType, chunk: &[ScalarValue]) -> HashSet<Vec<u8>> {
+        chunk
+            .iter()
+            .flat_map(|v| bloom::keys(col_type, v).unwrap_or_default())
+            .collect()
+    }
+
+    fn write_block(
+        &mut self,
+        col_name: &str,
+        col_type: ColumnType,
+        chunk: &[ScalarValue],
+    ) -> Result<BlockStats, WriteError> {
+        let mut bytes = Vec::with_capacity(chunk.len() * col_type.elem_size());
+        let mut stats = StatsCollector::new();
+        for val in chunk.iter() {
+            if val.column_type() != col_type {
+                return Err(WriteError::TypeMismatch(
+                    col_name.to_string(),
+                    col_type,
+                    val.clone(),
+                ));
+            }
+            for s in val.stats_values() {
+                if let ScalarValue::Str(s) = s {
+                    if s.len() >= STR_SIZE {
+                        return Err(WriteError::StringTooLong(col_name.to_string(), s));
+                    }
+                }
+            }
+            val.encode(&mut bytes);
+            stats.process(val);
+        }
+        let mut stats = stats.finish(col_type);
+        let mut encoding = Encoding::Plain;
+        if self.choose_encoding {
+            (encoding, bytes) = Writer::<W>::encode_block(col_type, chunk, bytes);
+        }
+        if encoding != Encoding::Plain {
+            stats.encoding = Some(encoding);
+        }
+        if self.compression != Codec::None {
+            let compressed = self.compression.compress(&bytes)?;
+            if compressed.len() < bytes.len() {
+                if !col_type.is_fixed_width() {
+                    stats.raw_size = Some(bytes.len());
+                }
+                bytes = compressed;
+            } else {
+                // not worth it, keep the block as is
+                stats.compression = Some(Codec::None);
+            }
+        }
+        if !col_type.is_fixed_width()
+            || self.compression != Codec::None
+            || encoding != Encoding::Plain
+        {
+            stats.size = Some(bytes.len());
+        }
+        if let Some(checksum) = self.checksum {
+            stats.crc32c = Some(checksum.compute(&bytes));
+            self.metadata.checksum = Some(checksum);
+        }
+        self.out.write_all(&bytes)?;
+        self.offset += bytes.len();
+        Ok(stats)
+    }
+
+    // write the bloom filters, the metadata, its length and the trailing magic bytes,
+    // returns the underlying writer
+    pub fn finalize(mut self) -> Result<W, WriteError> {
+        self.write_header()?;
+        for (col_name, filters) in std::mem::take(&mut self.bloom_filters) {
+            let column = self.metadata.columns.get_mut(&col_name).unwrap();
+            column.bloom_filter = Some(BloomFilterMeta {
+                offset: self.offset,
+                num_bytes: filters[0].bits.len(),
+                num_hashes: filters[0].num_hashes,
+            });
+            for filter in filters {
+                self.out.write_all(&filter.bits)?;
+                self.offset += filter.bits.len();
+            }
+        }
+        // the metadata is checksummed as soon as any block is
+        self.metadata.checksum = self.metadata.checksum.or(self.checksum);
+        let json_schema = serde_json::to_vec(&self.metadata)?;
+        if let Some(checksum) = self.metadata.checksum {
+            self.out
+                .write_all(&checksum.compute(&json_schema).to_le_bytes())?;
+        }
+        self.out.write_all(&json_schema)?;
+        match u32::try_from(json_schema.len()) {
+            Ok(len) if !self.wide_footer => {
+                self.out.write_all(&len.to_le_bytes())?;
+                self.out.write_all(MAGIC)?;
+            }
+            _ => {
+                self.out
+                    .write_all(&(json_schema.len() as u64).to_le_bytes())?;
+                self.out.write_all(MAGIC_WIDE)?;
+            }
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
@@ -0,0 +1,108 @@
+use std::path::Path;
+
+/*
+Helpers shared by the tests of the crates of the workspace, a dev-dependency only:
+
+    TmpPath         a file or directory in the temporary directory of the system,
+                    removed when dropped, also when a test fails before the end. A file
+                    goes with the files next to it named after it: its delete vector,
+                    lock, journal and staged temporary files.
+    gen_path        a file of db721-gen, e.g. data-farms.db721
+*/
+
+pub struct TmpPath {
+    path: String,
+    dir: bool,
+}
+
+impl TmpPath {
+    // a file that does not exist yet, unique to the test process and `name`
+    pub fn file(name: &str) -> TmpPath {
+        TmpPath {
+            path: tmp_name(name, ".db721"),
+            dir: false,
+        }
+    }
+
+    // an empty directory, removed with everything in it
+    pub fn dir(name: &str) -> TmpPath {
+        let path = tmp_name(name, "");
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        TmpPath { path, dir: true }
+    }
+
+    // a path inside the directory
+    pub fn join(&self, name: &str) -> String {
+        format!("{}/{}", self.path, name)
+    }
+}
+
+fn tmp_name(name: &str, extension: &str) -> String {
+    std::env::temp_dir()
+        .join(format!(
+            "db721-{}-{}{}",
+            std::process::id(),
+            name,
+            extension
+        ))
+        .to_string_lossy()
+        .into_owned()
+}
+
+impl std::ops::Deref for TmpPath {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.path
+    }
+}
+
+impl AsRef<Path> for TmpPath {
+    fn as_ref(&self) -> &Path {
+        self.path.as_ref()
+    }
+}
+
+impl Drop for TmpPath {
+    fn drop(&mut self) {
+        if self.dir {
+            let _ = std::fs::remove_dir_all(&self.path);
+            return;
+        }
+        // the lock is taken on the canonical path, which may be in another directory
+        let canonical = std::fs::canonicalize(&self.path).ok();
+        let _ = std::fs::remove_file(&self.path);
+        for path in [Some(Path::new(&self.path)), canonical.as_deref()]
+            .into_iter()
+            .flatten()
+        {
+            remove_siblings(path);
+        }
+    }
+}
+
+// `<name>.*` and `.<name>.*` next to the file
+fn remove_siblings(path: &Path) {
+    let (dir, name) = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => (dir, name.to_string_lossy()),
+        _ => return,
+    };
+    let (named, hidden) = (format!("{}.", name), format!(".{}.", name));
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let sibling = entry.file_name();
+        let sibling = sibling.to_string_lossy();
+        if sibling.starts_with(&named) || sibling.starts_with(&hidden) {
+            let _ = std::fs::remove_file(entry.path());
+        }
+    }
+}
+
+// a file of db721-gen, like the sample data-farms.db721
+pub fn gen_path(name: &str) -> String {
+    format!("{}/../db721-gen/{}", env!("CARGO_MANIFEST_DIR"), name)
+}
@@ -1,11 +1,17 @@
+use db721_parser::{
+    stage_deletes, Append, BuildError, ColumnBlock, ColumnType, FileIdentity, FileLock, Operator,
+    Predicate, ScalarValue, Staged, VerifyMode,
+};
 use pgx::pg_sys;
-use pgx::prelude::PgSqlErrorCode;
-use pgx::Spi;
-use std::collections::HashMap;
+use pgx::prelude::{Date, PgSqlErrorCode, TimestampWithTimeZone};
+use pgx::{register_xact_callback, PgXactCallbackEvent};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
 
 use supabase_wrappers::prelude::*;
 
-use crate::fdw::db721_fdw::{Parser, ParserBuilder};
+use crate::fdw::db721_fdw::{Dataset, DatasetBuilder, ParserBuilder, SchemaPolicy};
 
 /* We support the following pushdowns:
 
@@ -23,75 +29,720 @@ wrappers=# explain select id from hello where id = 1 order by col limit 1;
                Wrappers: limit = Some(Limit { count: 1, offset: 0 })
 */
 
+/* INSERT, DELETE and UPDATE buffer their changes to every table in the transaction and
+write them at commit, before it is final, so that an error aborts it:
+
+    INSERT      to a `filename`, the file is rewritten with new blocks after its own.
+                To a `directory`, the rows become new files of the directory, in the
+                `key=value` directories of their partition values
+    DELETE      the rows are marked in the delete vector next to their file, see
+                `db721_parser::deletes`
+    UPDATE      a DELETE of the old row and an INSERT of the new one
+
+The changes of all tables are published under one journal once every one of them is
+written, a commit that fails on the way is undone, one that crashes by the next writer
+of its files or `db721 recover`, see `db721_parser::staging`. A scan running while
+they are published, or before a crash is recovered, may see some of the changed files
+and not others. Tables given only by a `pattern` do not take
+INSERTs, there is no telling where new files belong.
+
+Changes are buffered for the transaction as a whole, a rollback to a savepoint could not
+take back those made after it. Tables are not changed inside subtransactions, savepoints
+and exception blocks, that is an error.
+
+DELETE and UPDATE need the `rowid_column` option, naming a text column of the foreign
+table that scans fill with `<file>:<version>:<row>`: the version of the file as its
+length and metadata crc32c, see `db721_parser::FileIdentity`, and the row id in it. Row
+ids only hold for that version, changing a row of a file rewritten since it was read,
+e.g. by a compaction, fails the commit. Changes are not visible to the transaction
+before it commits.
+*/
+
 #[wrappers_fdw(
     version = "0.1.0",
     author = "CyanPineapple",
     website = "www.github.com"
 )]
 pub(crate) struct PoloFdw {
-    cur_row: i32,
+    // (file, block) pairs left after pruning by the quals
+    scan_blocks: Vec<(usize, usize)>,
+    // index of the next block in `scan_blocks`
+    cur_block: usize,
+    // row index inside the current block
+    cur_row: usize,
+    // rows of the current block that match the quals
+    selection: Vec<bool>,
     tgt_cols: Vec<Column>,
-    parser: Parser,
+    predicates: Vec<Predicate>,
+    dataset: Option<Dataset>,
+    // values of each target column in the current block
+    blocks: Vec<Values>,
+    // the text column scans fill with row ids
+    rowid_column: Option<String>,
+    // the options of the table being modified
+    modify_options: Option<HashMap<String, String>>,
+}
+
+// the changes of the current transaction
+#[derive(Default)]
+struct Pending {
+    // rows inserted, by filename or directory
+    appends: BTreeMap<String, Append>,
+    // the version the rows were read from and the row ids deleted, by file
+    deletes: BTreeMap<String, (FileIdentity, Vec<usize>)>,
+}
+
+thread_local! {
+    // None until the transaction changes a table
+    static PENDING: RefCell<Option<Pending>> = const { RefCell::new(None) };
+}
+
+// change the pending changes, the first change of a transaction registers their commit
+fn with_pending<R>(f: impl FnOnce(&mut Pending) -> R) -> R {
+    PENDING.with(|p| {
+        let mut pending = p.borrow_mut();
+        if pending.is_none() {
+            register_xact_callback(PgXactCallbackEvent::PreCommit, flush_pending);
+            register_xact_callback(PgXactCallbackEvent::Abort, discard_pending);
+        }
+        f(pending.get_or_insert_with(Pending::default))
+    })
+}
+
+// of new files in a directory without files to take it from, like db721-gen
+const MAX_VALUES_PER_BLOCK: u32 = 50_000;
+
+// write the changes of the transaction, an error aborts it
+pub(super) fn flush_pending() {
+    let pending = match PENDING.with(|p| p.borrow_mut().take()) {
+        Some(pending) => pending,
+        None => return,
+    };
+    if let Err(e) = pending.commit() {
+        report_error(PgSqlErrorCode::ERRCODE_FDW_ERROR, &e);
+    }
+}
+
+// forget the changes of the transaction
+pub(super) fn discard_pending() {
+    PENDING.with(|p| p.borrow_mut().take());
+}
+
+impl Pending {
+    // lock the files, stage every change and publish them only once all are staged.
+    // Staged files are removed after an error, a publish that fails is undone.
+    fn commit(self) -> Result<(), String> {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_or(0, |d| d.as_nanos());
+        // ordered after the files written before
+        let id = format!("{:020}-{}", nanos, std::process::id());
+        let files = self.appends.values().filter_map(Append::filename);
+        let files = files.chain(self.deletes.keys().map(String::as_str));
+        let _locks = FileLock::acquire_all(files).map_err(|e| e.to_string())?;
+        let mut appends = Staged::default();
+        for append in self.appends.values() {
+            appends.extend(append.stage(&id).map_err(|e| e.to_string())?);
+        }
+        // the deletes are published first, so that the old rows of an UPDATE are gone
+        // before its new ones appear. A file rewritten by appends gets its sidecar from
+        // here, for its old version and the new one, with the old deletes and the new.
+        let mut staged = Staged::default();
+        for (filename, (version, rows)) in &self.deletes {
+            let data = appends.staged(filename).unwrap_or(filename);
+            let (deletes, _) =
+                stage_deletes(filename, rows, data, Some(*version)).map_err(|e| e.to_string())?;
+            staged.extend(deletes);
+        }
+        staged.extend(appends);
+        staged.publish().map_err(|e| e.to_string())?;
+        Ok(())
+    }
+}
+
+enum Values {
+    // and the type of the dataset when the column is wider than in the file
+    Block(ColumnBlock, Option<ColumnType>),
+    // a partition column or a column the file lacks, the same on every row of the file
+    Constant(Option<Cell>),
+    // the row id column, with the file, its version and the row id of the first row of
+    // the block
+    RowId(String, FileIdentity, usize),
+}
+
+// postgres counts dates and timestamps from 2000-01-01, db721 from 1970-01-01
+const PG_EPOCH_DAYS: i32 = 10_957;
+const PG_EPOCH_MICROS: i64 = 946_684_800_000_000;
+
+/* column type mapping:
+    int         -> int4
+    float       -> float4 (real)
+    str         -> varchar / text
+    bigint      -> int8
+    double      -> float8
+    bool        -> boolean
+    date        -> date
+    timestamp   -> timestamptz
+    list[str]   -> varchar[] / text[]
+*/
+fn to_cell(value: ScalarValue) -> Option<Cell> {
+    Some(match value {
+        ScalarValue::Int(v) => Cell::I32(v),
+        ScalarValue::Float(v) => Cell::F32(v),
+        ScalarValue::Str(v) => Cell::String(v),
+        ScalarValue::BigInt(v) => Cell::I64(v),
+        ScalarValue::Double(v) => Cell::F64(v),
+        ScalarValue::Bool(v) => Cell::Bool(v),
+        ScalarValue::Date(v) => Cell::Date(unsafe { Date::from_pg_epoch_days(v - PG_EPOCH_DAYS) }),
+        ScalarValue::Timestamp(v) => {
+            Cell::Timestamptz(TimestampWithTimeZone::try_from(v - PG_EPOCH_MICROS).ok()?)
+        }
+        ScalarValue::StrList(v) => Cell::StringArray(v.into_iter().map(Some).collect()),
+    })
+}
+
+fn to_scalar(cell: &Cell) -> Option<ScalarValue> {
+    Some(match cell {
+        Cell::Bool(v) => ScalarValue::Bool(*v),
+        Cell::I8(v) => ScalarValue::Int(*v as i32),
+        Cell::I16(v) => ScalarValue::Int(*v as i32),
+        Cell::I32(v) => ScalarValue::Int(*v),
+        Cell::I64(v) => ScalarValue::BigInt(*v),
+        Cell::F32(v) => ScalarValue::Float(*v),
+        Cell::F64(v) => ScalarValue::Double(*v),
+        Cell::String(v) => ScalarValue::Str(v.clone()),
+        Cell::Date(v) => ScalarValue::Date(v.to_pg_epoch_days() + PG_EPOCH_DAYS),
+        Cell::Timestamptz(v) => {
+            ScalarValue::Timestamp(pg_sys::TimestampTz::from(*v) + PG_EPOCH_MICROS)
+        }
+        Cell::StringArray(v) => ScalarValue::StrList(v.iter().cloned().collect::<Option<_>>()?),
+        _ => return None,
+    })
+}
+
+// partition values are strings, parsed as the type of the column of the foreign table.
+// Err names the type the value is not.
+fn partition_cell(value: &str, type_oid: pg_sys::Oid) -> Result<Cell, ColumnType> {
+    let column_type = [
+        (pg_sys::INT4OID, ColumnType::Int),
+        (pg_sys::INT8OID, ColumnType::BigInt),
+        (pg_sys::FLOAT4OID, ColumnType::Float),
+        (pg_sys::FLOAT8OID, ColumnType::Double),
+        (pg_sys::BOOLOID, ColumnType::Bool),
+        (pg_sys::DATEOID, ColumnType::Date),
+        (pg_sys::TIMESTAMPTZOID, ColumnType::Timestamp),
+    ]
+    .iter()
+    .find(|(oid, _)| *oid == type_oid)
+    .map_or(ColumnType::Str, |(_, ty)| *ty);
+    ScalarValue::parse(column_type, value)
+        .and_then(to_cell)
+        .ok_or(column_type)
+}
+
+// turn a qual into a predicate the parser can evaluate, the rest is left to postgres
+fn to_predicate(qual: &Qual, column_type: ColumnType) -> Option<Predicate> {
+    if qual.param.is_some() {
+        return None;
+    }
+    match &qual.value {
+        Value::Cell(cell) => {
+            let value = to_scalar(cell)?;
+            let operator = match (qual.operator.as_str(), column_type, &value) {
+                // quals only hold `column op value`, `'MALE' = ANY(sexes)` never arrives
+                // here and `sexes = '{MALE}'` compares whole arrays, left to postgres
+                (_, ColumnType::StrList, _) => return None,
+                ("=", _, _) => Operator::Eq,
+                // strings sort by the collation of the column, which quals do not carry,
+                // and the parser compares bytes, which is only the "C" collation
+                (_, _, ScalarValue::Str(_)) => return None,
+                ("<", _, _) => Operator::Lt,
+                ("<=", _, _) => Operator::Le,
+                (">", _, _) => Operator::Gt,
+                (">=", _, _) => Operator::Ge,
+                _ => return None,
+            };
+            Some(Predicate::new(&qual.field, operator, value))
+        }
+        // `col IN (...)` and `col = ANY(ARRAY[...])`
+        Value::Array(cells) if qual.use_or && qual.operator == "=" => {
+            let values = cells.iter().map(to_scalar).collect::<Option<Vec<_>>>()?;
+            Some(Predicate::in_list(&qual.field, values))
+        }
+        _ => None,
+    }
 }
 
 impl PoloFdw {
-    // foreign table and data source
+    // foreign table and data source: a single `filename`, or the files of a `directory`
+    // and/or matching a glob `pattern` scanned as one table
+    fn open(
+        options: &HashMap<String, String>,
+        quals: &[Qual],
+    ) -> Result<Dataset, (PgSqlErrorCode, String)> {
+        let fdw_error = |e: BuildError| (PgSqlErrorCode::ERRCODE_FDW_ERROR, e.to_string());
+        let tablename = options.get("tablename").cloned().unwrap_or_default();
+        // `verify 'strict'` rejects files and blocks without checksums
+        let verify = match options.get("verify").map(String::as_str) {
+            Some("strict") => VerifyMode::Strict,
+            _ => VerifyMode::Lenient,
+        };
+        let filename = match options.get("filename") {
+            Some(filename) => filename,
+            None if options.contains_key("directory") || options.contains_key("pattern") => {
+                let mut builder = DatasetBuilder::new(tablename);
+                if let Some(directory) = options.get("directory") {
+                    builder.directory(directory);
+                }
+                if let Some(pattern) = options.get("pattern") {
+                    builder.pattern(pattern);
+                }
+                // quals on partition columns skip files before they are opened
+                let partition_columns = builder.partition_columns().map_err(fdw_error)?;
+                let predicates: Vec<_> = quals
+                    .iter()
+                    .filter(|qual| partition_columns.contains(&qual.field))
+                    .filter_map(|qual| to_predicate(qual, ColumnType::Str))
+                    .collect();
+                builder.predicates(&predicates);
+                // `schema 'merge'` reads files with missing or widened columns, as the
+                // `default_<column>` option or NULL for the missing ones
+                if options.get("schema").map(String::as_str) == Some("merge") {
+                    builder.schema(SchemaPolicy::Merge);
+                    for (option, value) in options {
+                        if let Some(column) = option.strip_prefix("default_") {
+                            builder.default_value(column, value);
+                        }
+                    }
+                }
+                return builder.verify(verify).build().map_err(|e| match e {
+                    BuildError::SchemaMismatch(..) => {
+                        (PgSqlErrorCode::ERRCODE_FDW_TABLE_NOT_FOUND, e.to_string())
+                    }
+                    e => fdw_error(e),
+                });
+            }
+            None => {
+                return Err((
+                    PgSqlErrorCode::ERRCODE_FDW_OPTION_NAME_NOT_FOUND,
+                    "option 'filename', 'directory' or 'pattern' is required".to_string(),
+                ))
+            }
+        };
+        let parser = ParserBuilder::new(filename.to_string(), tablename.clone())
+            .verify(verify)
+            .build()
+            .map_err(fdw_error)?;
+        if !parser.tablename.is_empty() && parser.tablename != parser.metadata.table {
+            return Err((
+                PgSqlErrorCode::ERRCODE_FDW_TABLE_NOT_FOUND,
+                format!(
+                    "file {} holds table {}, not {}",
+                    filename, parser.metadata.table, parser.tablename
+                ),
+            ));
+        }
+        Dataset::try_new(tablename, vec![filename.to_string()], vec![parser]).map_err(fdw_error)
+    }
+
+    // buffer a row for the table being modified, returns false after an error
+    fn append_row(&mut self, row: &Row) -> bool {
+        let options = match &self.modify_options {
+            Some(options) => options,
+            None => return false,
+        };
+        let target = match options.get("filename").or_else(|| options.get("directory")) {
+            Some(target) => target.clone(),
+            None => {
+                report_error(
+                    PgSqlErrorCode::ERRCODE_FDW_OPTION_NAME_NOT_FOUND,
+                    "INSERT needs option 'filename' or 'directory'",
+                );
+                return false;
+            }
+        };
+        let mut names = Vec::with_capacity(row.cols.len());
+        let mut values = Vec::with_capacity(row.cells.len());
+        for (name, cell) in row.cols.iter().zip(&row.cells) {
+            if Some(name) == options.get("rowid_column") {
+                continue;
+            }
+            match cell.as_ref().map(to_scalar) {
+                Some(None) => {
+                    report_error(
+                        PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
+                        &format!("column {} has a type db721 cannot hold", name),
+                    );
+                    return false;
+                }
+                value => {
+                    names.push(name.clone());
+                    values.push(value.flatten());
+                }
+            }
+        }
+        // the first row of the table in the transaction
+        if !PENDING.with(|p| {
+            p.borrow()
+                .as_ref()
+                .is_some_and(|p| p.appends.contains_key(&target))
+        }) {
+            let dataset = match Self::open(options, &[]) {
+                Ok(dataset) => dataset,
+                Err((code, e)) => {
+                    report_error(code, &e);
+                    return false;
+                }
+            };
+            let append = match options.get("filename") {
+                Some(filename) => Append::file(filename, &dataset.files[0]),
+                None => {
+                    let max_values_per_block = options
+                        .get("max_values_per_block")
+                        .and_then(|v| v.parse().ok())
+                        .unwrap_or(MAX_VALUES_PER_BLOCK);
+                    Append::directory(&target, &dataset, max_values_per_block)
+                }
+            };
+            with_pending(|p| p.appends.insert(target.clone(), append));
+        }
+        let result = with_pending(|p| match p.appends.get_mut(&target) {
+            Some(append) => append.push(&names, &values),
+            None => Ok(()),
+        });
+        if let Err(e) = result {
+            report_error(PgSqlErrorCode::ERRCODE_FDW_ERROR, &e.to_string());
+            return false;
+        }
+        true
+    }
+
+    // mark the row given by its `<file>:<version>:<row>` id deleted, returns false after
+    // an error
+    fn delete_row(&mut self, rowid: &Cell) -> bool {
+        let parsed = match rowid {
+            Cell::String(rowid) => {
+                let mut parts = rowid.rsplitn(3, ':');
+                let row = parts.next().and_then(|r| r.parse::<usize>().ok());
+                let version = parts.next().and_then(|v| v.parse::<FileIdentity>().ok());
+                match (parts.next(), version, row) {
+                    (Some(file), Some(version), Some(row)) => {
+                        Some((file.to_string(), version, row))
+                    }
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+        let (file, version, row) = match parsed {
+            Some(parsed) => parsed,
+            None => {
+                report_error(
+                    PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
+                    &format!("{} is not a db721 row id, see option 'rowid_column'", rowid),
+                );
+                return false;
+            }
+        };
+        let result = with_pending(|p| {
+            let (read, rows) = p
+                .deletes
+                .entry(file.clone())
+                .or_insert((version, Vec::new()));
+            if *read != version {
+                return Err(format!(
+                    "rows of {} were read from two versions of the file in one transaction",
+                    file
+                ));
+            }
+            rows.push(row);
+            Ok(())
+        });
+        if let Err(e) = result {
+            report_error(PgSqlErrorCode::ERRCODE_FDW_ERROR, &e);
+            return false;
+        }
+        true
+    }
 }
 
 impl ForeignDataWrapper for PoloFdw {
-    fn new(options: &HashMap<String, String>) -> Self {
-        let parser = ParserBuilder::new(
-            // TODO: get filename from options
-            String::from("/home/polo/Polo/PostgreSQL-FDW/db721-gen/data-farms.db721"),
-            String::new(),
-        )
-        .build()
-        .unwrap();
-
-        //let owner = parser.data.borrow_owner();
-        //let dep = parser.data.borrow_dependent();
-        //println!("{:#?}",owner);
-        //println!("{:#?}",dep.0.get(&0));
-
+    fn new(_options: &HashMap<String, String>) -> Self {
         Self {
+            scan_blocks: Vec::new(),
+            cur_block: 0,
             cur_row: 0,
+            selection: Vec::new(),
             tgt_cols: Vec::new(),
-            parser: parser,
+            predicates: Vec::new(),
+            dataset: None,
+            blocks: Vec::new(),
+            rowid_column: None,
+            modify_options: None,
         }
     }
 
     fn begin_scan(
         &mut self,
-        _quals: &[Qual], // TODO: Propagate filters
+        quals: &[Qual],
         columns: &[Column],
         _sorts: &[Sort],        // TODO: Propagate sort
         _limit: &Option<Limit>, // TODO: maxRecords
         options: &HashMap<String, String>,
     ) {
-        let tablename = options.get("tablename").unwrap();
-        self.parser.tablename = tablename.to_string();
+        let dataset = match Self::open(options, quals) {
+            Ok(dataset) => dataset,
+            Err((code, e)) => {
+                report_error(code, &e);
+                return;
+            }
+        };
+        self.rowid_column = options.get("rowid_column").cloned();
+        // an empty dataset has no columns to check, and no rows
+        if !dataset.files.is_empty() {
+            for tgt_col in columns {
+                if Some(&tgt_col.name) == self.rowid_column.as_ref() {
+                    continue;
+                }
+                if dataset.column_type(&tgt_col.name).is_none() {
+                    report_error(
+                        PgSqlErrorCode::ERRCODE_FDW_COLUMN_NAME_NOT_FOUND,
+                        &format!(
+                            "column {} does not exist in {}",
+                            tgt_col.name, dataset.paths[0]
+                        ),
+                    );
+                    return;
+                }
+            }
+        }
+
+        self.predicates = quals
+            .iter()
+            .filter_map(|qual| to_predicate(qual, dataset.column_type(&qual.field)?))
+            .collect();
+        self.scan_blocks = dataset.prune(&self.predicates);
+        self.dataset = Some(dataset);
         self.tgt_cols = columns.to_vec();
+        self.cur_block = 0;
+        self.cur_row = 0;
+        self.selection.clear();
+        self.blocks.clear();
     }
 
     fn iter_scan(&mut self, row: &mut Row) -> Option<()> {
-        // TODO: add check col name
-        let dep = self.parser.data.borrow_dependent();
-        for tgt_col in &self.tgt_cols {
-            if tgt_col.name == "farm_name" {
-                row.push(&tgt_col.name, Some(Cell::String("mercy".to_string())));
+        let dataset = self.dataset.as_ref()?;
+        loop {
+            if self.cur_row < self.selection.len() {
+                if self.selection[self.cur_row] {
+                    break;
+                }
+                self.cur_row += 1;
+                continue;
+            }
+            // the current block is exhausted, move on to the next one left after pruning
+            let (file, block) = *self.scan_blocks.get(self.cur_block)?;
+            let parser = &dataset.files[file];
+            self.cur_block += 1;
+            self.cur_row = 0;
+            self.blocks.clear();
+            self.selection = match parser.filter_block(block, &self.predicates) {
+                Ok(selection) => selection,
+                Err(e) => {
+                    report_error(PgSqlErrorCode::ERRCODE_FDW_ERROR, &e.to_string());
+                    return None;
+                }
+            };
+            if !self.selection.iter().any(|s| *s) {
                 continue;
             }
-            let arr = dep.0.get(&tgt_col.name).unwrap();
-            row.push(&tgt_col.name, Some(Cell::I8(arr[0] as i8)));
+            for tgt_col in &self.tgt_cols {
+                if Some(&tgt_col.name) == self.rowid_column.as_ref() {
+                    let start = parser.block_start(block);
+                    let path = dataset.paths[file].clone();
+                    self.blocks
+                        .push(Values::RowId(path, parser.identity, start));
+                    continue;
+                }
+                if let Some(value) = dataset.partition_value(file, &tgt_col.name) {
+                    match partition_cell(value, tgt_col.type_oid) {
+                        Ok(cell) => self.blocks.push(Values::Constant(Some(cell))),
+                        Err(ty) => {
+                            let path = Path::new(&dataset.paths[file]);
+                            report_error(
+                                PgSqlErrorCode::ERRCODE_FDW_INVALID_DATA_TYPE,
+                                &format!(
+                                    "partition value {:?} of column {} in {} is not a valid {}",
+                                    value,
+                                    tgt_col.name,
+                                    path.parent().unwrap_or(path).display(),
+                                    ty.name()
+                                ),
+                            );
+                            return None;
+                        }
+                    }
+                    continue;
+                }
+                if let Some(value) = dataset.file_constant(file, &tgt_col.name) {
+                    self.blocks.push(Values::Constant(value.and_then(to_cell)));
+                    continue;
+                }
+                let ty = dataset.column_type(&tgt_col.name);
+                let widened = ty.filter(|ty| {
+                    parser
+                        .column_meta(&tgt_col.name)
+                        .is_some_and(|meta| meta.column_type != *ty)
+                });
+                match parser.read_block(&tgt_col.name, block) {
+                    Ok(values) => self.blocks.push(Values::Block(values, widened)),
+                    Err(e) => {
+                        report_error(PgSqlErrorCode::ERRCODE_FDW_ERROR, &e.to_string());
+                        return None;
+                    }
+                }
+            }
+        }
+        for (tgt_col, block) in self.tgt_cols.iter().zip(&self.blocks) {
+            let cell = match block {
+                Values::Block(block, widened) => block
+                    .get(self.cur_row)
+                    .map(|v| match widened {
+                        Some(ty) => v.cast(*ty).unwrap_or(v),
+                        None => v,
+                    })
+                    .and_then(to_cell),
+                Values::Constant(cell) => cell.clone(),
+                Values::RowId(path, version, start) => Some(Cell::String(format!(
+                    "{}:{}:{}",
+                    path,
+                    version,
+                    start + self.cur_row
+                ))),
+            };
+            row.push(&tgt_col.name, cell);
+        }
+        self.cur_row += 1;
+        Some(())
+    }
+
+    fn end_scan(&mut self) {
+        self.blocks.clear();
+        self.selection.clear();
+        self.dataset = None;
+    }
+
+    fn begin_modify(&mut self, options: &HashMap<String, String>) {
+        // the changes of a subtransaction cannot be told apart from those before it
+        if unsafe { pg_sys::GetCurrentTransactionNestLevel() } > 1 {
+            report_error(
+                PgSqlErrorCode::ERRCODE_FEATURE_NOT_SUPPORTED,
+                "db721 tables cannot be changed inside a subtransaction",
+            );
+            return;
+        }
+        self.modify_options = Some(options.clone());
+    }
+
+    fn insert(&mut self, row: &Row) {
+        self.append_row(row);
+    }
+
+    fn update(&mut self, rowid: &Cell, new_row: &Row) {
+        if self.delete_row(rowid) {
+            self.append_row(new_row);
         }
-        if self.cur_row == 0 {
-            self.cur_row += 1;
-            return Some(());
+    }
+
+    fn delete(&mut self, rowid: &Cell) {
+        self.delete_row(rowid);
+    }
+
+    fn end_modify(&mut self) {
+        self.modify_options = None;
+    }
+
+    // the options of foreign tables, checked by CREATE and ALTER FOREIGN TABLE
+    fn validator(options: Vec<Option<String>>, catalog: Option<pg_sys::Oid>) {
+        if catalog != Some(pg_sys::ForeignTableRelationId) {
+            return;
+        }
+        // given as `<name>=<value>`
+        let options: HashMap<_, _> = options
+            .iter()
+            .flatten()
+            .filter_map(|option| option.split_once('='))
+            .collect();
+        if let Err((code, e)) = validate_options(&options) {
+            report_error(code, &e);
         }
-        None
     }
+}
 
-    fn end_scan(&mut self) {}
+// the names of the options of a foreign table, besides `default_<column>`
+const TABLE_OPTIONS: &[&str] = &[
+    "filename",
+    "directory",
+    "pattern",
+    "tablename",
+    "verify",
+    "schema",
+    "rowid_column",
+    "max_values_per_block",
+];
 
-    fn validator(options: Vec<Option<String>>, catalog: Option<pg_sys::Oid>) {}
+fn validate_options(options: &HashMap<&str, &str>) -> Result<(), (PgSqlErrorCode, String)> {
+    let invalid = |name: &str, e: &str| {
+        Err((
+            PgSqlErrorCode::ERRCODE_FDW_INVALID_ATTRIBUTE_VALUE,
+            format!("option '{}' {}", name, e),
+        ))
+    };
+    for name in options.keys() {
+        if !TABLE_OPTIONS.contains(name) && !name.starts_with("default_") {
+            return Err((
+                PgSqlErrorCode::ERRCODE_FDW_INVALID_OPTION_NAME,
+                format!("invalid option '{}'", name),
+            ));
+        }
+    }
+    let has = |name: &str| options.contains_key(name);
+    if !has("filename") && !has("directory") && !has("pattern") {
+        return Err((
+            PgSqlErrorCode::ERRCODE_FDW_OPTION_NAME_NOT_FOUND,
+            "option 'filename', 'directory' or 'pattern' is required".to_string(),
+        ));
+    }
+    if has("filename") && (has("directory") || has("pattern")) {
+        return invalid("filename", "cannot go with 'directory' or 'pattern'");
+    }
+    for name in ["filename", "directory", "pattern", "rowid_column"] {
+        if options.get(name).is_some_and(|value| value.is_empty()) {
+            return invalid(name, "cannot be empty");
+        }
+    }
+    if let Some(verify) = options.get("verify") {
+        if !matches!(*verify, "strict" | "lenient") {
+            return invalid("verify", "must be 'strict' or 'lenient'");
+        }
+    }
+    if let Some(schema) = options.get("schema") {
+        if !matches!(*schema, "strict" | "merge") {
+            return invalid("schema", "must be 'strict' or 'merge'");
+        }
+        if has("filename") {
+            return invalid("schema", "applies to tables of a 'directory' or 'pattern'");
+        }
+    }
+    if let Some(default) = options.keys().find(|name| name.starts_with("default_")) {
+        if options.get("schema") != Some(&"merge") {
+            return invalid(default, "needs option schema 'merge'");
+        }
+    }
+    if let Some(max) = options.get("max_values_per_block") {
+        if !max.parse::<u32>().is_ok_and(|max| max > 0) {
+            return invalid("max_values_per_block", "must be a positive integer");
+        }
+    }
+    Ok(())
 }
@@ -1,5 +1,4 @@
 #![allow(clippy::module_inception)]
 mod db721_fdw;
-mod parser;
 mod tests;
-pub use parser::{Parser, ParserBuilder};
+pub use db721_parser::{Dataset, DatasetBuilder, Parser, ParserBuilder, SchemaPolicy};
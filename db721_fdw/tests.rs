@@ -2,8 +2,120 @@
 #[pgx::pg_schema]
 
 mod tests {
+    use db721_parser::{Append, Checksum, ColumnType, ParserBuilder, ScalarValue, Writer};
     use pgx::pg_test;
     use pgx::prelude::*;
+    use std::fs::File;
+
+    use super::db721_fdw::{discard_pending, flush_pending};
+
+    /*
+    The tests below run in a transaction that is rolled back at the end, its pre-commit
+    callback never runs. `commit` writes the pending changes as it would, `rollback`
+    drops them as an abort does. The tables are written to fixed paths in /tmp, one
+    per test, rewritten when the test starts.
+    */
+
+    fn commit() {
+        flush_pending();
+    }
+
+    fn rollback() {
+        discard_pending();
+    }
+
+    fn create_server() {
+        Spi::run(
+            r#"
+            DROP foreign data wrapper IF EXISTS polo_wrapper CASCADE;
+            CREATE foreign data wrapper polo_wrapper
+                handler polo_fdw_handler
+                validator polo_fdw_validator;
+            CREATE SERVER polo_server foreign data wrapper polo_wrapper;
+            "#,
+        )
+        .unwrap();
+    }
+
+    // the table Chicken with `id` 0..num and a `name` per id, at `path`
+    fn write_chickens(path: &str, num: i32) {
+        let _ = std::fs::remove_file(format!("{}.deletes", path));
+        let mut writer = Writer::new("Chicken".to_string(), File::create(path).unwrap(), 4);
+        let ids: Vec<_> = (0..num).map(ScalarValue::Int).collect();
+        let names: Vec<_> = (0..num)
+            .map(|id| ScalarValue::Str(format!("chicken {}", id)))
+            .collect();
+        writer.write_col("id", ColumnType::Int, &ids).unwrap();
+        writer.write_col("name", ColumnType::Str, &names).unwrap();
+        writer.finalize().unwrap();
+    }
+
+    // the table Chicken with only the `id`s, at `path`
+    fn write_ids(path: &str, ids: std::ops::Range<i32>) {
+        let mut writer = Writer::new("Chicken".to_string(), File::create(path).unwrap(), 4);
+        let ids: Vec<_> = ids.map(ScalarValue::Int).collect();
+        writer.write_col("id", ColumnType::Int, &ids).unwrap();
+        writer.finalize().unwrap();
+    }
+
+    // an empty directory, with the directories `subdirs` in it
+    fn create_dir(dir: &str, subdirs: &[&str]) {
+        let _ = std::fs::remove_dir_all(dir);
+        for subdir in subdirs {
+            std::fs::create_dir_all(format!("{}/{}", dir, subdir)).unwrap();
+        }
+        std::fs::create_dir_all(dir).unwrap();
+    }
+
+    // the values of the column as text, in scan order
+    fn column(query: &str, name: &str) -> Vec<String> {
+        Spi::connect(|c| {
+            c.select(query, None, None)
+                .unwrap()
+                .map(|r| r.get_by_name::<&str, _>(name).unwrap().unwrap().to_string())
+                .collect()
+        })
+    }
+
+    // a foreign table of the chickens in the file or directory of `location`, an option
+    // like `filename '<path>'`, with the row id column `rowid`
+    fn create_chickens(table: &str, location: &str) {
+        Spi::run(&format!(
+            r#"
+            CREATE FOREIGN TABLE {}
+            (
+                id      integer,
+                name    text,
+                rowid   text
+            ) SERVER polo_server OPTIONS
+            (
+                {},
+                tablename 'Chicken',
+                rowid_column 'rowid'
+            );
+            "#,
+            table, location
+        ))
+        .unwrap();
+    }
+
+    // the (id, name) rows of the table, in scan order
+    fn chickens(table: &str) -> Vec<(i32, String)> {
+        Spi::connect(|c| {
+            c.select(&format!("SELECT id, name FROM {}", table), None, None)
+                .unwrap()
+                .map(|r| {
+                    let id = r.get_by_name::<i32, _>("id").unwrap().unwrap();
+                    let name = r.get_by_name::<&str, _>("name").unwrap().unwrap();
+                    (id, name.to_string())
+                })
+                .collect()
+        })
+    }
+
+    fn ids(rows: &[(i32, String)]) -> Vec<i32> {
+        rows.iter().map(|(id, _)| *id).collect()
+    }
 
     #[pg_test]
     fn polo_smoketest() {
@@ -40,10 +152,12 @@ mod tests {
                 CREATE FOREIGN TABLE IF NOT EXISTS db721_farm
                 (                                   
                     farm_name       varchar,
+                    sexes           varchar[],
                     min_age_weeks   real,
                     max_age_weeks   real
                 ) SERVER polo_server OPTIONS
                 (
+                    filename '/home/polo/Polo/PostgreSQL-FDW/db721-gen/data-farms.db721',
                     tablename 'Farm'
                 );
              "#,
@@ -63,16 +177,356 @@ mod tests {
 
             let results = c
                 .select("SELECT * FROM db721_farm", None, None)
-                .unwrap();
+                .unwrap()
+                .filter_map(|r| r.get_by_name::<&str, _>("farm_name").unwrap())
+                .collect::<Vec<_>>();
 
-            println!("{:#?}",results);
-                
-                
-                //.filter_map(|r| r.get_by_name::<&str, _>("name").unwrap())
-                //.collect::<Vec<_>>();
+            assert_eq!(
+                results,
+                vec![
+                    "Incubator",
+                    "Eggscellent",
+                    "Eggstraordinaire",
+                    "Breakfast Lunch Dinner",
+                    "Dish of the Day",
+                    "Cheep Birds"
+                ]
+            );
 
-            //assert_eq!(results, vec!["foo", "bar"]);
+            let results = c
+                .select(
+                    "SELECT farm_name FROM db721_farm WHERE 'MALE' = ANY(sexes) AND max_age_weeks <= 6",
+                    None,
+                    None,
+                )
+                .unwrap()
+                .filter_map(|r| r.get_by_name::<&str, _>("farm_name").unwrap())
+                .collect::<Vec<_>>();
 
+            assert_eq!(
+                results,
+                vec!["Incubator", "Breakfast Lunch Dinner", "Cheep Birds"]
+            );
         });
     }
+
+    // the blocks of ids 0..4 are corrupted, only scans that prune them can succeed
+    #[pg_test(error = "DB721|Checksum: column id block 0 is corrupted")]
+    fn polo_prunes_blocks() {
+        let path = "/tmp/db721-fdw-pruned.db721";
+        let mut writer = Writer::new("Chicken".to_string(), File::create(path).unwrap(), 4);
+        writer.checksum(Some(Checksum::Crc32c));
+        let ids: Vec<_> = (0..12).map(ScalarValue::Int).collect();
+        let names: Vec<_> = (0..12)
+            .map(|id| ScalarValue::Str(format!("chicken {}", id)))
+            .collect();
+        writer.write_col("id", ColumnType::Int, &ids).unwrap();
+        writer.write_col("name", ColumnType::Str, &names).unwrap();
+        writer.finalize().unwrap();
+        let parser = ParserBuilder::new(path.to_string(), "Chicken".to_string())
+            .build()
+            .unwrap();
+        let mut data = std::fs::read(path).unwrap();
+        for column in ["id", "name"] {
+            let meta = parser.column_meta(column).unwrap();
+            data[meta.start_offset + meta.block_offsets[0]] ^= 0x10;
+        }
+        std::fs::write(path, &data).unwrap();
+        create_server();
+        create_chickens("chickens", &format!("filename '{}'", path));
+
+        assert_eq!(
+            column("SELECT id::text FROM chickens WHERE id >= 6", "id"),
+            ["6", "7", "8", "9", "10", "11"]
+        );
+        assert_eq!(
+            column("SELECT id::text FROM chickens WHERE id IN (5, 9)", "id"),
+            ["5", "9"]
+        );
+        assert_eq!(
+            column(
+                "SELECT id::text FROM chickens WHERE name = 'chicken 9'",
+                "id"
+            ),
+            ["9"]
+        );
+        // without the quals every block is read
+        column("SELECT id::text FROM chickens", "id");
+    }
+
+    #[pg_test]
+    fn polo_deletes_rows() {
+        let path = "/tmp/db721-fdw-delete.db721";
+        write_chickens(path, 10);
+        create_server();
+        create_chickens("chickens", &format!("filename '{}'", path));
+        Spi::run("DELETE FROM chickens WHERE id % 3 = 0").unwrap();
+        // not visible before the commit
+        assert_eq!(ids(&chickens("chickens")), (0..10).collect::<Vec<_>>());
+        commit();
+        assert_eq!(ids(&chickens("chickens")), [1, 2, 4, 5, 7, 8]);
+        let parser = ParserBuilder::new(path.to_string(), "Chicken".to_string())
+            .build()
+            .unwrap();
+        assert_eq!((parser.num_rows(), parser.num_live_rows()), (10, 6));
+    }
+
+    #[pg_test]
+    fn polo_updates_rows() {
+        let path = "/tmp/db721-fdw-update.db721";
+        write_chickens(path, 6);
+        create_server();
+        create_chickens("chickens", &format!("filename '{}'", path));
+        Spi::run("UPDATE chickens SET name = 'renamed' WHERE id = 2").unwrap();
+        commit();
+        // the old row is deleted, the new one appended
+        let rows = chickens("chickens");
+        assert_eq!(ids(&rows), [0, 1, 3, 4, 5, 2]);
+        assert_eq!(rows[5].1, "renamed");
+        assert_eq!(rows[0].1, "chicken 0");
+    }
+
+    #[pg_test(
+        error = "DB721|Version: file /tmp/db721-fdw-changed.db721 changed since its rows were read"
+    )]
+    fn polo_refuses_deletes_of_rewritten_files() {
+        let path = "/tmp/db721-fdw-changed.db721";
+        write_chickens(path, 6);
+        create_server();
+        create_chickens("chickens", &format!("filename '{}'", path));
+        Spi::run("DELETE FROM chickens WHERE id = 0").unwrap();
+        // another writer appends to the file before the commit, the row ids read
+        // name the version before
+        let parser = ParserBuilder::new(path.to_string(), "Chicken".to_string())
+            .build()
+            .unwrap();
+        let mut append = Append::file(path, &parser);
+        let names = ["id".to_string(), "name".to_string()];
+        let values = [
+            Some(ScalarValue::Int(6)),
+            Some(ScalarValue::Str("chicken 6".to_string())),
+        ];
+        append.push(&names, &values).unwrap();
+        append.commit("0").unwrap();
+        commit();
+    }
+
+    #[pg_test]
+    fn polo_inserts_into_files() {
+        let path = "/tmp/db721-fdw-insert.db721";
+        write_chickens(path, 3);
+        create_server();
+        create_chickens("chickens", &format!("filename '{}'", path));
+        Spi::run("INSERT INTO chickens (id, name) VALUES (3, 'chicken 3'), (4, 'chicken 4')")
+            .unwrap();
+        commit();
+        let rows = chickens("chickens");
+        assert_eq!(ids(&rows), [0, 1, 2, 3, 4]);
+        assert_eq!(rows[4].1, "chicken 4");
+    }
+
+    #[pg_test]
+    fn polo_rolls_back_inserts() {
+        let path = "/tmp/db721-fdw-rollback.db721";
+        write_chickens(path, 3);
+        let before = std::fs::read(path).unwrap();
+        create_server();
+        create_chickens("chickens", &format!("filename '{}'", path));
+        Spi::run("INSERT INTO chickens (id, name) VALUES (3, 'chicken 3')").unwrap();
+        Spi::run("DELETE FROM chickens WHERE id = 0").unwrap();
+        rollback();
+        // nothing is left to commit
+        commit();
+        assert_eq!(ids(&chickens("chickens")), [0, 1, 2]);
+        assert_eq!(std::fs::read(path).unwrap(), before);
+        assert!(!std::path::Path::new(&format!("{}.deletes", path)).exists());
+    }
+
+    #[pg_test]
+    fn polo_inserts_into_directories() {
+        let dir = "/tmp/db721-fdw-insert-dir";
+        let _ = std::fs::remove_dir_all(dir);
+        std::fs::create_dir_all(dir).unwrap();
+        write_chickens(&format!("{}/part-0.db721", dir), 2);
+        create_server();
+        create_chickens("chickens", &format!("directory '{}'", dir));
+        Spi::run("INSERT INTO chickens (id, name) VALUES (2, 'chicken 2')").unwrap();
+        commit();
+        // the rows of the transaction are a new file next to the first
+        let files = std::fs::read_dir(dir)
+            .unwrap()
+            .filter(|e| {
+                let name = e.as_ref().unwrap().file_name();
+                let name = name.to_string_lossy();
+                name.starts_with("part-") && name.ends_with(".db721")
+            })
+            .count();
+        assert_eq!(files, 2);
+        let mut ids = ids(&chickens("chickens"));
+        ids.sort();
+        assert_eq!(ids, [0, 1, 2]);
+    }
+
+    #[pg_test]
+    fn polo_commits_several_tables() {
+        let (hens, roosters) = ("/tmp/db721-fdw-hens.db721", "/tmp/db721-fdw-roosters.db721");
+        write_chickens(hens, 3);
+        write_chickens(roosters, 3);
+        create_server();
+        create_chickens("hens", &format!("filename '{}'", hens));
+        create_chickens("roosters", &format!("filename '{}'", roosters));
+        Spi::run("INSERT INTO hens (id, name) VALUES (3, 'chicken 3')").unwrap();
+        Spi::run("DELETE FROM roosters WHERE id = 1").unwrap();
+        // neither table changes before the commit, both do with it
+        assert_eq!(ids(&chickens("hens")), [0, 1, 2]);
+        assert_eq!(ids(&chickens("roosters")), [0, 1, 2]);
+        commit();
+        assert_eq!(ids(&chickens("hens")), [0, 1, 2, 3]);
+        assert_eq!(ids(&chickens("roosters")), [0, 2]);
+    }
+
+    #[pg_test(error = "db721 tables cannot be changed inside a subtransaction")]
+    fn polo_rejects_changes_in_subtransactions() {
+        let path = "/tmp/db721-fdw-subtransaction.db721";
+        write_chickens(path, 3);
+        create_server();
+        create_chickens("chickens", &format!("filename '{}'", path));
+        Spi::run(
+            r#"
+            DO $$
+            BEGIN
+                INSERT INTO chickens (id, name) VALUES (3, 'chicken 3');
+            EXCEPTION WHEN division_by_zero THEN
+                NULL;
+            END $$;
+            "#,
+        )
+        .unwrap();
+    }
+
+    #[pg_test]
+    fn polo_reads_directories() {
+        let dir = "/tmp/db721-fdw-directory";
+        create_dir(dir, &["nested"]);
+        write_chickens(&format!("{}/part-0.db721", dir), 3);
+        write_chickens(&format!("{}/nested/part-1.db721", dir), 2);
+        std::fs::write(format!("{}/notes.txt", dir), "not a table").unwrap();
+        create_server();
+        create_chickens("chickens", &format!("directory '{}'", dir));
+        let mut ids = ids(&chickens("chickens"));
+        ids.sort();
+        assert_eq!(ids, [0, 0, 1, 1, 2]);
+    }
+
+    #[pg_test]
+    fn polo_reads_patterns() {
+        let dir = "/tmp/db721-fdw-pattern";
+        create_dir(dir, &[]);
+        write_ids(&format!("{}/2023-01.db721", dir), 0..2);
+        write_ids(&format!("{}/2023-02.db721", dir), 2..4);
+        write_ids(&format!("{}/2024-01.db721", dir), 4..6);
+        create_server();
+        Spi::run(&format!(
+            r#"
+            CREATE FOREIGN TABLE chickens (id integer) SERVER polo_server
+            OPTIONS (pattern '{}/2023-*.db721', tablename 'Chicken');
+            "#,
+            dir
+        ))
+        .unwrap();
+        let ids = column("SELECT id::text FROM chickens ORDER BY id", "id");
+        assert_eq!(ids, ["0", "1", "2", "3"]);
+    }
+
+    #[pg_test]
+    fn polo_reads_partitions() {
+        let dir = "/tmp/db721-fdw-partition";
+        create_dir(dir, &["farm=Incubator", "farm=Cheep%20Birds"]);
+        write_ids(&format!("{}/farm=Incubator/part-0.db721", dir), 0..3);
+        write_ids(&format!("{}/farm=Cheep%20Birds/part-0.db721", dir), 3..5);
+        create_server();
+        Spi::run(&format!(
+            r#"
+            CREATE FOREIGN TABLE chickens (id integer, farm text) SERVER polo_server
+            OPTIONS (directory '{}', tablename 'Chicken');
+            "#,
+            dir
+        ))
+        .unwrap();
+        let ids = column(
+            "SELECT id::text FROM chickens WHERE farm = 'Cheep Birds' ORDER BY id",
+            "id",
+        );
+        assert_eq!(ids, ["3", "4"]);
+        let farms = column("SELECT DISTINCT farm FROM chickens ORDER BY farm", "farm");
+        assert_eq!(farms, ["Cheep Birds", "Incubator"]);
+    }
+
+    #[pg_test(
+        error = "partition value \"unknown\" of column year in /tmp/db721-fdw-bad-partition/year=unknown is not a valid int"
+    )]
+    fn polo_rejects_invalid_partition_values() {
+        let dir = "/tmp/db721-fdw-bad-partition";
+        create_dir(dir, &["year=2023", "year=unknown"]);
+        write_ids(&format!("{}/year=2023/part-0.db721", dir), 0..3);
+        write_ids(&format!("{}/year=unknown/part-0.db721", dir), 3..5);
+        create_server();
+        Spi::run(&format!(
+            r#"
+            CREATE FOREIGN TABLE chickens (id integer, year integer) SERVER polo_server
+            OPTIONS (directory '{}', tablename 'Chicken');
+            "#,
+            dir
+        ))
+        .unwrap();
+        column("SELECT year::text FROM chickens", "year");
+    }
+
+    #[pg_test]
+    fn polo_merges_schemas() {
+        let dir = "/tmp/db721-fdw-merge";
+        create_dir(dir, &[]);
+        write_chickens(&format!("{}/part-0.db721", dir), 2);
+        // a file from before the table had names
+        write_ids(&format!("{}/part-1.db721", dir), 2..4);
+        create_server();
+        Spi::run(&format!(
+            r#"
+            CREATE FOREIGN TABLE chickens (id integer, name text) SERVER polo_server
+            OPTIONS (directory '{}', tablename 'Chicken', schema 'merge',
+                default_name 'unnamed');
+            "#,
+            dir
+        ))
+        .unwrap();
+        let names = column("SELECT name FROM chickens ORDER BY id", "name");
+        assert_eq!(names, ["chicken 0", "chicken 1", "unnamed", "unnamed"]);
+    }
+
+    #[pg_test(error = "invalid option 'filenmae'")]
+    fn polo_rejects_unknown_options() {
+        create_server();
+        create_chickens("chickens", "filenmae '/tmp/db721-fdw-typo.db721'");
+    }
+
+    #[pg_test(error = "option 'filename', 'directory' or 'pattern' is required")]
+    fn polo_rejects_tables_without_files() {
+        create_server();
+        Spi::run(
+            r#"
+            CREATE FOREIGN TABLE chickens (id integer) SERVER polo_server
+            OPTIONS (tablename 'Chicken');
+            "#,
+        )
+        .unwrap();
+    }
+
+    #[pg_test(error = "option 'default_name' needs option schema 'merge'")]
+    fn polo_rejects_defaults_without_merge() {
+        create_server();
+        create_chickens(
+            "chickens",
+            "directory '/tmp/db721-fdw-merge', default_name 'unnamed'",
+        );
+    }
 }